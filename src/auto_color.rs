@@ -0,0 +1,391 @@
+use crate::blend_mode::blend_pixel;
+use crate::color::ColorVisitor;
+use crate::contrast::{luminance_contrast, relative_luminance};
+use crate::geometry::RoundedRect;
+use crate::{blend, Block, Color, ColorContext, Pixel, Rect};
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde_derive::Deserialize;
+use std::fmt;
+
+/// The color of a block's text, either fixed or chosen from the image behind it.
+#[derive(Clone, Debug)]
+pub enum TextColor<'a> {
+    /// `"auto"`: pick the most readable of the block's `auto_color` candidates.
+    Auto,
+    Color(Color<'a>),
+}
+
+impl<'de, 'a> serde::Deserialize<'de> for TextColor<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct TextColorVisitor;
+
+        impl<'de> Visitor<'de> for TextColorVisitor {
+            type Value = TextColor<'static>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a color or \"auto\"")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Self::Value, E> {
+                if v.trim().eq_ignore_ascii_case("auto") {
+                    Ok(TextColor::Auto)
+                } else {
+                    ColorVisitor.visit_str(v).map(TextColor::Color)
+                }
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                seq: A,
+            ) -> std::result::Result<Self::Value, A::Error> {
+                ColorVisitor.visit_seq(seq).map(TextColor::Color)
+            }
+        }
+
+        deserializer.deserialize_any(TextColorVisitor)
+    }
+}
+
+impl<'a> Default for TextColor<'a> {
+    fn default() -> TextColor<'a> {
+        TextColor::Color(Color::default())
+    }
+}
+
+impl<'a> From<Color<'a>> for TextColor<'a> {
+    fn from(color: Color<'a>) -> TextColor<'a> {
+        TextColor::Color(color)
+    }
+}
+
+/// How a block chooses its text color when `color = "auto"`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AutoColor<'a> {
+    /// The colors to choose from. Defaults to white and black.
+    #[serde(default = "default_candidates")]
+    pub candidates: Vec<Color<'a>>,
+    /// The WCAG contrast ratio the text should reach. Defaults to 4.5, the AA level for body text.
+    #[serde(default = "default_min_contrast")]
+    pub min_contrast: f32,
+    /// Darken or lighten the block's background when no candidate reaches `min_contrast` on its own.
+    pub scrim: Option<Scrim<'a>>,
+}
+
+impl<'a> Default for AutoColor<'a> {
+    fn default() -> AutoColor<'a> {
+        AutoColor {
+            candidates: default_candidates(),
+            min_contrast: default_min_contrast(),
+            scrim: None,
+        }
+    }
+}
+
+/// A translucent color drawn over the block's background, behind the text. It's only as opaque as it
+/// needs to be for the text to reach the minimum contrast.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scrim<'a> {
+    /// Defaults to black.
+    #[serde(default)]
+    pub color: Color<'a>,
+    #[serde(default = "default_max_opacity")]
+    pub max_opacity: f32,
+}
+
+fn default_candidates() -> Vec<Color<'static>> {
+    vec![Color::Rgb(255, 255, 255), Color::Rgb(0, 0, 0)]
+}
+
+fn default_min_contrast() -> f32 {
+    4.5
+}
+
+fn default_max_opacity() -> f32 {
+    0.75
+}
+
+/// The most pixels sampled along each side of the text rect.
+const MAX_SAMPLES: u32 = 64;
+
+/// The share of the sampled pixels that the text is allowed to have less contrast with. Judging by the
+/// worst pixel would let a few specks decide the color, and judging by the average would ignore large
+/// patches that the text disappears into.
+const CONTRAST_PERCENTILE: f32 = 0.1;
+
+/// The colors under the text rect once the block's own background is drawn over `bg`.
+fn sample_background(
+    bg: &RgbaImage,
+    block_layer: &RgbaImage,
+    block: &Block,
+    rect: &Rect,
+) -> Vec<Pixel> {
+    let (width, height) = bg.dimensions();
+    let right = rect.right.min(width.saturating_sub(1));
+    let bottom = rect.bottom.min(height.saturating_sub(1));
+    if rect.left > right || rect.top > bottom {
+        return Vec::new();
+    }
+
+    let step = |length: u32| ((length + 1) / MAX_SAMPLES).max(1) as usize;
+    let mut samples = Vec::new();
+    for y in (rect.top..=bottom).step_by(step(bottom - rect.top)) {
+        for x in (rect.left..=right).step_by(step(right - rect.left)) {
+            samples.push(blend_pixel(
+                *bg.get_pixel(x, y),
+                *block_layer.get_pixel(x, y),
+                block.blend_mode,
+                block.opacity,
+            ));
+        }
+    }
+    samples
+}
+
+/// The contrast that the text has with all but the lowest `CONTRAST_PERCENTILE` of the samples.
+fn text_contrast(samples: &[Pixel], text: Pixel, scrim: Option<(Pixel, f32)>) -> f32 {
+    let mut contrasts = samples
+        .iter()
+        .map(|&p| {
+            let p = match scrim {
+                Some((color, opacity)) => blend(p, color, opacity),
+                None => p,
+            };
+            luminance_contrast(
+                relative_luminance(p),
+                relative_luminance(blend(p, text, 1.0)),
+            )
+        })
+        .collect::<Vec<_>>();
+    if contrasts.is_empty() {
+        return 21.0;
+    }
+
+    contrasts.sort_by(|a, b| a.total_cmp(b));
+    contrasts[(contrasts.len() as f32 * CONTRAST_PERCENTILE) as usize]
+}
+
+/// The text color chosen for a block, and how opaque a scrim needs to be behind it.
+pub(crate) struct ChosenColor {
+    pub color: Pixel,
+    pub contrast: f32,
+    pub scrim: Option<(Pixel, f32)>,
+}
+
+/// Pick the candidate that is most readable over the pixels under `rect`. A candidate that reaches the
+/// minimum contrast with less scrim wins, then the one with the most contrast.
+pub(crate) fn choose_color(
+    bg: &RgbaImage,
+    block_layer: &RgbaImage,
+    block: &Block,
+    rect: &Rect,
+    auto: &AutoColor,
+    colors: &ColorContext,
+) -> Result<ChosenColor> {
+    let samples = sample_background(bg, block_layer, block, rect);
+    let scrim = auto
+        .scrim
+        .as_ref()
+        .map(|s| Ok::<_, anyhow::Error>((s.color.resolve(colors)?, s.max_opacity.clamp(0.0, 1.0))))
+        .transpose()?;
+
+    let mut best: Option<ChosenColor> = None;
+    for candidate in &auto.candidates {
+        let color = candidate.resolve(colors)?;
+        let mut chosen = ChosenColor {
+            color,
+            contrast: text_contrast(&samples, color, None),
+            scrim: None,
+        };
+
+        if let Some((scrim_color, max_opacity)) = scrim {
+            if chosen.contrast < auto.min_contrast && max_opacity > 0.0 {
+                // Search for the lightest scrim that's enough, or use the darkest one allowed.
+                let at =
+                    |opacity: f32| text_contrast(&samples, color, Some((scrim_color, opacity)));
+                let opacity = if at(max_opacity) < auto.min_contrast {
+                    max_opacity
+                } else {
+                    let (mut low, mut high) = (0.0, max_opacity);
+                    for _ in 0..12 {
+                        let mid = (low + high) / 2.0;
+                        if at(mid) >= auto.min_contrast {
+                            high = mid;
+                        } else {
+                            low = mid;
+                        }
+                    }
+                    high
+                };
+                chosen.contrast = at(opacity);
+                chosen.scrim = Some((scrim_color, opacity));
+            }
+        }
+
+        let scrim_opacity = |c: &ChosenColor| c.scrim.map(|s| s.1).unwrap_or(0.0);
+        let reaches = |c: &ChosenColor| c.contrast >= auto.min_contrast;
+        let better = match best.as_ref() {
+            None => true,
+            Some(b) => match (reaches(&chosen), reaches(b)) {
+                (true, false) => true,
+                (false, true) => false,
+                (true, true) if scrim_opacity(&chosen) != scrim_opacity(b) => {
+                    scrim_opacity(&chosen) < scrim_opacity(b)
+                }
+                _ => chosen.contrast > b.contrast,
+            },
+        };
+        if better {
+            best = Some(chosen);
+        }
+    }
+
+    best.ok_or_else(|| anyhow!("Automatic text color needs at least one candidate color"))
+}
+
+/// Draw the scrim over the block's background.
+pub(crate) fn draw_scrim(image: &mut RgbaImage, area: &RoundedRect, color: Pixel, opacity: f32) {
+    let (width, height) = image.dimensions();
+    let bounds = match area.pixel_bounds(1.0, width, height) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+
+    for y in bounds.top..=bounds.bottom {
+        for x in bounds.left..=bounds.right {
+            let coverage = area.coverage(x, y);
+            if coverage > 0.0 {
+                let p = image.get_pixel_mut(x, y);
+                *p = blend(*p, color, opacity * coverage);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel;
+
+    fn block(config: &str) -> Block<'static> {
+        toml::from_str::<toml::Value>(&format!(
+            "min_size = 10\nmax_size = 20\ntext = []\nrect = {{ left = 0, top = 0, right = 19, bottom = 19 }}\n{}",
+            config
+        ))
+        .unwrap()
+        .try_into()
+        .unwrap()
+    }
+
+    fn choose(bg: &RgbaImage, config: &str) -> Result<ChosenColor> {
+        let block = block(config);
+        let auto = block.auto_color.clone().unwrap_or_default();
+        let layer = RgbaImage::new(bg.width(), bg.height());
+        choose_color(
+            bg,
+            &layer,
+            &block,
+            &block.rect,
+            &auto,
+            &ColorContext::default(),
+        )
+    }
+
+    #[test]
+    fn the_most_readable_candidate_wins() {
+        let dark = RgbaImage::from_pixel(20, 20, pixel(20, 20, 60, 255));
+        let chosen = choose(&dark, "").unwrap();
+        assert_eq!(chosen.color, pixel(255, 255, 255, 255));
+        assert!(chosen.contrast > 15.0);
+        assert!(chosen.scrim.is_none());
+
+        let light = RgbaImage::from_pixel(20, 20, pixel(250, 240, 200, 255));
+        let chosen = choose(&light, "").unwrap();
+        assert_eq!(chosen.color, pixel(0, 0, 0, 255));
+
+        let chosen = choose(
+            &light,
+            "auto_color = { candidates = [[255, 255, 0], [0, 0, 128], [128, 0, 0]] }",
+        )
+        .unwrap();
+        assert_eq!(chosen.color, pixel(0, 0, 128, 255));
+    }
+
+    #[test]
+    fn a_few_pixels_dont_decide_the_color() {
+        // Mostly black, with every 20th pixel white.
+        let specks = RgbaImage::from_fn(20, 20, |x, y| {
+            if (x + y * 20) % 20 == 0 {
+                pixel(255, 255, 255, 255)
+            } else {
+                pixel(0, 0, 0, 255)
+            }
+        });
+        assert_eq!(
+            choose(&specks, "").unwrap().color,
+            pixel(255, 255, 255, 255)
+        );
+
+        // Half white is too much to ignore, so the contrast is judged by the white half.
+        let halves = RgbaImage::from_fn(20, 20, |x, _| {
+            if x < 10 {
+                pixel(255, 255, 255, 255)
+            } else {
+                pixel(0, 0, 0, 255)
+            }
+        });
+        let chosen = choose(&halves, "").unwrap();
+        assert!((chosen.contrast - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_block_background_counts() {
+        let white = RgbaImage::from_pixel(20, 20, pixel(255, 255, 255, 255));
+        let block = block("");
+        let layer = RgbaImage::from_pixel(20, 20, pixel(0, 0, 0, 255));
+        let chosen = choose_color(
+            &white,
+            &layer,
+            &block,
+            &block.rect,
+            &AutoColor::default(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        assert_eq!(chosen.color, pixel(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn scrims_are_as_light_as_possible() {
+        let gray = RgbaImage::from_pixel(20, 20, pixel(128, 128, 128, 255));
+        let config =
+            "auto_color = { candidates = [[255, 255, 255]], min_contrast = 7, scrim = {} }";
+        let chosen = choose(&gray, config).unwrap();
+        let (color, opacity) = chosen.scrim.unwrap();
+        assert_eq!(color, pixel(0, 0, 0, 255));
+        assert!(opacity > 0.2 && opacity < 0.75, "{}", opacity);
+        assert!(
+            chosen.contrast >= 7.0 && chosen.contrast < 7.1,
+            "{}",
+            chosen.contrast
+        );
+
+        // The scrim can't go past its maximum opacity.
+        let config = "auto_color = { candidates = [[255, 255, 255]], min_contrast = 21, scrim = { max_opacity = 0.5 } }";
+        let chosen = choose(&gray, config).unwrap();
+        assert_eq!(chosen.scrim.unwrap().1, 0.5);
+
+        // A candidate that's readable without a scrim beats one that needs it.
+        let config = "auto_color = { candidates = [[255, 255, 255], [0, 0, 0]], min_contrast = 5, scrim = {} }";
+        let chosen = choose(&gray, config).unwrap();
+        assert_eq!(chosen.color, pixel(0, 0, 0, 255));
+        assert!(chosen.scrim.is_none());
+    }
+
+    #[test]
+    fn candidates_are_needed() {
+        let gray = RgbaImage::from_pixel(20, 20, pixel(128, 128, 128, 255));
+        assert!(choose(&gray, "auto_color = { candidates = [] }").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::geometry::RoundedRect;
+use crate::{blend, pixel, Color, ColorContext, Pixel};
+use anyhow::Result;
+use image::RgbaImage;
+use serde_derive::Deserialize;
+
+/// Filters applied to the part of the image behind a block, like the CSS `backdrop-filter` property.
+#[derive(Debug, Deserialize)]
+pub struct Backdrop<'a> {
+    /// The sigma of the gaussian blur.
+    #[serde(default)]
+    pub blur: f32,
+    /// Scale the color saturation. 0 is grayscale and 1 leaves the colors unchanged.
+    #[serde(default = "crate::one")]
+    pub saturation: f32,
+    /// Scale the brightness. Values below 1 darken the backdrop.
+    #[serde(default = "crate::one")]
+    pub brightness: f32,
+    /// A color drawn over the filtered backdrop.
+    pub tint: Option<Color<'a>>,
+}
+
+fn adjust(p: Pixel, saturation: f32, brightness: f32) -> Pixel {
+    let [r, g, b, a] = p.0;
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let gray = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let channel = |c: f32| ((gray + (c - gray) * saturation) * brightness).clamp(0.0, 255.0) as u8;
+    pixel(channel(r), channel(g), channel(b), a)
+}
+
+/// Filter the part of `image` that lies under `area`.
+pub(crate) fn apply_backdrop(
+    image: &mut RgbaImage,
+    backdrop: &Backdrop,
+    area: &RoundedRect,
+    colors: &ColorContext,
+) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let tint = backdrop
+        .tint
+        .as_ref()
+        .map(|c| c.resolve(colors))
+        .transpose()?;
+
+    // Take in some extra pixels around the block so that the blur near the edges uses the actual
+    // image instead of fading out.
+    let margin = (backdrop.blur * 3.0).ceil() + 1.0;
+    let region = match area.pixel_bounds(margin, width, height) {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+
+    let source = image::imageops::crop_imm(
+        image,
+        region.left,
+        region.top,
+        region.right - region.left + 1,
+        region.bottom - region.top + 1,
+    )
+    .to_image();
+    let filtered = if backdrop.blur > 0.0 {
+        image::imageops::blur(&source, backdrop.blur)
+    } else {
+        source
+    };
+
+    for (x, y, p) in filtered.enumerate_pixels() {
+        let (image_x, image_y) = (x + region.left, y + region.top);
+        let coverage = area.coverage(image_x, image_y);
+        if coverage <= 0.0 {
+            continue;
+        }
+
+        let mut filtered_pixel = adjust(*p, backdrop.saturation, backdrop.brightness);
+        if let Some(tint) = tint {
+            filtered_pixel = blend(filtered_pixel, tint, 1.0);
+        }
+
+        let dest = image.get_pixel_mut(image_x, image_y);
+        *dest = blend(*dest, filtered_pixel, coverage);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    fn backdrop(config: &str) -> Backdrop<'static> {
+        toml::from_str(config).unwrap()
+    }
+
+    /// A red left half and a blue right half.
+    fn halves() -> RgbaImage {
+        RgbaImage::from_fn(40, 20, |x, _| {
+            if x < 20 {
+                pixel(255, 0, 0, 255)
+            } else {
+                pixel(0, 0, 255, 255)
+            }
+        })
+    }
+
+    fn area() -> RoundedRect {
+        RoundedRect::new(
+            &Rect {
+                left: 10,
+                top: 5,
+                right: 29,
+                bottom: 14,
+            },
+            [0.0; 4],
+        )
+    }
+
+    #[test]
+    fn only_the_area_is_filtered() {
+        let mut image = halves();
+        apply_backdrop(
+            &mut image,
+            &backdrop("saturation = 0"),
+            &area(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        let gray = *image.get_pixel(12, 10);
+        assert_eq!(gray[0], gray[1]);
+        assert_eq!(gray[1], gray[2]);
+        assert_eq!(*image.get_pixel(5, 10), pixel(255, 0, 0, 255));
+        assert_eq!(*image.get_pixel(12, 2), pixel(255, 0, 0, 255));
+    }
+
+    #[test]
+    fn blur_mixes_colors_across_the_area() {
+        let mut image = halves();
+        apply_backdrop(
+            &mut image,
+            &backdrop("blur = 3"),
+            &area(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        let middle = *image.get_pixel(19, 10);
+        assert!(middle[0] > 64 && middle[2] > 64, "{:?}", middle);
+        assert_eq!(*image.get_pixel(9, 10), pixel(255, 0, 0, 255));
+    }
+
+    #[test]
+    fn brightness_and_tint() {
+        let mut image = halves();
+        apply_backdrop(
+            &mut image,
+            &backdrop("brightness = 0.5"),
+            &area(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        assert_eq!(*image.get_pixel(12, 10), pixel(127, 0, 0, 255));
+
+        let mut image = halves();
+        apply_backdrop(
+            &mut image,
+            &backdrop("tint = [0, 255, 0, 255]"),
+            &area(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        assert_eq!(*image.get_pixel(12, 10), pixel(0, 255, 0, 255));
+    }
+}
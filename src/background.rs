@@ -0,0 +1,253 @@
+use crate::generator::Generator;
+use crate::geometry::RoundedRect;
+use crate::image_block::{ImageCache, Source};
+use crate::paint::Paint;
+use crate::{blend, ColorContext, Rect, TRANSPARENT};
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, RgbaImage};
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+
+/// The image that the card is drawn on.
+#[derive(Clone, Debug)]
+pub enum Background<'a> {
+    Image(DynamicImage),
+    /// An image file, loaded when the card is drawn. SVG files are rasterized to cover the card.
+    File(PathBuf),
+    Generated(GeneratedBackground<'a>),
+}
+
+impl<'a> From<DynamicImage> for Background<'a> {
+    fn from(image: DynamicImage) -> Background<'a> {
+        Background::Image(image)
+    }
+}
+
+impl<'a> From<GeneratedBackground<'a>> for Background<'a> {
+    fn from(generated: GeneratedBackground<'a>) -> Background<'a> {
+        Background::Generated(generated)
+    }
+}
+
+/// A background drawn from a description instead of loaded from an image file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GeneratedBackground<'a> {
+    /// The size of the background. Defaults to the size of the card.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// A color or gradient covering the whole card. The background is transparent if this is not set.
+    pub fill: Option<Paint<'a>>,
+    /// A pattern generated from a seed, drawn on top of the fill.
+    pub generator: Option<Generator<'a>>,
+    /// An image repeated across the card, on top of the fill and generated pattern.
+    pub pattern: Option<Pattern>,
+}
+
+/// An image tiled to fill the card.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Pattern {
+    pub path: PathBuf,
+    /// Scale each tile by this amount. SVG tiles are rasterized at the scaled size.
+    #[serde(default = "crate::one")]
+    pub scale: f32,
+    /// How far to shift the tiles, in pixels.
+    #[serde(default)]
+    pub offset: (i32, i32),
+    #[serde(default = "crate::one")]
+    pub opacity: f32,
+}
+
+impl<'a> Background<'a> {
+    /// Draw the background. `card_size` is the size of the card, if it has been set explicitly.
+    pub(crate) fn render(
+        &self,
+        card_size: Option<(u32, u32)>,
+        colors: &ColorContext,
+        images: Option<&ImageCache>,
+    ) -> Result<RgbaImage> {
+        match self {
+            Background::Image(image) => Ok(image.to_rgba8()),
+            Background::File(path) => {
+                let source = Source::load(path, None, images)
+                    .with_context(|| format!("Opening background image {:?}", path))?;
+                let (width, height) = source.background_size(card_size);
+                source.render(width, height)
+            }
+            Background::Generated(g) => g.render(card_size, colors, images),
+        }
+    }
+}
+
+impl<'a> GeneratedBackground<'a> {
+    fn render(
+        &self,
+        card_size: Option<(u32, u32)>,
+        colors: &ColorContext,
+        images: Option<&ImageCache>,
+    ) -> Result<RgbaImage> {
+        let (width, height) = match (self.width, self.height, card_size) {
+            (Some(width), Some(height), _) => (width, height),
+            (width, height, Some((card_width, card_height))) => {
+                (width.unwrap_or(card_width), height.unwrap_or(card_height))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Generated backgrounds need a width and height when the card size isn't set"
+                ))
+            }
+        };
+
+        if width == 0 || height == 0 {
+            return Err(anyhow!("Background width and height must be positive"));
+        }
+
+        let mut image = match self.fill.as_ref() {
+            Some(fill) => {
+                let area = RoundedRect::new(
+                    &Rect {
+                        left: 0,
+                        top: 0,
+                        right: width - 1,
+                        bottom: height - 1,
+                    },
+                    [0.0; 4],
+                );
+                let painter = fill.painter(&area, colors)?;
+                RgbaImage::from_fn(width, height, |x, y| painter.at(x, y))
+            }
+            None => RgbaImage::from_pixel(width, height, TRANSPARENT),
+        };
+
+        if let Some(generator) = self.generator.as_ref() {
+            generator.draw(&mut image, colors)?;
+        }
+
+        if let Some(pattern) = self.pattern.as_ref() {
+            draw_pattern(&mut image, pattern, images)?;
+        }
+
+        Ok(image)
+    }
+}
+
+fn draw_pattern(
+    image: &mut RgbaImage,
+    pattern: &Pattern,
+    images: Option<&ImageCache>,
+) -> Result<()> {
+    if pattern.scale <= 0.0 {
+        return Err(anyhow!("Pattern scale must be positive"));
+    }
+
+    let source = Source::load(&pattern.path, None, images)?;
+    let (width, height) = source.size();
+    let tile = source.render(
+        ((width as f32 * pattern.scale).round() as u32).max(1),
+        ((height as f32 * pattern.scale).round() as u32).max(1),
+    )?;
+
+    let (tile_width, tile_height) = (tile.width() as i64, tile.height() as i64);
+    let (offset_x, offset_y) = (pattern.offset.0 as i64, pattern.offset.1 as i64);
+    for (x, y, dest) in image.enumerate_pixels_mut() {
+        let tile_x = (x as i64 - offset_x).rem_euclid(tile_width) as u32;
+        let tile_y = (y as i64 - offset_y).rem_euclid(tile_height) as u32;
+        *dest = blend(*dest, *tile.get_pixel(tile_x, tile_y), pattern.opacity);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel;
+
+    fn generated(config: &str) -> GeneratedBackground<'static> {
+        toml::from_str(config).unwrap()
+    }
+
+    #[test]
+    fn fills_cover_the_card() {
+        let image = generated("width = 4\nheight = 3\nfill = [255, 0, 0]")
+            .render(None, &ColorContext::default(), None)
+            .unwrap();
+        assert_eq!(image.dimensions(), (4, 3));
+        assert!(image.pixels().all(|p| *p == pixel(255, 0, 0, 255)));
+
+        let image = generated(
+            r#"
+            width = 10
+            height = 10
+            fill = { type = "linear", angle = 90, stops = ["000000", "ffffff"] }
+            "#,
+        )
+        .render(None, &ColorContext::default(), None)
+        .unwrap();
+        assert!(image.get_pixel(0, 5)[0] < 20);
+        assert!(image.get_pixel(9, 5)[0] > 235);
+
+        let image = generated("width = 2\nheight = 2")
+            .render(None, &ColorContext::default(), None)
+            .unwrap();
+        assert_eq!(*image.get_pixel(1, 1), TRANSPARENT);
+    }
+
+    #[test]
+    fn the_size_defaults_to_the_card_size() {
+        let image = generated("width = 4")
+            .render(Some((10, 6)), &ColorContext::default(), None)
+            .unwrap();
+        assert_eq!(image.dimensions(), (4, 6));
+        let image = generated("")
+            .render(Some((10, 6)), &ColorContext::default(), None)
+            .unwrap();
+        assert_eq!(image.dimensions(), (10, 6));
+        assert!(generated("height = 4")
+            .render(None, &ColorContext::default(), None)
+            .is_err());
+    }
+
+    #[test]
+    fn empty_backgrounds_are_errors() {
+        assert!(generated("width = 0\nheight = 10")
+            .render(None, &ColorContext::default(), None)
+            .is_err());
+    }
+
+    #[test]
+    fn patterns_are_tiled_with_an_offset() {
+        let path = std::env::temp_dir().join(format!(
+            "create-social-card-tile-{}.png",
+            std::process::id()
+        ));
+        // A 2x2 tile with a red top left pixel.
+        let tile = RgbaImage::from_fn(2, 2, |x, y| {
+            if x == 0 && y == 0 {
+                pixel(255, 0, 0, 255)
+            } else {
+                pixel(0, 0, 255, 255)
+            }
+        });
+        tile.save(&path).unwrap();
+
+        let background = GeneratedBackground {
+            width: Some(6),
+            height: Some(6),
+            fill: None,
+            generator: None,
+            pattern: Some(Pattern {
+                path: path.clone(),
+                scale: 1.0,
+                offset: (1, 0),
+                opacity: 1.0,
+            }),
+        };
+        let image = background.render(None, &ColorContext::default(), None);
+        std::fs::remove_file(&path).unwrap();
+        let image = image.unwrap();
+
+        assert_eq!(*image.get_pixel(1, 0), pixel(255, 0, 0, 255));
+        assert_eq!(*image.get_pixel(5, 4), pixel(255, 0, 0, 255));
+        assert_eq!(*image.get_pixel(0, 0), pixel(0, 0, 255, 255));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{pixel, Pixel};
+use image::RgbaImage;
+use serde_derive::Deserialize;
+
+/// How a layer's colors combine with the colors beneath it. These follow the blend modes in the W3C
+/// Compositing and Blending spec, as used by the CSS `mix-blend-mode` property.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    #[serde(alias = "color-dodge")]
+    ColorDodge,
+    #[serde(alias = "color-burn")]
+    ColorBurn,
+    #[serde(alias = "hard-light")]
+    HardLight,
+    #[serde(alias = "soft-light")]
+    SoftLight,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+type Rgb = [f32; 3];
+
+fn luminosity(c: Rgb) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn clip_color(c: Rgb) -> Rgb {
+    let l = luminosity(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    let mut out = c;
+    for v in out.iter_mut() {
+        if n < 0.0 {
+            *v = l + (*v - l) * l / (l - n);
+        }
+        if x > 1.0 {
+            *v = l + (*v - l) * (1.0 - l) / (x - l);
+        }
+    }
+    out
+}
+
+fn set_luminosity(c: Rgb, l: f32) -> Rgb {
+    let d = l - luminosity(c);
+    clip_color([c[0] + d, c[1] + d, c[2] + d])
+}
+
+fn saturation(c: Rgb) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_saturation(c: Rgb, s: f32) -> Rgb {
+    let max = c[0].max(c[1]).max(c[2]);
+    let min = c[0].min(c[1]).min(c[2]);
+    if max <= min {
+        return [0.0; 3];
+    }
+
+    let mut out = [0.0; 3];
+    for (o, v) in out.iter_mut().zip(c.iter()) {
+        *o = (v - min) * s / (max - min);
+    }
+    out
+}
+
+fn separable(mode: BlendMode, b: f32, s: f32) -> f32 {
+    match mode {
+        BlendMode::Multiply => b * s,
+        BlendMode::Screen => b + s - b * s,
+        BlendMode::Overlay => separable(BlendMode::HardLight, s, b),
+        BlendMode::Darken => b.min(s),
+        BlendMode::Lighten => b.max(s),
+        BlendMode::ColorDodge => {
+            if b <= 0.0 {
+                0.0
+            } else if s >= 1.0 {
+                1.0
+            } else {
+                (b / (1.0 - s)).min(1.0)
+            }
+        }
+        BlendMode::ColorBurn => {
+            if b >= 1.0 {
+                1.0
+            } else if s <= 0.0 {
+                0.0
+            } else {
+                1.0 - ((1.0 - b) / s).min(1.0)
+            }
+        }
+        BlendMode::HardLight => {
+            if s <= 0.5 {
+                b * 2.0 * s
+            } else {
+                separable(BlendMode::Screen, b, 2.0 * s - 1.0)
+            }
+        }
+        BlendMode::SoftLight => {
+            if s <= 0.5 {
+                b - (1.0 - 2.0 * s) * b * (1.0 - b)
+            } else {
+                let d = if b <= 0.25 {
+                    ((16.0 * b - 12.0) * b + 4.0) * b
+                } else {
+                    b.sqrt()
+                };
+                b + (2.0 * s - 1.0) * (d - b)
+            }
+        }
+        BlendMode::Difference => (b - s).abs(),
+        BlendMode::Exclusion => b + s - 2.0 * b * s,
+        _ => s,
+    }
+}
+
+/// The blended color of the source over the backdrop, before taking alpha into account.
+fn blend_colors(mode: BlendMode, b: Rgb, s: Rgb) -> Rgb {
+    match mode {
+        BlendMode::Normal => s,
+        BlendMode::Hue => set_luminosity(set_saturation(s, saturation(b)), luminosity(b)),
+        BlendMode::Saturation => set_luminosity(set_saturation(b, saturation(s)), luminosity(b)),
+        BlendMode::Color => set_luminosity(s, luminosity(b)),
+        BlendMode::Luminosity => set_luminosity(b, luminosity(s)),
+        _ => [
+            separable(mode, b[0], s[0]),
+            separable(mode, b[1], s[1]),
+            separable(mode, b[2], s[2]),
+        ],
+    }
+}
+
+/// Draw `src` over `dest` using the blend mode, with the source's alpha scaled by `opacity`.
+pub(crate) fn blend_pixel(dest: Pixel, src: Pixel, mode: BlendMode, opacity: f32) -> Pixel {
+    let src_alpha = src[3] as f32 / 255.0 * opacity;
+    if src_alpha <= 0.0 {
+        return dest;
+    }
+
+    let dest_alpha = dest[3] as f32 / 255.0;
+    let to_rgb = |p: Pixel| {
+        [
+            p[0] as f32 / 255.0,
+            p[1] as f32 / 255.0,
+            p[2] as f32 / 255.0,
+        ]
+    };
+    let (b, s) = (to_rgb(dest), to_rgb(src));
+    let blended = blend_colors(mode, b, s);
+
+    let out_alpha = src_alpha + dest_alpha * (1.0 - src_alpha);
+    let channel = |i: usize| {
+        let mixed = (1.0 - dest_alpha) * s[i] + dest_alpha * blended[i];
+        let premultiplied = src_alpha * mixed + (1.0 - src_alpha) * dest_alpha * b[i];
+        ((premultiplied / out_alpha).clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    pixel(
+        channel(0),
+        channel(1),
+        channel(2),
+        (out_alpha * 255.0).round() as u8,
+    )
+}
+
+/// Like `image::imageops::overlay`, but with a blend mode and opacity.
+pub(crate) fn composite(
+    dest: &mut RgbaImage,
+    src: &RgbaImage,
+    left: u32,
+    top: u32,
+    mode: BlendMode,
+    opacity: f32,
+) {
+    if mode == BlendMode::Normal && opacity >= 1.0 {
+        image::imageops::overlay(dest, src, left as i64, top as i64);
+        return;
+    }
+
+    let (width, height) = dest.dimensions();
+    for (x, y, p) in src.enumerate_pixels() {
+        let (dest_x, dest_y) = (x + left, y + top);
+        if dest_x >= width || dest_y >= height {
+            continue;
+        }
+
+        let d = dest.get_pixel_mut(dest_x, dest_y);
+        *d = blend_pixel(*d, *p, mode, opacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAY: Pixel = pixel(128, 128, 128, 255);
+
+    fn blend(mode: BlendMode, dest: Pixel, src: Pixel) -> Pixel {
+        blend_pixel(dest, src, mode, 1.0)
+    }
+
+    #[test]
+    fn separable_modes() {
+        let orange = pixel(255, 128, 0, 255);
+        assert_eq!(blend(BlendMode::Normal, GRAY, orange), orange);
+        assert_eq!(
+            blend(BlendMode::Multiply, GRAY, orange),
+            pixel(128, 64, 0, 255)
+        );
+        assert_eq!(
+            blend(BlendMode::Screen, GRAY, orange),
+            pixel(255, 192, 128, 255)
+        );
+        assert_eq!(
+            blend(BlendMode::Darken, GRAY, orange),
+            pixel(128, 128, 0, 255)
+        );
+        assert_eq!(
+            blend(BlendMode::Lighten, GRAY, orange),
+            pixel(255, 128, 128, 255)
+        );
+        assert_eq!(
+            blend(BlendMode::Difference, GRAY, orange),
+            pixel(127, 0, 128, 255)
+        );
+        assert_eq!(
+            blend(BlendMode::Difference, orange, orange),
+            pixel(0, 0, 0, 255)
+        );
+        // Overlay is hard light with the layers swapped.
+        let dark = pixel(64, 64, 64, 255);
+        assert_eq!(
+            blend(BlendMode::Overlay, dark, orange),
+            blend(BlendMode::HardLight, orange, dark)
+        );
+        assert_eq!(
+            blend(BlendMode::ColorDodge, pixel(0, 128, 255, 255), GRAY),
+            pixel(0, 255, 255, 255)
+        );
+        assert_eq!(
+            blend(BlendMode::ColorBurn, pixel(0, 128, 255, 255), GRAY),
+            pixel(0, 2, 255, 255)
+        );
+    }
+
+    #[test]
+    fn non_separable_modes() {
+        let red = pixel(255, 0, 0, 255);
+        // A gray source has no saturation to give, so these leave the backdrop's luminosity in gray.
+        assert_eq!(blend(BlendMode::Color, red, GRAY), pixel(77, 77, 77, 255));
+        assert_eq!(
+            blend(BlendMode::Saturation, red, GRAY),
+            pixel(77, 77, 77, 255)
+        );
+        let colored = blend(BlendMode::Color, GRAY, red);
+        assert!(
+            colored[0] > colored[1] && colored[1] == colored[2],
+            "{:?}",
+            colored
+        );
+        assert_eq!(
+            luminosity(rgb(blend(BlendMode::Luminosity, red, GRAY))).round(),
+            luminosity(rgb(GRAY)).round()
+        );
+        assert_eq!(blend(BlendMode::Hue, GRAY, red), GRAY);
+    }
+
+    fn rgb(p: Pixel) -> Rgb {
+        [p[0] as f32, p[1] as f32, p[2] as f32]
+    }
+
+    #[test]
+    fn opacity_and_alpha() {
+        let white = pixel(255, 255, 255, 255);
+        assert_eq!(blend_pixel(GRAY, white, BlendMode::Normal, 0.0), GRAY);
+        assert_eq!(
+            blend_pixel(pixel(0, 0, 0, 255), white, BlendMode::Normal, 0.5),
+            pixel(128, 128, 128, 255)
+        );
+
+        // Over a transparent backdrop the blend mode has nothing to blend with, so the source is used.
+        let orange = pixel(255, 128, 0, 200);
+        assert_eq!(
+            blend(BlendMode::Multiply, pixel(0, 0, 0, 0), orange),
+            orange
+        );
+    }
+
+    #[test]
+    fn composite_clips_to_the_destination() {
+        let mut dest = RgbaImage::from_pixel(4, 4, GRAY);
+        let src = RgbaImage::from_pixel(3, 3, pixel(0, 0, 0, 255));
+        composite(&mut dest, &src, 2, 2, BlendMode::Screen, 1.0);
+        assert_eq!(*dest.get_pixel(3, 3), GRAY);
+        assert_eq!(*dest.get_pixel(1, 1), GRAY);
+
+        composite(&mut dest, &src, 2, 2, BlendMode::Multiply, 0.5);
+        assert_eq!(*dest.get_pixel(3, 3), pixel(64, 64, 64, 255));
+        assert_eq!(*dest.get_pixel(1, 3), GRAY);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::border::{BlockBorder, BorderPainter};
+use crate::geometry::RoundedRect;
+use crate::paint::Painter;
+use crate::{mix, ColorContext, Radius, Rect, TRANSPARENT};
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+
+/// The outline of an element on the card: its border box, the padding box inside the border, and
+/// what's needed to draw the border.
+pub(crate) struct BlockBox {
+    pub border_box: RoundedRect,
+    pub padding_box: RoundedRect,
+    /// The border widths in the order top, right, bottom, left.
+    pub border_widths: [u32; 4],
+    border: Option<BorderPainter>,
+}
+
+impl BlockBox {
+    pub fn new(
+        rect: &Rect,
+        border: Option<&BlockBorder>,
+        radius: Option<Radius>,
+        image_size: (u32, u32),
+        colors: &ColorContext,
+    ) -> Result<BlockBox> {
+        let (width, height) = image_size;
+        if rect.left > width || rect.right > width || rect.top > height || rect.bottom > height {
+            return Err(anyhow!(
+                "Rect {rect:?} does not fit in image of size {width}x{height}",
+                rect = rect,
+                width = width,
+                height = height
+            ));
+        } else if rect.left >= rect.right || rect.top > rect.bottom {
+            return Err(anyhow!("rect must not have a negative size"));
+        }
+
+        let border_widths = border.map(|b| b.widths()).unwrap_or_default();
+        let radius = border
+            .and_then(|b| b.radius)
+            .or(radius)
+            .map(|r| r.corners())
+            .unwrap_or_default();
+        let border_box = RoundedRect::new(rect, radius);
+        let [top, right, bottom, left] = border_widths;
+        let padding_box = border_box.inset(top as f32, right as f32, bottom as f32, left as f32);
+
+        Ok(BlockBox {
+            border_box,
+            padding_box,
+            border_widths,
+            border: border
+                .map(|b| BorderPainter::new(b, &border_box, colors))
+                .transpose()?,
+        })
+    }
+
+    /// Shrink `rect` by the width of the border.
+    pub fn inside_border(&self, rect: &Rect) -> Rect {
+        let [top, right, bottom, left] = self.border_widths;
+        Rect {
+            top: rect.top + top,
+            right: rect.right - right,
+            bottom: rect.bottom - bottom,
+            left: rect.left + left,
+        }
+    }
+
+    /// Draw the background and border onto a transparent image of the given size.
+    pub fn render(&self, width: u32, height: u32, background: Option<&Painter>) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let outer = self.border_box.coverage(x, y);
+            if outer <= 0.0 {
+                return TRANSPARENT;
+            }
+
+            let inner = self.padding_box.coverage(x, y);
+            let (border_pixel, border_coverage) = self
+                .border
+                .as_ref()
+                .map(|b| b.at(x, y, outer, inner))
+                .unwrap_or((TRANSPARENT, 0.0));
+            let bg_pixel = background.map(|p| p.at(x, y)).unwrap_or(TRANSPARENT);
+            mix(border_pixel, border_coverage, bg_pixel, inner)
+        })
+    }
+}
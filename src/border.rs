@@ -0,0 +1,279 @@
+use crate::geometry::RoundedRect;
+use crate::paint::{Paint, Painter};
+use crate::{BoxShadow, ColorContext, Pixel, Radius};
+use anyhow::Result;
+use serde_derive::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct BlockBorder<'a> {
+    /// The width of every side that doesn't set its own width.
+    #[serde(default)]
+    pub width: u32,
+    /// The color of every side that doesn't set its own color. Gradients span the entire border box.
+    #[serde(default)]
+    pub color: Paint<'a>,
+    #[serde(default)]
+    pub style: BorderStyle,
+    pub top: Option<BorderSide<'a>>,
+    pub right: Option<BorderSide<'a>>,
+    pub bottom: Option<BorderSide<'a>>,
+    pub left: Option<BorderSide<'a>>,
+    /// One or more shadows cast by the block. Like CSS, the first shadow is drawn on top.
+    #[serde(default, deserialize_with = "crate::one_or_many")]
+    pub shadow: Vec<BoxShadow<'a>>,
+    /// Round the corners of the border. Overrides the block's `radius` when set.
+    pub radius: Option<Radius>,
+}
+
+/// Settings for one side of a border. Anything left unset comes from the border itself.
+#[derive(Debug, Default, Deserialize)]
+pub struct BorderSide<'a> {
+    pub width: Option<u32>,
+    pub color: Option<Paint<'a>>,
+    pub style: Option<BorderStyle>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BorderStyle {
+    #[default]
+    Solid,
+    /// Dashes twice as long as the border is wide.
+    Dashed,
+    /// Round dots as wide as the border.
+    Dotted,
+    /// Two solid lines, each a third of the border's width.
+    Double,
+}
+
+impl<'a> BlockBorder<'a> {
+    /// The sides in the order top, right, bottom, left.
+    fn sides(&self) -> [Option<&BorderSide<'a>>; 4] {
+        [
+            self.top.as_ref(),
+            self.right.as_ref(),
+            self.bottom.as_ref(),
+            self.left.as_ref(),
+        ]
+    }
+
+    /// The width of each side, in the order top, right, bottom, left.
+    pub fn widths(&self) -> [u32; 4] {
+        let mut widths = [self.width; 4];
+        for (w, side) in widths.iter_mut().zip(self.sides().iter()) {
+            if let Some(side_width) = side.and_then(|s| s.width) {
+                *w = side_width;
+            }
+        }
+        widths
+    }
+}
+
+struct SidePainter {
+    width: f32,
+    style: BorderStyle,
+    painter: Painter,
+}
+
+/// A border ready to be drawn around a particular box.
+pub(crate) struct BorderPainter {
+    /// The sides in the order top, right, bottom, left.
+    sides: Vec<SidePainter>,
+    border_box: RoundedRect,
+    /// The inside edge of the outer line of a double border.
+    double_outer: RoundedRect,
+    /// The outside edge of the inner line of a double border.
+    double_inner: RoundedRect,
+}
+
+impl BorderPainter {
+    pub fn new(
+        border: &BlockBorder,
+        border_box: &RoundedRect,
+        colors: &ColorContext,
+    ) -> Result<BorderPainter> {
+        let widths = border.widths();
+        let sides = border
+            .sides()
+            .iter()
+            .zip(widths.iter())
+            .map(|(side, width)| {
+                let paint = side.and_then(|s| s.color.as_ref()).unwrap_or(&border.color);
+                Ok(SidePainter {
+                    width: *width as f32,
+                    style: side.and_then(|s| s.style).unwrap_or(border.style),
+                    painter: paint.painter(border_box, colors)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let inset = |fraction: f32| {
+            let w = |i: usize| sides[i].width * fraction;
+            border_box.inset(w(0), w(1), w(2), w(3))
+        };
+
+        Ok(BorderPainter {
+            double_outer: inset(1.0 / 3.0),
+            double_inner: inset(2.0 / 3.0),
+            sides,
+            border_box: *border_box,
+        })
+    }
+
+    /// Figure out which side a pixel in the border belongs to. As in CSS, the corners are split along
+    /// the line from the outside corner to the inside corner.
+    fn side_at(&self, px: f32, py: f32) -> Option<usize> {
+        let b = &self.border_box;
+        let distances = [py - b.top, b.right - px, b.bottom - py, px - b.left];
+        distances
+            .iter()
+            .zip(self.sides.iter())
+            .enumerate()
+            .filter(|(_, (_, side))| side.width > 0.0)
+            .map(|(i, (d, side))| (i, d / side.width))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(i, _)| i)
+    }
+
+    /// The border's color at a pixel, and how much of the pixel it covers. `outer` and `inner` are the
+    /// pixel's coverage by the border box and padding box.
+    pub fn at(&self, x: u32, y: u32, outer: f32, inner: f32) -> (Pixel, f32) {
+        let ring = outer - inner;
+        if ring <= 0.0 {
+            return (crate::TRANSPARENT, 0.0);
+        }
+
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        let index = match self.side_at(px, py) {
+            Some(i) => i,
+            None => return (crate::TRANSPARENT, 0.0),
+        };
+        let side = &self.sides[index];
+        let b = &self.border_box;
+
+        // Position along the side, the side's length, and distance in from the outside edge.
+        let horizontal = index % 2 == 0;
+        let (along, length) = if horizontal {
+            (px - b.left, b.width())
+        } else {
+            (py - b.top, b.height())
+        };
+        let depth = [py - b.top, b.right - px, b.bottom - py, px - b.left][index];
+
+        let coverage = match side.style {
+            BorderStyle::Solid => ring,
+            BorderStyle::Double => {
+                let outer_line = outer - self.double_outer.coverage(x, y);
+                let inner_line = self.double_inner.coverage(x, y) - inner;
+                (outer_line + inner_line).clamp(0.0, 1.0)
+            }
+            BorderStyle::Dashed => ring.min(dash_coverage(along, length, side.width)),
+            BorderStyle::Dotted => ring.min(dot_coverage(along, depth, length, side.width)),
+        };
+
+        (side.painter.at(x, y), coverage)
+    }
+}
+
+/// Coverage of a pixel centered at `along` by a dash pattern. The dashes are stretched slightly so that
+/// the side starts and ends with a full dash.
+fn dash_coverage(along: f32, length: f32, width: f32) -> f32 {
+    let (dash, gap) = (width * 2.0, width);
+    let count = ((length + gap) / (dash + gap)).round().max(1.0);
+    let scale = (length + gap) / (count * (dash + gap));
+    let (dash, period) = (dash * scale, (dash + gap) * scale);
+
+    // Overlap of the pixel's span with the dash in this period and the start of the next one.
+    let pos = along.rem_euclid(period);
+    let this_dash = ((pos + 0.5).min(dash) - (pos - 0.5).max(0.0)).max(0.0);
+    let next_dash = (pos + 0.5 - period).max(0.0);
+    (this_dash + next_dash).min(1.0)
+}
+
+/// Coverage of a pixel by a row of dots along the middle of the side. The spacing is stretched slightly
+/// so that there is a dot at each end of the side.
+fn dot_coverage(along: f32, depth: f32, length: f32, width: f32) -> f32 {
+    let radius = width / 2.0;
+    let count = ((length - width) / (width * 2.0)).round().max(1.0);
+    let spacing = (length - width) / count;
+    let index = ((along - radius) / spacing).round().clamp(0.0, count);
+    let center = radius + index * spacing;
+
+    let distance = ((along - center).powi(2) + (depth - radius).powi(2)).sqrt();
+    (0.5 - (distance - radius)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    fn border(config: &str) -> BlockBorder<'static> {
+        toml::from_str(config).unwrap()
+    }
+
+    fn painter(border: &BlockBorder) -> BorderPainter {
+        let border_box = RoundedRect::new(
+            &Rect {
+                left: 0,
+                top: 0,
+                right: 39,
+                bottom: 19,
+            },
+            [0.0; 4],
+        );
+        BorderPainter::new(border, &border_box, &ColorContext::default()).unwrap()
+    }
+
+    #[test]
+    fn sides_override_the_border() {
+        let b = border(
+            r##"
+            width = 2
+            color = "ff0000"
+            left = { width = 6, color = "0000ff" }
+            bottom = { width = 0 }
+            "##,
+        );
+        assert_eq!(b.widths(), [2, 2, 0, 6]);
+
+        let p = painter(&b);
+        assert_eq!(p.at(20, 0, 1.0, 0.0), (crate::pixel(255, 0, 0, 255), 1.0));
+        assert_eq!(p.at(3, 10, 1.0, 0.0), (crate::pixel(0, 0, 255, 255), 1.0));
+        // The corner is split between the top and left sides in proportion to their widths.
+        assert_eq!(p.at(1, 1, 1.0, 0.0).0, crate::pixel(0, 0, 255, 255));
+        assert_eq!(p.at(5, 1, 1.0, 0.0).0, crate::pixel(255, 0, 0, 255));
+        // A zero-width side never owns a pixel.
+        assert_eq!(p.side_at(36.5, 19.5), Some(1));
+    }
+
+    #[test]
+    fn dashes_start_and_end_each_side() {
+        let p = painter(&border("width = 2\nstyle = \"dashed\""));
+        let coverage = (0..40).map(|x| p.at(x, 0, 1.0, 0.0).1).collect::<Vec<_>>();
+        assert_eq!(coverage[0], 1.0);
+        assert_eq!(coverage[39], 1.0);
+        assert!(coverage.contains(&0.0));
+
+        // Dashes are about twice as long as the gaps.
+        let covered = coverage.iter().sum::<f32>();
+        assert!((24.0..30.0).contains(&covered), "covered {}", covered);
+    }
+
+    #[test]
+    fn dots_are_round() {
+        let p = painter(&border("width = 4\nstyle = \"dotted\""));
+        // The first dot on the top side is centered at (2, 2).
+        assert_eq!(p.at(1, 2, 1.0, 0.0).1, 1.0);
+        assert_eq!(p.at(2, 1, 1.0, 0.0).1, 1.0);
+        assert!(p.at(0, 0, 1.0, 0.0).1 < 0.5);
+        assert_eq!(p.at(5, 2, 1.0, 0.0).1, 0.0);
+    }
+
+    #[test]
+    fn double_borders_leave_a_gap() {
+        let p = painter(&border("width = 6\nstyle = \"double\""));
+        let coverage = (0..6).map(|y| p.at(20, y, 1.0, 0.0).1).collect::<Vec<_>>();
+        assert_eq!(coverage, vec![1.0, 1.0, 0.0, 0.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,1121 @@
+use crate::swatch::{self, Swatches};
+use crate::{pixel, Palette, Pixel};
+use anyhow::{anyhow, Result};
+use serde::de::{self, value::MapAccessDeserializer, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A color, written in the config as an `[r, g, b]` or `[r, g, b, a]` array or as a CSS color string:
+/// hex with or without `#`, `rgb()`, `rgba()`, `hsl()`, `hsla()`, `oklch()` or a CSS color name. Strings
+/// starting with `@` refer to a swatch taken from the background, and strings starting with `$` refer to a
+/// color in the palette. References can be adjusted with `.alpha(0.5)`, `.darken(20%)` and `.lighten(20%)`.
+#[derive(Clone, Debug)]
+pub enum Color<'a> {
+    Rgb(u8, u8, u8),
+    Rgba(u8, u8, u8, u8),
+    RgbString(Cow<'a, str>),
+}
+
+impl<'a> Default for Color<'a> {
+    fn default() -> Color<'a> {
+        Color::Rgb(0, 0, 0)
+    }
+}
+
+/// Converting a color on its own only works for literal colors, since references need a card to refer to.
+impl<'a> TryFrom<&Color<'a>> for Pixel {
+    type Error = anyhow::Error;
+
+    fn try_from(val: &Color) -> Result<Pixel> {
+        val.resolve(&ColorContext::default())
+    }
+}
+
+/// What the references in colors refer to while a card is drawn.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ColorContext<'a> {
+    /// The swatches picked from the background. These are only set once the background has been drawn,
+    /// and only if something on the card refers to them.
+    pub swatches: Option<&'a Swatches>,
+    pub palette: Option<&'a Palette<'a>>,
+}
+
+impl<'a> Color<'a> {
+    /// The color as a pixel, looking up any reference in `context`.
+    pub(crate) fn resolve(&self, context: &ColorContext) -> Result<Pixel> {
+        match self {
+            Color::Rgb(r, g, b) => Ok(pixel(*r, *g, *b, 255)),
+            Color::Rgba(r, g, b, a) => Ok(pixel(*r, *g, *b, *a)),
+            Color::RgbString(s) => parse_color(s, context),
+        }
+    }
+
+    /// Whether the color refers to a swatch, like `"@vibrant"`, directly or through the palette.
+    pub(crate) fn uses_swatches(&self, palette: Option<&Palette>) -> bool {
+        let mut found = false;
+        if let Color::RgbString(s) = self {
+            // A broken reference is reported when the color is resolved.
+            let _ = walk_references(s, palette, &mut || found = true);
+        }
+        found
+    }
+}
+
+/// Whether the color refers to something that's only known when the card is drawn.
+fn is_reference(color: &str) -> bool {
+    color.starts_with('@') || color.starts_with('$')
+}
+
+pub(crate) struct ColorVisitor;
+
+impl<'de> Visitor<'de> for ColorVisitor {
+    type Value = Color<'static>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a CSS color string or an array of 3 or 4 numbers from 0 to 255")
+    }
+
+    /// Check the color while parsing the config, so that the error points at where it's written.
+    /// References are kept as strings to look up when the card is drawn.
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Color<'static>, E> {
+        if is_reference(v.trim()) {
+            parse_reference(v).map_err(E::custom)?;
+            return Ok(Color::RgbString(Cow::Owned(v.to_string())));
+        }
+
+        let p = parse_css_color(v).map_err(E::custom)?;
+        Ok(Color::Rgba(p[0], p[1], p[2], p[3]))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<Color<'static>, A::Error> {
+        let mut channels = Vec::with_capacity(4);
+        while let Some(channel) = seq.next_element::<u8>()? {
+            channels.push(channel);
+        }
+
+        match channels[..] {
+            [r, g, b] => Ok(Color::Rgb(r, g, b)),
+            [r, g, b, a] => Ok(Color::Rgba(r, g, b, a)),
+            _ => Err(de::Error::invalid_length(channels.len(), &self)),
+        }
+    }
+}
+
+impl<'de, 'a> serde::Deserialize<'de> for Color<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(ColorVisitor)
+    }
+}
+
+/// Either a color or a table. Enums that accept both use this instead of `#[serde(untagged)]`, which
+/// would replace the color's parse error with a generic one.
+pub(crate) enum ColorOr<T> {
+    Color(Color<'static>),
+    Table(T),
+}
+
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for ColorOr<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct ColorOrVisitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: serde::Deserialize<'de>> Visitor<'de> for ColorOrVisitor<T> {
+            type Value = ColorOr<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a color or a table")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<ColorOr<T>, E> {
+                ColorVisitor.visit_str(v).map(ColorOr::Color)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                seq: A,
+            ) -> std::result::Result<ColorOr<T>, A::Error> {
+                ColorVisitor.visit_seq(seq).map(ColorOr::Color)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> std::result::Result<ColorOr<T>, A::Error> {
+                T::deserialize(MapAccessDeserializer::new(map)).map(ColorOr::Table)
+            }
+        }
+
+        deserializer.deserialize_any(ColorOrVisitor(std::marker::PhantomData))
+    }
+}
+
+fn parse_color(color: &str, context: &ColorContext) -> Result<Pixel> {
+    resolve(color, context, &mut Vec::new())
+}
+
+/// Resolve a color string. `chain` holds the palette colors being looked up, to catch colors that refer to
+/// themselves.
+fn resolve(color: &str, context: &ColorContext, chain: &mut Vec<String>) -> Result<Pixel> {
+    if !is_reference(color.trim()) {
+        return parse_css_color(color);
+    }
+
+    let reference = parse_reference(color)?;
+    let mut p = match reference.kind {
+        ReferenceKind::Swatch => swatch::lookup(&reference.name, context.swatches)?,
+        ReferenceKind::Palette => {
+            let target = palette_color(&reference.name, color, context.palette, chain)?;
+            chain.push(reference.name.clone());
+            let p = match target {
+                Color::RgbString(s) => resolve(s, context, chain),
+                c => c.resolve(context),
+            };
+            chain.pop();
+            p?
+        }
+    };
+
+    for modifier in &reference.modifiers {
+        p = modifier.apply(p);
+    }
+    Ok(p)
+}
+
+/// Check that a color's palette references can be resolved, without resolving swatches. Strings that
+/// aren't references are left to the color parser.
+pub(crate) fn check_reference(color: &str, palette: Option<&Palette>) -> Result<()> {
+    walk_references(color, palette, &mut || {})
+}
+
+/// Follow a color's palette references, calling `on_swatch` for each swatch reference found on the way.
+fn walk_references(
+    color: &str,
+    palette: Option<&Palette>,
+    on_swatch: &mut dyn FnMut(),
+) -> Result<()> {
+    fn walk(
+        color: &str,
+        palette: Option<&Palette>,
+        on_swatch: &mut dyn FnMut(),
+        chain: &mut Vec<String>,
+    ) -> Result<()> {
+        if !is_reference(color.trim()) {
+            return Ok(());
+        }
+
+        let reference = parse_reference(color)?;
+        match reference.kind {
+            ReferenceKind::Swatch => on_swatch(),
+            ReferenceKind::Palette => {
+                if let Color::RgbString(s) = palette_color(&reference.name, color, palette, chain)?
+                {
+                    chain.push(reference.name);
+                    walk(s, palette, on_swatch, chain)?;
+                    chain.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    walk(color, palette, on_swatch, &mut Vec::new())
+}
+
+/// Look up a palette color, unless it's already in `chain`.
+fn palette_color<'p>(
+    name: &str,
+    color: &str,
+    palette: Option<&'p Palette<'p>>,
+    chain: &[String],
+) -> Result<&'p Color<'p>> {
+    if let Some(start) = chain.iter().position(|n| n == name) {
+        let cycle = chain[start..]
+            .iter()
+            .chain(std::iter::once(&name.to_string()))
+            .map(|n| format!("${}", n))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        return Err(anyhow!(
+            "Palette color \"${}\" refers to itself: {}",
+            name,
+            cycle
+        ));
+    }
+
+    palette
+        .and_then(|p| p.colors.get(name))
+        .ok_or_else(|| anyhow!("Unknown palette color \"${}\" in {:?}", name, color))
+}
+
+/// Parse a color in CSS syntax. Errors quote the color and say where in it the problem is.
+fn parse_css_color(color: &str) -> Result<Pixel> {
+    Parser::new(color).parse().map_err(|e| e.describe(color))
+}
+
+fn parse_reference(color: &str) -> Result<Reference> {
+    Parser::new(color)
+        .parse_reference()
+        .map_err(|e| e.describe(color))
+}
+
+enum ReferenceKind {
+    /// `@name`, a swatch taken from the background.
+    Swatch,
+    /// `$name`, a color from the palette.
+    Palette,
+}
+
+/// A reference to a swatch or palette color, such as `$accent.alpha(0.5)`.
+struct Reference {
+    kind: ReferenceKind,
+    name: String,
+    /// Changes to the color, applied in order.
+    modifiers: Vec<Modifier>,
+}
+
+enum Modifier {
+    /// Set the opacity, from 0 to 1.
+    Alpha(f32),
+    /// Lower or raise the HSL lightness by this many percentage points, as a fraction.
+    Darken(f32),
+    Lighten(f32),
+}
+
+impl Modifier {
+    fn apply(&self, p: Pixel) -> Pixel {
+        let channel = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let adjust_lightness = |amount: f32| {
+            let (hue, saturation, lightness) = rgb_to_hsl(p);
+            let [r, g, b] = hsl_to_rgb(hue, saturation, lightness + amount);
+            pixel(channel(r), channel(g), channel(b), p[3])
+        };
+
+        match *self {
+            Modifier::Alpha(alpha) => pixel(p[0], p[1], p[2], channel(alpha)),
+            Modifier::Darken(amount) => adjust_lightness(-amount),
+            Modifier::Lighten(amount) => adjust_lightness(amount),
+        }
+    }
+}
+
+struct ParseError {
+    message: String,
+    /// The character offset in the color string.
+    position: usize,
+}
+
+impl ParseError {
+    fn describe(&self, color: &str) -> anyhow::Error {
+        anyhow!(
+            "Invalid color {:?}: {} at position {}",
+            color,
+            self.message,
+            self.position + 1
+        )
+    }
+}
+
+type ParseResult<T> = std::result::Result<T, ParseError>;
+
+/// A number in a color function, with its unit if it has one.
+struct Number {
+    value: f32,
+    unit: String,
+    position: usize,
+}
+
+impl Number {
+    fn is_percentage(&self) -> bool {
+        self.unit == "%"
+    }
+}
+
+#[derive(PartialEq)]
+enum Separator {
+    Space,
+    Comma,
+    Slash,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Parser {
+        Parser {
+            chars: input.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn error<T>(&self, position: usize, message: impl Into<String>) -> ParseResult<T> {
+        Err(ParseError {
+            message: message.into(),
+            position,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let start = self.position;
+        while self.peek().map(&f).unwrap_or(false) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn parse(mut self) -> ParseResult<Pixel> {
+        self.skip_whitespace();
+        let start = self.position;
+        if self.peek().is_none() {
+            return self.error(start, "the color is empty");
+        }
+
+        let color = if self.peek() == Some('#') {
+            self.position += 1;
+            let digits = self.take_while(|c| c.is_ascii_alphanumeric());
+            self.parse_hex(&digits, start + 1)?
+        } else {
+            let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '-');
+            if self.peek() == Some('(') {
+                self.position += 1;
+                self.parse_function(&word.to_ascii_lowercase(), start)?
+            } else if let Some(named) = named_color(&word.to_ascii_lowercase()) {
+                named
+            } else if word.chars().all(|c| c.is_ascii_hexdigit()) && !word.is_empty() {
+                self.parse_hex(&word, start)?
+            } else if word.is_empty() {
+                return self.error(start, format!("unexpected {:?}", self.peek().unwrap()));
+            } else {
+                return self.error(start, format!("unknown color name {:?}", word));
+            }
+        };
+
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return self.error(self.position, "unexpected text after the color");
+        }
+
+        Ok(color)
+    }
+
+    fn parse_reference(mut self) -> ParseResult<Reference> {
+        self.skip_whitespace();
+        let kind = match self.peek() {
+            Some('@') => ReferenceKind::Swatch,
+            _ => ReferenceKind::Palette,
+        };
+        self.position += 1;
+
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        let name = self.take_while(is_name_char);
+        if name.is_empty() {
+            return self.error(self.position, "expected a name");
+        }
+
+        let mut modifiers = Vec::new();
+        while self.peek() == Some('.') {
+            self.position += 1;
+            let start = self.position;
+            let function = self.take_while(|c| c.is_ascii_alphabetic());
+            if self.peek() != Some('(') {
+                return self.error(self.position, format!("expected '(' after {:?}", function));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let argument = self.parse_number()?;
+            self.skip_whitespace();
+            if self.peek() != Some(')') {
+                return self.error(
+                    self.position,
+                    format!("expected ')' to close {}()", function),
+                );
+            }
+            self.position += 1;
+
+            modifiers.push(match function.to_ascii_lowercase().as_str() {
+                "alpha" => Modifier::Alpha(self.fraction(&argument, 1.0)?),
+                "darken" => Modifier::Darken(self.fraction(&argument, 100.0)?),
+                "lighten" => Modifier::Lighten(self.fraction(&argument, 100.0)?),
+                _ => {
+                    return self.error(
+                        start,
+                        format!(
+                            "unknown modifier {:?}, expected alpha, darken or lighten",
+                            function
+                        ),
+                    )
+                }
+            });
+        }
+
+        self.skip_whitespace();
+        if self.peek().is_some() {
+            return self.error(self.position, "unexpected text after the color");
+        }
+
+        Ok(Reference {
+            kind,
+            name,
+            modifiers,
+        })
+    }
+
+    fn parse_hex(&self, digits: &str, position: usize) -> ParseResult<Pixel> {
+        if let Some(offset) = digits.find(|c: char| !c.is_ascii_hexdigit()) {
+            return self.error(position + offset, "hex colors can only contain 0-9 and a-f");
+        }
+
+        let value = |i: usize, len: usize| {
+            let v = u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).unwrap();
+            if len == 1 {
+                v * 17
+            } else {
+                v
+            }
+        };
+        match digits.len() {
+            3 => Ok(pixel(value(0, 1), value(1, 1), value(2, 1), 255)),
+            4 => Ok(pixel(value(0, 1), value(1, 1), value(2, 1), value(3, 1))),
+            6 => Ok(pixel(value(0, 2), value(1, 2), value(2, 2), 255)),
+            8 => Ok(pixel(value(0, 2), value(1, 2), value(2, 2), value(3, 2))),
+            n => self.error(
+                position,
+                format!("hex colors must have 3, 4, 6 or 8 digits, not {}", n),
+            ),
+        }
+    }
+
+    fn parse_number(&mut self) -> ParseResult<Number> {
+        let position = self.position;
+        let text = self.take_while(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+');
+        if text.is_empty() {
+            return match self.peek() {
+                Some(c) => self.error(position, format!("expected a number but found {:?}", c)),
+                None => self.error(position, "expected a number"),
+            };
+        }
+
+        let value = text
+            .parse::<f32>()
+            .or_else(|_| self.error(position, format!("{:?} is not a number", text)))?;
+        let unit = self.take_while(|c| c.is_ascii_alphabetic() || c == '%');
+        Ok(Number {
+            value,
+            unit: unit.to_ascii_lowercase(),
+            position,
+        })
+    }
+
+    /// Parse the arguments of a color function up to the closing parenthesis. Arguments can be separated
+    /// by commas or spaces, with the alpha after a comma or a slash.
+    fn parse_arguments(&mut self, name: &str) -> ParseResult<(Vec<Number>, Option<Number>)> {
+        let mut components = Vec::new();
+        let mut alpha = None;
+        let mut separator = Separator::Comma;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(')') if !components.is_empty() => {
+                    self.position += 1;
+                    break;
+                }
+                None => {
+                    return self.error(self.position, format!("missing ')' to close {}()", name))
+                }
+                _ => {}
+            }
+
+            if components.len() == 4 || alpha.is_some() {
+                return self.error(self.position, format!("too many arguments to {}()", name));
+            }
+
+            let number = self.parse_number()?;
+            if separator == Separator::Slash || components.len() == 3 {
+                alpha = Some(number);
+            } else {
+                components.push(number);
+            }
+
+            separator = if self.skip_whitespace() {
+                Separator::Space
+            } else {
+                Separator::Comma
+            };
+            match self.peek() {
+                Some(',') => {
+                    self.position += 1;
+                    separator = Separator::Comma;
+                }
+                Some('/') => {
+                    self.position += 1;
+                    separator = Separator::Slash;
+                }
+                Some(')') | None => {}
+                Some(_) if separator == Separator::Space => {}
+                Some(c) => {
+                    return self.error(
+                        self.position,
+                        format!("expected ',', '/' or ')' but found {:?}", c),
+                    )
+                }
+            }
+        }
+
+        if components.len() != 3 {
+            return self.error(
+                self.position - 1,
+                format!(
+                    "{}() needs 3 components, but found {}",
+                    name,
+                    components.len()
+                ),
+            );
+        }
+
+        Ok((components, alpha))
+    }
+
+    fn parse_function(&mut self, name: &str, start: usize) -> ParseResult<Pixel> {
+        let (c, alpha) = match name {
+            "rgb" | "rgba" | "hsl" | "hsla" | "oklch" => self.parse_arguments(name)?,
+            _ => return self.error(start, format!("unknown color function {:?}", name)),
+        };
+
+        let alpha = match alpha {
+            Some(a) => self.fraction(&a, 1.0)?,
+            None => 1.0,
+        };
+        let [r, g, b] = match name {
+            "rgb" | "rgba" => [
+                self.fraction(&c[0], 255.0)?,
+                self.fraction(&c[1], 255.0)?,
+                self.fraction(&c[2], 255.0)?,
+            ],
+            "hsl" | "hsla" => {
+                let hue = self.angle(&c[0])?;
+                // Saturation and lightness are percentages, but modern CSS allows leaving out the `%`.
+                let saturation = self.fraction(&c[1], 100.0)?;
+                let lightness = self.fraction(&c[2], 100.0)?;
+                hsl_to_rgb(hue, saturation, lightness)
+            }
+            _ => {
+                let lightness = self.fraction(&c[0], 1.0)?;
+                let chroma = if c[1].is_percentage() {
+                    c[1].value / 100.0 * 0.4
+                } else {
+                    self.unitless(&c[1])?
+                };
+                let hue = self.angle(&c[2])?;
+                oklch_to_rgb(lightness, chroma, hue)
+            }
+        };
+
+        let channel = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Ok(pixel(channel(r), channel(g), channel(b), channel(alpha)))
+    }
+
+    fn unitless(&self, n: &Number) -> ParseResult<f32> {
+        if n.unit.is_empty() {
+            Ok(n.value)
+        } else {
+            self.error(n.position, format!("unexpected unit {:?}", n.unit))
+        }
+    }
+
+    /// A percentage, or a number where `max` is 100%, as a fraction from 0 to 1.
+    fn fraction(&self, n: &Number, max: f32) -> ParseResult<f32> {
+        if n.is_percentage() {
+            Ok(n.value / 100.0)
+        } else {
+            Ok(self.unitless(n)? / max)
+        }
+    }
+
+    /// An angle in degrees.
+    fn angle(&self, n: &Number) -> ParseResult<f32> {
+        let degrees = match n.unit.as_str() {
+            "" | "deg" => n.value,
+            "rad" => n.value.to_degrees(),
+            "grad" => n.value * 0.9,
+            "turn" => n.value * 360.0,
+            unit => return self.error(n.position, format!("unexpected unit {:?} for a hue", unit)),
+        };
+        Ok(degrees.rem_euclid(360.0))
+    }
+}
+
+/// The hue in degrees, and the saturation and lightness from 0 to 1.
+fn rgb_to_hsl(p: Pixel) -> (f32, f32, f32) {
+    let [r, g, b] = [
+        p[0] as f32 / 255.0,
+        p[1] as f32 / 255.0,
+        p[2] as f32 / 255.0,
+    ];
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (hue * 60.0, saturation, lightness)
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let saturation = saturation.clamp(0.0, 1.0);
+    let lightness = lightness.clamp(0.0, 1.0);
+    let f = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        let a = saturation * lightness.min(1.0 - lightness);
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    [f(0.0), f(8.0), f(4.0)]
+}
+
+/// Convert an OKLCH color to sRGB, clipping it to the sRGB gamut.
+fn oklch_to_rgb(lightness: f32, chroma: f32, hue: f32) -> [f32; 3] {
+    let (a, b) = (
+        chroma.max(0.0) * hue.to_radians().cos(),
+        chroma.max(0.0) * hue.to_radians().sin(),
+    );
+    let l = (lightness + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+    let m = (lightness - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+    let s = (lightness - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+
+    let linear = [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ];
+    let gamma = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.003_130_8 {
+            12.92 * c
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    [gamma(linear[0]), gamma(linear[1]), gamma(linear[2])]
+}
+
+/// Look up one of the CSS named colors.
+fn named_color(name: &str) -> Option<Pixel> {
+    if name == "transparent" {
+        return Some(pixel(0, 0, 0, 0));
+    }
+
+    NAMED_COLORS
+        .binary_search_by_key(&name, |(n, _)| n)
+        .ok()
+        .map(|i| {
+            let rgb = NAMED_COLORS[i].1;
+            pixel((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255)
+        })
+}
+
+/// The CSS named colors, sorted by name.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn css(color: &str) -> Pixel {
+        parse_css_color(color).unwrap()
+    }
+
+    fn css_error(color: &str) -> String {
+        parse_css_color(color).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(css("#f80"), pixel(0xff, 0x88, 0x00, 255));
+        assert_eq!(css("#f808"), pixel(0xff, 0x88, 0x00, 0x88));
+        assert_eq!(css("#3A7BD5"), pixel(0x3a, 0x7b, 0xd5, 255));
+        assert_eq!(css("#3a7bd580"), pixel(0x3a, 0x7b, 0xd5, 0x80));
+        // The `#` can be left out, as older configs did.
+        assert_eq!(css("3a7bd5"), pixel(0x3a, 0x7b, 0xd5, 255));
+    }
+
+    #[test]
+    fn named_colors() {
+        assert_eq!(css("rebeccapurple"), pixel(0x66, 0x33, 0x99, 255));
+        assert_eq!(css(" White "), pixel(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(css("rgb(255, 128, 0)"), pixel(255, 128, 0, 255));
+        assert_eq!(css("rgba(255, 128, 0, 0.5)"), pixel(255, 128, 0, 128));
+        assert_eq!(css("rgb(100% 50% 0% / 25%)"), pixel(255, 128, 0, 64));
+        assert_eq!(css("RGB(0 0 0)"), pixel(0, 0, 0, 255));
+        assert_eq!(css("hsl(120, 100%, 25%)"), pixel(0, 128, 0, 255));
+        assert_eq!(css("hsl(0.5turn 100 50 / 0.5)"), pixel(0, 255, 255, 128));
+        assert_eq!(css("hsla(240deg, 100%, 50%, 1)"), pixel(0, 0, 255, 255));
+        assert_eq!(css("oklch(1 0 0)"), pixel(255, 255, 255, 255));
+        assert_eq!(css("oklch(0% 0 0)"), pixel(0, 0, 0, 255));
+        assert_eq!(css("oklch(62.8% 0.2577 29.23)"), pixel(255, 0, 0, 255));
+        // Out of range values are clamped rather than rejected, like in CSS.
+        assert_eq!(css("rgb(300 -20 0)"), pixel(255, 0, 0, 255));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            css_error(""),
+            "Invalid color \"\": the color is empty at position 1"
+        );
+        assert_eq!(
+            css_error("#12345"),
+            "Invalid color \"#12345\": hex colors must have 3, 4, 6 or 8 digits, not 5 at position 2"
+        );
+        assert_eq!(
+            css_error("#12g"),
+            "Invalid color \"#12g\": hex colors can only contain 0-9 and a-f at position 4"
+        );
+        assert_eq!(
+            css_error("bluish"),
+            "Invalid color \"bluish\": unknown color name \"bluish\" at position 1"
+        );
+        assert_eq!(
+            css_error("rgb(1, 2)"),
+            "Invalid color \"rgb(1, 2)\": rgb() needs 3 components, but found 2 at position 9"
+        );
+        assert_eq!(
+            css_error("rgb(1, x, 3)"),
+            "Invalid color \"rgb(1, x, 3)\": expected a number but found 'x' at position 8"
+        );
+        assert_eq!(
+            css_error("rgb(1 2 3"),
+            "Invalid color \"rgb(1 2 3\": missing ')' to close rgb() at position 10"
+        );
+        assert_eq!(
+            css_error("rgb(1 2 3 4 5)"),
+            "Invalid color \"rgb(1 2 3 4 5)\": too many arguments to rgb() at position 13"
+        );
+        assert_eq!(
+            css_error("hsl(10px 50% 50%)"),
+            "Invalid color \"hsl(10px 50% 50%)\": unexpected unit \"px\" for a hue at position 5"
+        );
+        assert_eq!(
+            css_error("lab(50 0 0)"),
+            "Invalid color \"lab(50 0 0)\": unknown color function \"lab\" at position 1"
+        );
+        assert_eq!(
+            css_error("red blue"),
+            "Invalid color \"red blue\": unexpected text after the color at position 5"
+        );
+    }
+
+    #[test]
+    fn references_are_kept_until_the_card_is_drawn() {
+        let color: Color = toml::Value::String(" @vibrant".into()).try_into().unwrap();
+        assert!(color.uses_swatches(None));
+        assert!(Pixel::try_from(&color).is_err());
+
+        let color: Color = toml::Value::String("red".into()).try_into().unwrap();
+        assert!(!color.uses_swatches(None));
+        assert_eq!(Pixel::try_from(&color).unwrap(), pixel(255, 0, 0, 255));
+    }
+
+    fn palette(colors: &[(&str, &str)]) -> Palette<'static> {
+        Palette {
+            colors: colors
+                .iter()
+                .map(|(name, color)| {
+                    (
+                        name.to_string(),
+                        Color::RgbString(Cow::Owned(color.to_string())),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn resolve_with(color: &str, palette: &Palette) -> Result<Pixel> {
+        let context = ColorContext {
+            swatches: None,
+            palette: Some(palette),
+        };
+        parse_color(color, &context)
+    }
+
+    #[test]
+    fn references() {
+        let palette = palette(&[
+            ("accent", "#3a7bd5"),
+            ("gray", "hsl(0 0% 50%)"),
+            ("link", "$accent"),
+            ("faded", "$link.alpha(0.5)"),
+        ]);
+        let resolve = |color: &str| resolve_with(color, &palette).unwrap();
+
+        assert_eq!(resolve("$accent"), pixel(0x3a, 0x7b, 0xd5, 255));
+        assert_eq!(resolve(" $link "), pixel(0x3a, 0x7b, 0xd5, 255));
+        assert_eq!(resolve("$faded"), pixel(0x3a, 0x7b, 0xd5, 128));
+        assert_eq!(resolve("$accent.alpha(25%)"), pixel(0x3a, 0x7b, 0xd5, 64));
+        assert_eq!(resolve("$gray.lighten(10)"), pixel(154, 154, 154, 255));
+        assert_eq!(resolve("$gray.darken(25%)"), pixel(64, 64, 64, 255));
+        // Modifiers apply in order.
+        assert_eq!(
+            resolve("$gray.alpha(0).lighten(10).alpha( 1 )"),
+            pixel(154, 154, 154, 255)
+        );
+    }
+
+    #[test]
+    fn reference_errors() {
+        let palette = palette(&[
+            ("a", "$b"),
+            ("b", "$c.alpha(0.5)"),
+            ("c", "$a"),
+            ("d", "red"),
+        ]);
+        let error = |color: &str| resolve_with(color, &palette).unwrap_err().to_string();
+
+        assert_eq!(
+            error("$"),
+            "Invalid color \"$\": expected a name at position 2"
+        );
+        assert_eq!(
+            error("$d.alpha"),
+            "Invalid color \"$d.alpha\": expected '(' after \"alpha\" at position 9"
+        );
+        assert_eq!(
+            error("$d.alpha(0.5"),
+            "Invalid color \"$d.alpha(0.5\": expected ')' to close alpha() at position 13"
+        );
+        assert_eq!(
+            error("$d.fade(0.5)"),
+            "Invalid color \"$d.fade(0.5)\": unknown modifier \"fade\", expected alpha, darken or lighten at position 4"
+        );
+        assert_eq!(
+            error("$d red"),
+            "Invalid color \"$d red\": unexpected text after the color at position 4"
+        );
+        assert_eq!(error("$e"), "Unknown palette color \"$e\" in \"$e\"");
+        assert_eq!(
+            error("$a"),
+            "Palette color \"$a\" refers to itself: $a -> $b -> $c -> $a"
+        );
+        assert!(resolve_with("@vibrant", &palette)
+            .unwrap_err()
+            .to_string()
+            .contains("only available after the background is drawn"));
+    }
+
+    #[test]
+    fn long_chains_are_not_cycles() {
+        let names = (0..40).map(|i| format!("c{}", i)).collect::<Vec<_>>();
+        let colors = (0..40)
+            .map(|i| {
+                if i == 0 {
+                    "red".to_string()
+                } else {
+                    format!("$c{}", i - 1)
+                }
+            })
+            .collect::<Vec<_>>();
+        let pairs = names
+            .iter()
+            .zip(&colors)
+            .map(|(n, c)| (n.as_str(), c.as_str()))
+            .collect::<Vec<_>>();
+        let palette = palette(&pairs);
+
+        assert_eq!(
+            resolve_with("$c39", &palette).unwrap(),
+            pixel(255, 0, 0, 255)
+        );
+        assert!(check_reference("$c39", Some(&palette)).is_ok());
+    }
+
+    #[test]
+    fn checking_references() {
+        let palette = palette(&[("a", "$b"), ("b", "$a"), ("c", "@muted.darken(10)")]);
+
+        assert!(check_reference("#fff", Some(&palette)).is_ok());
+        assert!(check_reference("$c", Some(&palette)).is_ok());
+        assert!(check_reference("$a", Some(&palette))
+            .unwrap_err()
+            .to_string()
+            .contains("refers to itself"));
+        assert!(check_reference("$c", None).is_err());
+    }
+}
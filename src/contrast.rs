@@ -0,0 +1,311 @@
+use crate::Pixel;
+use image::RgbaImage;
+
+/// The relative luminance of a color as defined by WCAG 2, from 0 for black to 1 for white. Alpha is
+/// ignored.
+pub(crate) fn relative_luminance(color: Pixel) -> f32 {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    0.2126 * linear(color[0]) + 0.7152 * linear(color[1]) + 0.0722 * linear(color[2])
+}
+
+/// The WCAG contrast ratio between two relative luminances, from 1 to 21.
+pub(crate) fn luminance_contrast(a: f32, b: f32) -> f32 {
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// A level of the WCAG 2 contrast guidelines.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WcagLevel {
+    Aa,
+    Aaa,
+}
+
+impl WcagLevel {
+    /// The lowest contrast ratio allowed for text at this level. Large text is at least 18pt.
+    pub fn min_contrast(self, large_text: bool) -> f32 {
+        match (self, large_text) {
+            (WcagLevel::Aa, true) => 3.0,
+            (WcagLevel::Aa, false) => 4.5,
+            (WcagLevel::Aaa, true) => 4.5,
+            (WcagLevel::Aaa, false) => 7.0,
+        }
+    }
+}
+
+impl std::str::FromStr for WcagLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<WcagLevel> {
+        match s.to_ascii_lowercase().as_str() {
+            "aa" => Ok(WcagLevel::Aa),
+            "aaa" => Ok(WcagLevel::Aaa),
+            _ => Err(anyhow::anyhow!(
+                "Unknown WCAG level {:?}, expected \"aa\" or \"aaa\"",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for WcagLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WcagLevel::Aa => write!(f, "AA"),
+            WcagLevel::Aaa => write!(f, "AAA"),
+        }
+    }
+}
+
+/// The smallest font size, in points, that WCAG counts as large text. Bold text counts as large from
+/// 14pt, but the weight of a font isn't known here, so bold text is held to the normal threshold.
+const LARGE_TEXT_SIZE: f32 = 18.0;
+
+/// The measured contrast between a text run and the pixels behind its glyphs.
+#[derive(Clone, Debug)]
+pub struct TextContrast {
+    /// The index of the block in `OverlayOptions::blocks`.
+    pub block: usize,
+    /// The index of the run in the block's `text`.
+    pub run: usize,
+    pub text: String,
+    /// The size the text was drawn at, in points.
+    pub font_size: f32,
+    /// The lowest contrast ratio of any pixel that the glyphs mostly cover, against the nearest pixel that no
+    /// text covers, on the finished card.
+    pub min: f32,
+    /// The median contrast ratio of the pixels that the glyphs mostly cover. This is what's compared
+    /// against the WCAG thresholds, so that a few stray pixels in a photo don't fail the whole run.
+    pub median: f32,
+}
+
+impl TextContrast {
+    pub fn is_large_text(&self) -> bool {
+        self.font_size >= LARGE_TEXT_SIZE
+    }
+
+    pub fn required_contrast(&self, level: WcagLevel) -> f32 {
+        level.min_contrast(self.is_large_text())
+    }
+
+    pub fn passes(&self, level: WcagLevel) -> bool {
+        self.median >= self.required_contrast(level)
+    }
+}
+
+/// Undo the antialiasing of a pixel that a glyph covers by `coverage` over `backdrop`, to get the color of
+/// the text itself.
+fn unmix(pixel: Pixel, backdrop: Pixel, coverage: f32) -> Pixel {
+    let channel = |c: usize| {
+        let text = (pixel[c] as f32 - backdrop[c] as f32 * (1.0 - coverage)) / coverage;
+        text.round().clamp(0.0, 255.0) as u8
+    };
+    crate::pixel(channel(0), channel(1), channel(2), pixel[3])
+}
+
+/// How far to look for an uncovered pixel next to a text pixel, to use as its backdrop.
+const MAX_BACKDROP_DISTANCE: u32 = 32;
+
+/// Where the text of each block was drawn, so that its contrast can be measured on the finished card,
+/// after everything that's drawn over or around it.
+pub(crate) struct TextCoverage {
+    width: u32,
+    height: u32,
+    /// Whether any glyph touches each pixel of the card.
+    covered: Vec<bool>,
+    blocks: Vec<BlockCoverage>,
+}
+
+struct BlockCoverage {
+    block: usize,
+    texts: Vec<String>,
+    font_size: f32,
+    /// The pixels that each glyph mostly covers, the run that the glyph belongs to, and the coverage.
+    pixels: Vec<(u32, u32, usize, f32)>,
+}
+
+impl TextCoverage {
+    pub fn new(width: u32, height: u32) -> TextCoverage {
+        TextCoverage {
+            width,
+            height,
+            covered: vec![false; (width * height) as usize],
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Start recording the glyphs of a block.
+    pub fn start_block(&mut self, block: usize, texts: &[crate::Text], font_size: f32) {
+        self.blocks.push(BlockCoverage {
+            block,
+            texts: texts.iter().map(|t| t.text.to_string()).collect(),
+            font_size,
+            pixels: Vec::new(),
+        });
+    }
+
+    /// Record that a glyph of the current block's `run` covers the pixel by `coverage`.
+    pub fn add(&mut self, x: u32, y: u32, run: usize, coverage: f32) {
+        if coverage <= 0.0 {
+            return;
+        }
+
+        self.covered[(y * self.width + x) as usize] = true;
+        // Only measure the pixels the glyph mostly covers, since antialiased edges always have low
+        // contrast.
+        if coverage >= 0.5 {
+            if let Some(block) = self.blocks.last_mut() {
+                block.pixels.push((x, y, run, coverage));
+            }
+        }
+    }
+
+    fn is_covered(&self, x: i64, y: i64) -> bool {
+        x < 0
+            || y < 0
+            || x >= self.width as i64
+            || y >= self.height as i64
+            || self.covered[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    /// The nearest pixel to `(x, y)` in the same row or column that no text touches.
+    fn backdrop(&self, image: &RgbaImage, x: u32, y: u32) -> Option<Pixel> {
+        let (x, y) = (x as i64, y as i64);
+        (1..=MAX_BACKDROP_DISTANCE as i64)
+            .flat_map(|d| [(x - d, y), (x + d, y), (x, y - d), (x, y + d)])
+            .find(|&(x, y)| !self.is_covered(x, y))
+            .map(|(x, y)| *image.get_pixel(x as u32, y as u32))
+    }
+
+    /// Measure the contrast of every text run that drew any pixels, on the finished card.
+    pub fn measure(self, image: &RgbaImage) -> Vec<TextContrast> {
+        let mut results = Vec::new();
+        for block in &self.blocks {
+            let mut runs = vec![Vec::new(); block.texts.len()];
+            for &(x, y, run, coverage) in &block.pixels {
+                if let Some(backdrop) = self.backdrop(image, x, y) {
+                    let text = unmix(*image.get_pixel(x, y), backdrop, coverage);
+                    runs[run].push(luminance_contrast(
+                        relative_luminance(text),
+                        relative_luminance(backdrop),
+                    ));
+                }
+            }
+
+            results.extend(
+                runs.into_iter()
+                    .zip(&block.texts)
+                    .enumerate()
+                    .filter(|(_, (samples, _))| !samples.is_empty())
+                    .map(|(run, (mut samples, text))| {
+                        samples.sort_by(|a, b| a.total_cmp(b));
+                        TextContrast {
+                            block: block.block,
+                            run,
+                            text: text.clone(),
+                            font_size: block.font_size,
+                            min: samples[0],
+                            median: samples[samples.len() / 2],
+                        }
+                    }),
+            );
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel;
+
+    #[test]
+    fn wcag_contrast() {
+        let black = relative_luminance(pixel(0, 0, 0, 255));
+        let white = relative_luminance(pixel(255, 255, 255, 0));
+        assert_eq!(black, 0.0);
+        assert!((white - 1.0).abs() < 1e-6);
+        assert!((luminance_contrast(black, white) - 21.0).abs() < 1e-4);
+        assert_eq!(
+            luminance_contrast(white, black),
+            luminance_contrast(black, white)
+        );
+        assert_eq!(luminance_contrast(0.3, 0.3), 1.0);
+
+        // Green counts for much more than blue.
+        let green = relative_luminance(pixel(0, 255, 0, 255));
+        let blue = relative_luminance(pixel(0, 0, 255, 255));
+        assert!(green > 0.7 && blue < 0.1);
+    }
+
+    #[test]
+    fn wcag_levels() {
+        assert_eq!("AA".parse::<WcagLevel>().unwrap(), WcagLevel::Aa);
+        assert_eq!("aaa".parse::<WcagLevel>().unwrap(), WcagLevel::Aaa);
+        assert!("a".parse::<WcagLevel>().is_err());
+        assert_eq!(WcagLevel::Aa.min_contrast(false), 4.5);
+        assert_eq!(WcagLevel::Aaa.min_contrast(true), 4.5);
+    }
+
+    #[test]
+    fn antialiasing_is_undone() {
+        let backdrop = pixel(200, 100, 0, 255);
+        let text = pixel(0, 50, 250, 255);
+        let mixed = crate::blend(backdrop, text, 0.6);
+        let unmixed = unmix(mixed, backdrop, 0.6);
+        for c in 0..3 {
+            assert!((unmixed[c] as i32 - text[c] as i32).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn text_is_measured_against_the_pixels_around_it() {
+        let texts = vec![
+            crate::Text {
+                font: "font".into(),
+                text: "dark".into(),
+                color: None,
+            },
+            crate::Text {
+                font: "font".into(),
+                text: "light".into(),
+                color: None,
+            },
+        ];
+
+        // Dark text on the left half, light text on the right half, on a white card.
+        let mut image = RgbaImage::from_pixel(20, 10, pixel(255, 255, 255, 255));
+        let mut coverage = TextCoverage::new(20, 10);
+        coverage.start_block(3, &texts, 24.0);
+        for y in 4..6 {
+            for x in 2..8 {
+                image.put_pixel(x, y, pixel(0, 0, 0, 255));
+                coverage.add(x, y, 0, 1.0);
+            }
+            for x in 12..18 {
+                image.put_pixel(x, y, pixel(220, 220, 220, 255));
+                coverage.add(x, y, 1, 1.0);
+            }
+            // Faint edges are left out.
+            image.put_pixel(8, y, pixel(250, 250, 250, 255));
+            coverage.add(8, y, 0, 0.1);
+        }
+
+        let results = coverage.measure(&image);
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].block, results[0].run), (3, 0));
+        assert_eq!(results[0].text, "dark");
+        assert!((results[0].median - 21.0).abs() < 0.01);
+        assert!(results[0].passes(WcagLevel::Aaa));
+        assert!(results[0].is_large_text());
+        assert!(results[1].median < 1.5);
+        assert!(!results[1].passes(WcagLevel::Aa));
+    }
+}
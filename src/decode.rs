@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use qcms::{DataType, Intent, Profile, Transform};
+use std::path::Path;
+
+/// Decode a raster image file. The image is rotated and flipped according to its EXIF orientation, and
+/// if it has an embedded ICC profile, its colors are converted to sRGB, which is what the card is drawn
+/// in and what the output is assumed to be.
+pub(crate) fn decode_image(path: &Path) -> Result<DynamicImage> {
+    let mut decoder = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .with_context(|| format!("Opening image {:?}", path))?
+        .into_decoder()
+        .with_context(|| format!("Opening image {:?}", path))?;
+
+    // Broken metadata shouldn't stop the image from loading, so it's ignored rather than reported.
+    let orientation = decoder.orientation().ok();
+    let icc_profile = decoder.icc_profile().ok().flatten();
+    let mut image = DynamicImage::from_decoder(decoder)
+        .with_context(|| format!("Decoding image {:?}", path))?;
+
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+
+    if let Some(icc_profile) = icc_profile {
+        if let Some(converted) = convert_to_srgb(&image, &icc_profile) {
+            image = DynamicImage::ImageRgba8(converted);
+        }
+    }
+
+    Ok(image)
+}
+
+/// Convert `image` from the color space described by `icc_profile` to sRGB. Returns `None` if the
+/// profile can't be used, in which case the image is left as it is.
+fn convert_to_srgb(image: &DynamicImage, icc_profile: &[u8]) -> Option<RgbaImage> {
+    let input = Profile::new_from_slice(icc_profile, false)?;
+    let mut srgb = Profile::new_sRGB();
+    srgb.precache_output_transform();
+
+    if let Some(transform) = Transform::new(&input, &srgb, DataType::RGBA8, Intent::Perceptual) {
+        let mut rgba = image.to_rgba8();
+        transform.apply(&mut rgba);
+        return Some(rgba);
+    }
+
+    // Grayscale profiles can only be applied to grayscale pixels.
+    let transform = Transform::new_to(
+        &input,
+        &srgb,
+        DataType::GrayA8,
+        DataType::RGBA8,
+        Intent::Perceptual,
+    )?;
+    let gray = image.to_luma_alpha8();
+    let mut rgba = RgbaImage::new(gray.width(), gray.height());
+    transform.convert(&gray, &mut rgba);
+    Some(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::png::PngEncoder, ImageEncoder, Rgba};
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    /// Write a 2x1 PNG, red on the left and blue on the right, with the given EXIF orientation and ICC
+    /// profile, and decode it.
+    fn decode(name: &str, orientation: Option<u16>, icc_profile: Option<&[u8]>) -> DynamicImage {
+        let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { RED } else { BLUE });
+        let mut png = Vec::new();
+        let mut encoder = PngEncoder::new(&mut png);
+        if let Some(orientation) = orientation {
+            // A big-endian TIFF header and one IFD with only the orientation tag.
+            let mut exif = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+            exif.extend_from_slice(&orientation.to_be_bytes());
+            exif.extend_from_slice(&[0; 6]);
+            encoder.set_exif_metadata(exif).unwrap();
+        }
+        if let Some(icc_profile) = icc_profile {
+            encoder.set_icc_profile(icc_profile.to_vec()).unwrap();
+        }
+        encoder
+            .write_image(image.as_raw(), 2, 1, image::ExtendedColorType::Rgba8)
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "create-social-card-{}-{}.png",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, png).unwrap();
+        let image = decode_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        image.unwrap()
+    }
+
+    #[test]
+    fn exif_orientation_is_applied() {
+        let image = decode("upright", Some(1), None).to_rgba8();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(*image.get_pixel(0, 0), RED);
+
+        // 6 means the image has to be turned a quarter clockwise to be upright.
+        let image = decode("rotated", Some(6), None).to_rgba8();
+        assert_eq!(image.dimensions(), (1, 2));
+        assert_eq!(*image.get_pixel(0, 0), RED);
+        assert_eq!(*image.get_pixel(0, 1), BLUE);
+
+        // 2 means it's mirrored.
+        let image = decode("mirrored", Some(2), None).to_rgba8();
+        assert_eq!(*image.get_pixel(0, 0), BLUE);
+    }
+
+    #[test]
+    fn broken_metadata_is_ignored() {
+        let image = decode("broken-orientation", Some(42), None).to_rgba8();
+        assert_eq!(*image.get_pixel(0, 0), RED);
+
+        let image = decode("broken-profile", None, Some(b"not a profile")).to_rgba8();
+        assert_eq!(*image.get_pixel(0, 0), RED);
+        assert_eq!(*image.get_pixel(1, 0), BLUE);
+    }
+
+    #[test]
+    fn missing_images_are_errors() {
+        assert!(decode_image(Path::new("does-not-exist.png")).is_err());
+    }
+}
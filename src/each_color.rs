@@ -0,0 +1,231 @@
+use crate::auto_color::{AutoColor, Scrim, TextColor};
+use crate::backdrop::Backdrop;
+use crate::background::{Background, GeneratedBackground};
+use crate::border::{BlockBorder, BorderSide};
+use crate::filter::Filter;
+use crate::fit::BackgroundFit;
+use crate::generator::Generator;
+use crate::image_block::ImageBlock;
+use crate::paint::{ColorStop, Paint};
+use crate::shadow::BoxShadow;
+use crate::shape::{ShapeBlock, Stroke};
+use crate::{Block, Color, OverlayOptions, Palette, Shadow, Text};
+
+/// Something in a card that holds colors, so that the colors can be checked before anything is drawn.
+pub(crate) trait EachColor {
+    /// Call `f` with every color in `self`.
+    fn each_color(&self, f: &mut dyn FnMut(&Color));
+}
+
+impl<'a> EachColor for Color<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        f(self)
+    }
+}
+
+impl<T: EachColor> EachColor for Option<T> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        if let Some(v) = self {
+            v.each_color(f);
+        }
+    }
+}
+
+impl<T: EachColor + ?Sized> EachColor for &T {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        (**self).each_color(f);
+    }
+}
+
+impl<T: EachColor> EachColor for [T] {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        for v in self {
+            v.each_color(f);
+        }
+    }
+}
+
+impl<T: EachColor> EachColor for Vec<T> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.as_slice().each_color(f);
+    }
+}
+
+impl<'a> EachColor for Paint<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        match self {
+            Paint::Color(color) => f(color),
+            Paint::Gradient(gradient) => gradient.stops.each_color(f),
+        }
+    }
+}
+
+impl<'a> EachColor for ColorStop<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        match self {
+            ColorStop::Color(color) | ColorStop::Positioned { color, .. } => f(color),
+        }
+    }
+}
+
+impl<'a> EachColor for BoxShadow<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+    }
+}
+
+impl<'a> EachColor for BlockBorder<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+        for side in [&self.top, &self.right, &self.bottom, &self.left] {
+            side.each_color(f);
+        }
+        self.shadow.each_color(f);
+    }
+}
+
+impl<'a> EachColor for BorderSide<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Backdrop<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.tint.each_color(f);
+    }
+}
+
+impl<'a> EachColor for TextColor<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        if let TextColor::Color(color) = self {
+            f(color);
+        }
+    }
+}
+
+impl<'a> EachColor for AutoColor<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.candidates.each_color(f);
+        self.scrim.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Scrim<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        f(&self.color);
+    }
+}
+
+impl<'a> EachColor for Text<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Shadow<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Block<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.text.each_color(f);
+        self.shadow.each_color(f);
+        self.background.each_color(f);
+        self.border.each_color(f);
+        self.backdrop.each_color(f);
+        self.color.each_color(f);
+        self.auto_color.each_color(f);
+    }
+}
+
+impl<'a> EachColor for ImageBlock<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+        self.border.each_color(f);
+    }
+}
+
+impl<'a> EachColor for ShapeBlock<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.fill.each_color(f);
+        self.stroke.each_color(f);
+        self.shadow.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Stroke<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.color.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Filter<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        match self {
+            Filter::Duotone {
+                shadows,
+                highlights,
+            } => {
+                f(shadows);
+                f(highlights);
+            }
+            Filter::Overlay { color, .. } => color.each_color(f),
+            Filter::Vignette { color, .. } => f(color),
+            Filter::Brightness { .. }
+            | Filter::Contrast { .. }
+            | Filter::Saturation { .. }
+            | Filter::Blur { .. }
+            | Filter::Grayscale { .. } => {}
+        }
+    }
+}
+
+impl<'a> EachColor for Background<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        if let Background::Generated(generated) = self {
+            generated.each_color(f);
+        }
+    }
+}
+
+impl<'a> EachColor for GeneratedBackground<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.fill.each_color(f);
+        self.generator.each_color(f);
+    }
+}
+
+impl<'a> EachColor for Generator<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.palette.each_color(f);
+    }
+}
+
+impl<'a> EachColor for BackgroundFit<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        f(&self.letterbox);
+    }
+}
+
+impl<'a> EachColor for Palette<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        for color in self.colors.values() {
+            f(color);
+        }
+    }
+}
+
+impl<'a> EachColor for OverlayOptions<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.palette.each_color(f);
+        self.background.each_color(f);
+        self.background_fit.each_color(f);
+        self.background_filters.each_color(f);
+        self.shapes.each_color(f);
+        self.images.each_color(f);
+        self.blocks.each_color(f);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::blend_mode::{self, BlendMode};
+use crate::geometry::RoundedRect;
+use crate::paint::Paint;
+use crate::{blend, pixel, Color, ColorContext, Rect};
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use serde_derive::Deserialize;
+
+/// An adjustment applied to the whole background before anything is drawn on it. Filters are applied in
+/// the order they're listed.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Filter<'a> {
+    /// Scale the brightness. Values below 1 darken the image.
+    Brightness { amount: f32 },
+    /// Scale the contrast around middle gray. Values below 1 reduce the contrast.
+    Contrast { amount: f32 },
+    /// Scale the color saturation. 0 is grayscale and values above 1 make colors more vivid.
+    Saturation { amount: f32 },
+    /// Gaussian blur with the given sigma.
+    Blur { sigma: f32 },
+    /// Remove the color. An `amount` below 1 only removes some of it.
+    Grayscale {
+        #[serde(default = "crate::one")]
+        amount: f32,
+    },
+    /// Map the image's brightness onto a gradient between two colors.
+    Duotone {
+        shadows: Color<'a>,
+        highlights: Color<'a>,
+    },
+    /// Draw a color or gradient over the image.
+    Overlay {
+        color: Paint<'a>,
+        #[serde(default = "crate::one")]
+        opacity: f32,
+        #[serde(default)]
+        blend_mode: BlendMode,
+    },
+    /// Darken the edges of the image.
+    Vignette {
+        /// How opaque the vignette color is in the corners.
+        #[serde(default = "default_vignette_strength")]
+        strength: f32,
+        /// Where the vignette starts, as a fraction of the distance from the center to the edges.
+        #[serde(default = "default_vignette_radius")]
+        radius: f32,
+        #[serde(default)]
+        color: Color<'a>,
+    },
+}
+
+fn default_vignette_strength() -> f32 {
+    0.5
+}
+
+fn default_vignette_radius() -> f32 {
+    0.5
+}
+
+fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Run `f` on the color channels of every pixel, as values from 0 to 255.
+fn map_channels(image: &mut RgbaImage, f: impl Fn([f32; 3]) -> [f32; 3]) {
+    for p in image.pixels_mut() {
+        let [r, g, b] = f([p[0] as f32, p[1] as f32, p[2] as f32]);
+        let channel = |c: f32| c.round().clamp(0.0, 255.0) as u8;
+        *p = pixel(channel(r), channel(g), channel(b), p[3]);
+    }
+}
+
+fn saturate(image: &mut RgbaImage, amount: f32) {
+    map_channels(image, |[r, g, b]| {
+        let gray = luma(r, g, b);
+        [
+            gray + (r - gray) * amount,
+            gray + (g - gray) * amount,
+            gray + (b - gray) * amount,
+        ]
+    });
+}
+
+impl<'a> Filter<'a> {
+    fn apply(&self, image: &mut RgbaImage, colors: &ColorContext) -> Result<()> {
+        match self {
+            Filter::Brightness { amount } => {
+                map_channels(image, |[r, g, b]| [r * amount, g * amount, b * amount])
+            }
+            Filter::Contrast { amount } => map_channels(image, |c| {
+                let adjust = |v: f32| (v - 127.5) * amount + 127.5;
+                [adjust(c[0]), adjust(c[1]), adjust(c[2])]
+            }),
+            Filter::Saturation { amount } => saturate(image, *amount),
+            Filter::Grayscale { amount } => saturate(image, 1.0 - amount.clamp(0.0, 1.0)),
+            Filter::Blur { sigma } => {
+                if *sigma < 0.0 {
+                    return Err(anyhow!("Blur sigma must not be negative"));
+                } else if *sigma > 0.0 {
+                    *image = image::imageops::blur(image, *sigma);
+                }
+            }
+            Filter::Duotone {
+                shadows,
+                highlights,
+            } => {
+                let dark = shadows.resolve(colors)?;
+                let light = highlights.resolve(colors)?;
+                map_channels(image, |[r, g, b]| {
+                    let t = luma(r, g, b) / 255.0;
+                    let mix = |i: usize| dark[i] as f32 + (light[i] as f32 - dark[i] as f32) * t;
+                    [mix(0), mix(1), mix(2)]
+                });
+            }
+            Filter::Overlay {
+                color,
+                opacity,
+                blend_mode,
+            } => {
+                let (width, height) = image.dimensions();
+                let area = RoundedRect::new(
+                    &Rect {
+                        left: 0,
+                        top: 0,
+                        right: width - 1,
+                        bottom: height - 1,
+                    },
+                    [0.0; 4],
+                );
+                let painter = color.painter(&area, colors)?;
+                for (x, y, p) in image.enumerate_pixels_mut() {
+                    *p = blend_mode::blend_pixel(*p, painter.at(x, y), *blend_mode, *opacity);
+                }
+            }
+            Filter::Vignette {
+                strength,
+                radius,
+                color,
+            } => {
+                // The distance from the center to the corners, where the vignette is strongest.
+                let corner = 2f32.sqrt();
+                if *radius >= corner {
+                    return Err(anyhow!(
+                        "Vignette radius must be less than {:.3}, the distance to the corners",
+                        corner
+                    ));
+                }
+
+                let color = color.resolve(colors)?;
+                let (width, height) = image.dimensions();
+                let (half_width, half_height) = (width as f32 / 2.0, height as f32 / 2.0);
+                for (x, y, p) in image.enumerate_pixels_mut() {
+                    let dx = (x as f32 + 0.5 - half_width) / half_width;
+                    let dy = (y as f32 + 0.5 - half_height) / half_height;
+                    // Ease in from the radius to the corners.
+                    let t = ((dx.hypot(dy) - radius) / (corner - radius)).clamp(0.0, 1.0);
+                    let amount = t * t * (3.0 - 2.0 * t) * strength;
+                    *p = blend(*p, color, amount);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply each of the filters to the image, in order.
+pub(crate) fn apply_filters(
+    image: &mut RgbaImage,
+    filters: &[Filter],
+    colors: &ColorContext,
+) -> Result<()> {
+    for filter in filters {
+        filter.apply(image, colors)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(config: &str) -> RgbaImage {
+        let filters: Vec<Filter> = toml::from_str::<toml::Value>(config).unwrap()["filters"]
+            .clone()
+            .try_into()
+            .unwrap();
+        let mut image = RgbaImage::from_fn(10, 10, |x, _| {
+            if x < 5 {
+                pixel(200, 100, 50, 255)
+            } else {
+                pixel(20, 40, 60, 128)
+            }
+        });
+        apply_filters(&mut image, &filters, &ColorContext::default()).unwrap();
+        image
+    }
+
+    #[test]
+    fn color_adjustments() {
+        let image = filter(r#"filters = [{ type = "brightness", amount = 0.5 }]"#);
+        assert_eq!(*image.get_pixel(0, 0), pixel(100, 50, 25, 255));
+        // Alpha is left alone.
+        assert_eq!(*image.get_pixel(9, 0), pixel(10, 20, 30, 128));
+
+        let image = filter(r#"filters = [{ type = "contrast", amount = 0 }]"#);
+        assert_eq!(*image.get_pixel(0, 0), pixel(128, 128, 128, 255));
+
+        let image = filter(r#"filters = [{ type = "grayscale" }]"#);
+        let p = image.get_pixel(0, 0);
+        assert!(p[0] == p[1] && p[1] == p[2], "{:?}", p);
+
+        let image = filter(r#"filters = [{ type = "saturation", amount = 1 }]"#);
+        assert_eq!(*image.get_pixel(0, 0), pixel(200, 100, 50, 255));
+    }
+
+    #[test]
+    fn filters_apply_in_order() {
+        let image = filter(
+            r#"filters = [
+                { type = "brightness", amount = 0 },
+                { type = "overlay", color = "ff0000", opacity = 0.5 },
+            ]"#,
+        );
+        assert_eq!(*image.get_pixel(0, 0), pixel(128, 0, 0, 255));
+    }
+
+    #[test]
+    fn duotone_maps_brightness_to_colors() {
+        let image = filter(
+            r#"filters = [{ type = "duotone", shadows = "0000ff", highlights = "ffff00" }]"#,
+        );
+        assert_eq!(*image.get_pixel(0, 0), pixel(118, 118, 137, 255));
+        assert_eq!(*image.get_pixel(9, 0), pixel(37, 37, 218, 128));
+    }
+
+    #[test]
+    fn vignettes_darken_the_corners() {
+        let image = filter(r#"filters = [{ type = "vignette", strength = 1, radius = 0.2 }]"#);
+        assert!(image.get_pixel(0, 0)[0] < 20);
+        assert_eq!(*image.get_pixel(4, 4), pixel(200, 100, 50, 255));
+
+        let mut image = RgbaImage::new(4, 4);
+        let err = apply_filters(
+            &mut image,
+            &[Filter::Vignette {
+                strength: 1.0,
+                radius: 1.5,
+                color: Color::default(),
+            }],
+            &ColorContext::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("radius"), "{}", err);
+    }
+
+    #[test]
+    fn blur() {
+        let image = filter(r#"filters = [{ type = "blur", sigma = 2 }]"#);
+        let middle = image.get_pixel(5, 5);
+        assert!(middle[0] > 20 && middle[0] < 200, "{:?}", middle);
+
+        let mut image = RgbaImage::new(4, 4);
+        assert!(apply_filters(
+            &mut image,
+            &[Filter::Blur { sigma: -1.0 }],
+            &ColorContext::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::smart_crop::find_focal_point;
+use crate::{Color, ColorContext};
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, RgbaImage};
+use serde_derive::Deserialize;
+
+/// How the background is sized to the card when the card has an explicit size.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BackgroundFit<'a> {
+    #[serde(default)]
+    pub mode: FitMode,
+    /// The point of the background to keep in view when it's cropped, as fractions of its width and
+    /// height. Defaults to the center, or in `smart` mode, to the point that the smart crop chooses.
+    pub focal_point: Option<(f32, f32)>,
+    /// The color around the background when it doesn't fill the card in `contain` mode. Defaults to black.
+    #[serde(default)]
+    pub letterbox: Color<'a>,
+    /// The filter used when scaling the background.
+    #[serde(default)]
+    pub filter: ResizeFilter,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FitMode {
+    /// Scale the background to cover the card, cropping around the focal point.
+    #[default]
+    Cover,
+    /// Like `cover`, but choose the focal point by looking for the most detailed part of the image.
+    Smart,
+    /// Scale the background to fit inside the card, and fill the rest with the letterbox color.
+    Contain,
+    /// Stretch the background to the size of the card.
+    Stretch,
+    /// Repeat the background at its own size.
+    Tile,
+}
+
+/// A resampling filter, from fastest to highest quality.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResizeFilter {
+    Nearest,
+    #[serde(alias = "bilinear")]
+    Triangle,
+    #[serde(alias = "bicubic")]
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> FilterType {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+fn resize(image: &RgbaImage, width: u32, height: u32, filter: ResizeFilter) -> RgbaImage {
+    if image.dimensions() == (width, height) {
+        image.clone()
+    } else {
+        image::imageops::resize(image, width, height, filter.into())
+    }
+}
+
+/// The offset of a crop window of size `window` within `length` that centers `focus` as closely as
+/// possible.
+fn crop_offset(length: u32, window: u32, focus: f32) -> u32 {
+    let max = length.saturating_sub(window) as f32;
+    (focus * length as f32 - window as f32 / 2.0)
+        .round()
+        .clamp(0.0, max) as u32
+}
+
+/// The part of the background image that ends up on the card, in the background's pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CropWindow {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Scale, crop or tile `image` to exactly `width` by `height`. When the background is cropped, also
+/// return the part of it that was kept.
+pub(crate) fn fit_background(
+    image: &RgbaImage,
+    width: u32,
+    height: u32,
+    fit: &BackgroundFit,
+    colors: &ColorContext,
+) -> Result<(RgbaImage, Option<CropWindow>)> {
+    if width == 0 || height == 0 {
+        return Err(anyhow!("The card width and height must be positive"));
+    }
+
+    let (image_width, image_height) = image.dimensions();
+    if image_width == 0 || image_height == 0 {
+        return Err(anyhow!("The background image is empty"));
+    }
+
+    if image.dimensions() == (width, height) && fit.mode != FitMode::Tile {
+        return Ok((image.clone(), None));
+    }
+
+    let x_scale = width as f32 / image_width as f32;
+    let y_scale = height as f32 / image_height as f32;
+    let scaled_size = |scale: f32| {
+        (
+            ((image_width as f32 * scale).round() as u32).max(1),
+            ((image_height as f32 * scale).round() as u32).max(1),
+        )
+    };
+
+    let mut window = None;
+    let output = match fit.mode {
+        FitMode::Stretch => resize(image, width, height, fit.filter),
+        FitMode::Cover | FitMode::Smart => {
+            let scale = x_scale.max(y_scale);
+            let (scaled_width, scaled_height) = scaled_size(scale);
+            let scaled_width = scaled_width.max(width);
+            let scaled_height = scaled_height.max(height);
+            let scaled = resize(image, scaled_width, scaled_height, fit.filter);
+
+            let (fx, fy) = match (fit.focal_point, fit.mode) {
+                (Some(focal_point), _) => focal_point,
+                (None, FitMode::Smart) => find_focal_point(image, width, height),
+                (None, _) => (0.5, 0.5),
+            };
+            let left = crop_offset(scaled_width, width, fx);
+            let top = crop_offset(scaled_height, height, fy);
+
+            let to_source = |v: u32| (v as f32 / scale).round() as u32;
+            window = Some(CropWindow {
+                left: to_source(left),
+                top: to_source(top),
+                width: to_source(width).min(image_width),
+                height: to_source(height).min(image_height),
+            });
+            image::imageops::crop_imm(&scaled, left, top, width, height).to_image()
+        }
+        FitMode::Contain => {
+            let (scaled_width, scaled_height) = scaled_size(x_scale.min(y_scale));
+            let scaled = resize(
+                image,
+                scaled_width.min(width),
+                scaled_height.min(height),
+                fit.filter,
+            );
+
+            let letterbox = fit.letterbox.resolve(colors)?;
+            let mut output = RgbaImage::from_pixel(width, height, letterbox);
+            let left = (width - scaled.width()) / 2;
+            let top = (height - scaled.height()) / 2;
+            image::imageops::overlay(&mut output, &scaled, left as i64, top as i64);
+            output
+        }
+        FitMode::Tile => RgbaImage::from_fn(width, height, |x, y| {
+            *image.get_pixel(x % image_width, y % image_height)
+        }),
+    };
+
+    Ok((output, window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pixel, Pixel};
+
+    const RED: Pixel = pixel(255, 0, 0, 255);
+    const BLUE: Pixel = pixel(0, 0, 255, 255);
+
+    /// A 20x10 image with a red left half and a blue right half.
+    fn halves() -> RgbaImage {
+        RgbaImage::from_fn(20, 10, |x, _| if x < 10 { RED } else { BLUE })
+    }
+
+    fn fit_with_window(config: &str, width: u32, height: u32) -> (RgbaImage, Option<CropWindow>) {
+        let mut fit: BackgroundFit = toml::from_str(config).unwrap();
+        fit.filter = ResizeFilter::Nearest;
+        fit_background(&halves(), width, height, &fit, &ColorContext::default()).unwrap()
+    }
+
+    fn fit(config: &str, width: u32, height: u32) -> RgbaImage {
+        fit_with_window(config, width, height).0
+    }
+
+    #[test]
+    fn cover_crops_around_the_focal_point() {
+        let image = fit("", 10, 10);
+        assert_eq!(image.dimensions(), (10, 10));
+        assert_eq!(*image.get_pixel(0, 5), RED);
+        assert_eq!(*image.get_pixel(9, 5), BLUE);
+
+        let image = fit("focal_point = [0, 0.5]", 10, 10);
+        assert!(image.pixels().all(|p| *p == RED));
+        let image = fit("focal_point = [0.9, 0.5]", 10, 10);
+        assert!(image.pixels().all(|p| *p == BLUE));
+
+        // A taller card scales the background up to cover it.
+        let image = fit("", 10, 20);
+        assert_eq!(image.dimensions(), (10, 20));
+        assert_eq!(*image.get_pixel(0, 0), RED);
+    }
+
+    #[test]
+    fn crop_windows_are_in_background_pixels() {
+        let (_, window) = fit_with_window("focal_point = [1, 0.5]", 20, 20);
+        assert_eq!(
+            window,
+            Some(CropWindow {
+                left: 10,
+                top: 0,
+                width: 10,
+                height: 10
+            })
+        );
+        assert_eq!(fit_with_window("mode = \"stretch\"", 5, 5).1, None);
+        assert_eq!(fit_with_window("", 20, 10).1, None);
+    }
+
+    #[test]
+    fn contain_letterboxes() {
+        let image = fit("mode = \"contain\"\nletterbox = [0, 255, 0]", 20, 20);
+        assert_eq!(*image.get_pixel(10, 0), pixel(0, 255, 0, 255));
+        assert_eq!(*image.get_pixel(10, 19), pixel(0, 255, 0, 255));
+        assert_eq!(*image.get_pixel(0, 10), RED);
+        assert_eq!(*image.get_pixel(19, 10), BLUE);
+    }
+
+    #[test]
+    fn stretch_and_tile() {
+        let image = fit("mode = \"stretch\"", 4, 30);
+        assert_eq!(image.dimensions(), (4, 30));
+        assert_eq!(*image.get_pixel(1, 29), RED);
+        assert_eq!(*image.get_pixel(2, 0), BLUE);
+
+        let image = fit("mode = \"tile\"", 45, 10);
+        assert_eq!(*image.get_pixel(20, 0), RED);
+        assert_eq!(*image.get_pixel(35, 0), BLUE);
+        assert_eq!(*image.get_pixel(44, 0), RED);
+    }
+
+    #[test]
+    fn sizes_must_be_positive() {
+        let fit = BackgroundFit::default();
+        assert!(fit_background(&halves(), 0, 10, &fit, &ColorContext::default()).is_err());
+        assert!(fit_background(
+            &RgbaImage::new(0, 0),
+            10,
+            10,
+            &fit,
+            &ColorContext::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,559 @@
+use crate::geometry::Path;
+use crate::{blend, pixel, Color, ColorContext, Pixel};
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use serde_derive::Deserialize;
+use std::f32::consts::{PI, TAU};
+
+/// A background pattern generated from a seed. The same seed, palette and size always produce exactly
+/// the same image, so a post's title or slug can be used to give each card its own stable background.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Generator<'a> {
+    #[serde(flatten)]
+    pub kind: GeneratorKind,
+    /// Any text, such as the title or slug of a post.
+    pub seed: String,
+    /// The colors to use. Generators that blend between colors go from the first to the last.
+    pub palette: Vec<Color<'a>>,
+}
+
+/// The style of a generated pattern. Settings that are left out are chosen from the seed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GeneratorKind {
+    /// A grid of square cells, each with a circle, quarter circle or triangle.
+    Grid { cell_size: Option<u32> },
+    /// Large soft spots of color over the first color in the palette.
+    Blobs { count: Option<u32> },
+    /// Layered bands of waves, from the top of the image to the bottom.
+    Waves { count: Option<u32> },
+    /// A mesh of irregular triangles shaded along a gradient.
+    Triangulation { cell_size: Option<u32> },
+    /// Smooth noise mapped onto the palette.
+    NoiseGradient {
+        /// Roughly how many features the noise has across the width of the image.
+        scale: Option<f32>,
+    },
+}
+
+/// A small deterministic random number generator (SplitMix64), so that the random numbers a seed produces
+/// don't depend on the platform or on the version of an external crate. The drawing itself uses floating
+/// point math from the platform, so images may still differ very slightly between platforms.
+struct Rng(u64);
+
+impl Rng {
+    fn from_seed(seed: &str) -> Rng {
+        Rng(hash_seed(seed))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix64(self.0)
+    }
+
+    /// A number in the range [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Hash a seed string with FNV-1a.
+pub(crate) fn hash_seed(seed: &str) -> u64 {
+    seed.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Scramble the bits of a number, as the final step of SplitMix64.
+pub(crate) fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+struct Palette(Vec<Pixel>);
+
+impl Palette {
+    fn pick(&self, rng: &mut Rng) -> Pixel {
+        self.0[rng.below(self.0.len())]
+    }
+
+    /// Pick a color other than `other`, if the palette has any.
+    fn pick_other(&self, rng: &mut Rng, other: Pixel) -> Pixel {
+        if self.0.iter().all(|c| *c == other) {
+            return other;
+        }
+
+        loop {
+            let p = self.pick(rng);
+            if p != other {
+                return p;
+            }
+        }
+    }
+
+    /// Interpolate along the palette, where 0 is the first color and 1 is the last.
+    fn at(&self, t: f32) -> Pixel {
+        let last = self.0.len() - 1;
+        if last == 0 {
+            return self.0[0];
+        }
+
+        let position = t.clamp(0.0, 1.0) * last as f32;
+        let i = (position.floor() as usize).min(last - 1);
+
+        let f = position - i as f32;
+        let (a, b) = (self.0[i], self.0[i + 1]);
+        let channel = |c: usize| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * f).round() as u8;
+        pixel(channel(0), channel(1), channel(2), channel(3))
+    }
+}
+
+fn coverage(distance: f32) -> f32 {
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+impl<'a> Generator<'a> {
+    /// Draw the pattern over `image`.
+    pub(crate) fn draw(&self, image: &mut RgbaImage, colors: &ColorContext) -> Result<()> {
+        if self.palette.is_empty() {
+            return Err(anyhow!(
+                "Generated backgrounds need at least one palette color"
+            ));
+        }
+
+        let palette = Palette(
+            self.palette
+                .iter()
+                .map(|c| c.resolve(colors))
+                .collect::<Result<Vec<_>>>()?,
+        );
+        let mut rng = Rng::from_seed(&self.seed);
+
+        match self.kind {
+            GeneratorKind::Grid { cell_size } => draw_grid(image, &palette, &mut rng, cell_size),
+            GeneratorKind::Blobs { count } => draw_blobs(image, &palette, &mut rng, count),
+            GeneratorKind::Waves { count } => draw_waves(image, &palette, &mut rng, count),
+            GeneratorKind::Triangulation { cell_size } => {
+                draw_triangulation(image, &palette, &mut rng, cell_size)
+            }
+            GeneratorKind::NoiseGradient { scale } => {
+                draw_noise_gradient(image, &palette, &mut rng, scale)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_cell_size(image: &RgbaImage, rng: &mut Rng) -> f32 {
+    (image.width().min(image.height()) as f32 / rng.range(4.0, 7.0))
+        .round()
+        .max(1.0)
+}
+
+enum CellShape {
+    Empty,
+    Circle,
+    /// A quarter circle centered on one of the cell's corners.
+    QuarterCircle(f32, f32),
+    /// Half of the cell, split along a diagonal.
+    Triangle {
+        anti_diagonal: bool,
+        flip: bool,
+    },
+}
+
+struct Cell {
+    background: Pixel,
+    foreground: Pixel,
+    shape: CellShape,
+}
+
+fn draw_grid(image: &mut RgbaImage, palette: &Palette, rng: &mut Rng, cell_size: Option<u32>) {
+    let size = cell_size
+        .map(|s| s.max(1) as f32)
+        .unwrap_or_else(|| default_cell_size(image, rng));
+    let columns = (image.width() as f32 / size).ceil() as usize;
+    let rows = (image.height() as f32 / size).ceil() as usize;
+
+    let cells = (0..columns * rows)
+        .map(|_| {
+            let background = palette.pick(rng);
+            let foreground = palette.pick_other(rng, background);
+            let shape = match rng.below(4) {
+                0 => CellShape::Empty,
+                1 => CellShape::Circle,
+                2 => {
+                    CellShape::QuarterCircle(rng.below(2) as f32 * size, rng.below(2) as f32 * size)
+                }
+                _ => CellShape::Triangle {
+                    anti_diagonal: rng.below(2) == 1,
+                    flip: rng.below(2) == 1,
+                },
+            };
+
+            Cell {
+                background,
+                foreground,
+                shape,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        let (column, row) = ((x as f32 / size) as usize, (y as f32 / size) as usize);
+        let cell = &cells[row * columns + column];
+        let lx = x as f32 + 0.5 - column as f32 * size;
+        let ly = y as f32 + 0.5 - row as f32 * size;
+
+        let distance = match cell.shape {
+            CellShape::Empty => f32::INFINITY,
+            CellShape::Circle => {
+                let half = size / 2.0;
+                (lx - half).hypot(ly - half) - size * 0.4
+            }
+            CellShape::QuarterCircle(cx, cy) => (lx - cx).hypot(ly - cy) - size,
+            CellShape::Triangle {
+                anti_diagonal,
+                flip,
+            } => {
+                let d = if anti_diagonal {
+                    (lx + ly - size) / 2f32.sqrt()
+                } else {
+                    (lx - ly) / 2f32.sqrt()
+                };
+                if flip {
+                    -d
+                } else {
+                    d
+                }
+            }
+        };
+
+        *p = blend(*p, cell.background, 1.0);
+        *p = blend(*p, cell.foreground, coverage(distance));
+    }
+}
+
+fn draw_blobs(image: &mut RgbaImage, palette: &Palette, rng: &mut Rng, count: Option<u32>) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let count = count.unwrap_or_else(|| 5 + rng.below(4) as u32);
+    let base = palette.0[0];
+
+    let blobs = (0..count)
+        .map(|_| {
+            let x = rng.range(-0.1, 1.1) * width;
+            let y = rng.range(-0.1, 1.1) * height;
+            let radius = rng.range(0.25, 0.55) * width.max(height);
+            let color = palette.pick_other(rng, base);
+            (x, y, radius, color)
+        })
+        .collect::<Vec<_>>();
+
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        *p = blend(*p, base, 1.0);
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        for (bx, by, radius, color) in blobs.iter() {
+            let d = (px - bx).hypot(py - by) / radius;
+            let strength = (-d * d * 2.5).exp();
+            *p = blend(*p, *color, strength);
+        }
+    }
+}
+
+struct Wave {
+    baseline: f32,
+    /// Amplitude, frequency in radians per pixel, and phase of each sine component.
+    components: [(f32, f32, f32); 2],
+    color: Pixel,
+}
+
+impl Wave {
+    fn y(&self, x: f32) -> f32 {
+        self.baseline
+            + self
+                .components
+                .iter()
+                .map(|(a, f, p)| a * (f * x + p).sin())
+                .sum::<f32>()
+    }
+
+    fn slope(&self, x: f32) -> f32 {
+        self.components
+            .iter()
+            .map(|(a, f, p)| a * f * (f * x + p).cos())
+            .sum()
+    }
+}
+
+fn draw_waves(image: &mut RgbaImage, palette: &Palette, rng: &mut Rng, count: Option<u32>) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let count = count.unwrap_or_else(|| 4 + rng.below(3) as u32).max(1);
+
+    let waves = (0..count)
+        .map(|i| {
+            let spacing = height / (count + 1) as f32;
+            let amplitude = rng.range(0.03, 0.08) * height;
+            Wave {
+                baseline: spacing * (i + 1) as f32 + rng.range(-0.2, 0.2) * spacing,
+                components: [
+                    (
+                        amplitude,
+                        rng.range(1.0, 2.5) * TAU / width,
+                        rng.range(0.0, TAU),
+                    ),
+                    (
+                        amplitude * 0.35,
+                        rng.range(2.5, 5.0) * TAU / width,
+                        rng.range(0.0, TAU),
+                    ),
+                ],
+                color: palette.at((i + 1) as f32 / count as f32),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let top = palette.at(0.0);
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        *p = blend(*p, top, 1.0);
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        for wave in waves.iter() {
+            // Approximate the distance to the curve by dividing the vertical distance by the slope.
+            let distance = (wave.y(px) - py) / (1.0 + wave.slope(px).powi(2)).sqrt();
+            *p = blend(*p, wave.color, coverage(distance));
+        }
+    }
+}
+
+fn draw_triangulation(
+    image: &mut RgbaImage,
+    palette: &Palette,
+    rng: &mut Rng,
+    cell_size: Option<u32>,
+) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let size = cell_size
+        .map(|s| s.max(1) as f32)
+        .unwrap_or_else(|| default_cell_size(image, rng));
+    let columns = (width / size).ceil().max(1.0) as usize;
+    let rows = (height / size).ceil().max(1.0) as usize;
+    let (cell_width, cell_height) = (width / columns as f32, height / rows as f32);
+
+    // Jitter the grid points, keeping the points on the edges of the image on the edges.
+    let mut points = Vec::with_capacity((columns + 1) * (rows + 1));
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let mut x = column as f32 * cell_width;
+            let mut y = row as f32 * cell_height;
+            let (dx, dy) = (rng.range(-0.35, 0.35), rng.range(-0.35, 0.35));
+            if column > 0 && column < columns {
+                x += dx * cell_width;
+            }
+            if row > 0 && row < rows {
+                y += dy * cell_height;
+            }
+            points.push((x, y));
+        }
+    }
+
+    let angle = rng.range(0.0, TAU);
+    let (cos, sin) = (angle.cos(), angle.sin());
+    let point = |column: usize, row: usize| points[row * (columns + 1) + column];
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let corners = [
+                point(column, row),
+                point(column + 1, row),
+                point(column + 1, row + 1),
+                point(column, row + 1),
+            ];
+            let triangles = if rng.below(2) == 0 {
+                [
+                    [corners[0], corners[1], corners[2]],
+                    [corners[0], corners[2], corners[3]],
+                ]
+            } else {
+                [
+                    [corners[0], corners[1], corners[3]],
+                    [corners[1], corners[2], corners[3]],
+                ]
+            };
+
+            for triangle in triangles.iter() {
+                let cx = triangle.iter().map(|p| p.0).sum::<f32>() / 3.0 / width - 0.5;
+                let cy = triangle.iter().map(|p| p.1).sum::<f32>() / 3.0 / height - 0.5;
+                let t = 0.5 + (cx * cos + cy * sin) + rng.range(-0.08, 0.08);
+                fill_triangle(image, triangle, palette.at(t));
+            }
+        }
+    }
+}
+
+fn fill_triangle(image: &mut RgbaImage, triangle: &[(f32, f32); 3], color: Pixel) {
+    let path = Path::polygon(triangle.to_vec());
+    let bounds = match path
+        .bounds()
+        .pixel_bounds(1.0, image.width(), image.height())
+    {
+        Some(b) => b,
+        None => return,
+    };
+
+    for y in bounds.top..=bounds.bottom {
+        for x in bounds.left..=bounds.right {
+            // Grow each triangle by half a pixel so that the background doesn't show through the
+            // antialiased edges shared by neighboring triangles.
+            let d = path.fill_distance(x as f32 + 0.5, y as f32 + 0.5);
+            let c = (1.0 - d).clamp(0.0, 1.0);
+            if c > 0.0 {
+                let p = image.get_pixel_mut(x, y);
+                *p = blend(*p, color, c);
+            }
+        }
+    }
+}
+
+/// Smoothly interpolated random values on an integer lattice.
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (sx, sy) = (smooth(fx), smooth(fy));
+
+    let value = |ix: f32, iy: f32| {
+        let h = mix64(seed ^ mix64((ix as i64 as u64) ^ mix64(iy as i64 as u64)));
+        (h >> 40) as f32 / (1u64 << 24) as f32
+    };
+
+    let top = value(x0, y0) + (value(x0 + 1.0, y0) - value(x0, y0)) * sx;
+    let bottom = value(x0, y0 + 1.0) + (value(x0 + 1.0, y0 + 1.0) - value(x0, y0 + 1.0)) * sx;
+    top + (bottom - top) * sy
+}
+
+fn draw_noise_gradient(
+    image: &mut RgbaImage,
+    palette: &Palette,
+    rng: &mut Rng,
+    scale: Option<f32>,
+) {
+    let width = image.width() as f32;
+    let scale = scale.unwrap_or_else(|| rng.range(1.5, 3.0)).max(0.01);
+    let seed = rng.next_u64();
+    let warp_seed = rng.next_u64();
+    let octaves = 4;
+
+    let fbm = |seed: u64, x: f32, y: f32| {
+        let mut total = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        for octave in 0..octaves {
+            total +=
+                amplitude * value_noise(seed.wrapping_add(octave), x * frequency, y * frequency);
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        total / (1.0 - 0.5f32.powi(octaves as i32))
+    };
+
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        let nx = (x as f32 + 0.5) / width * scale;
+        let ny = (y as f32 + 0.5) / width * scale;
+
+        // Warp the coordinates with a second noise field so the result looks less blocky.
+        let warp = fbm(warp_seed, nx, ny) * PI;
+        let v = fbm(seed, nx + warp.cos() * 0.5, ny + warp.sin() * 0.5);
+        let t = ((v - 0.5) * 1.8 + 0.5).clamp(0.0, 1.0);
+        *p = blend(*p, palette.at(t), 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [&str; 5] = ["grid", "blobs", "waves", "triangulation", "noiseGradient"];
+
+    fn render(config: &str) -> Result<RgbaImage> {
+        let generator: Generator = toml::from_str(config).unwrap();
+        let mut image = RgbaImage::from_pixel(48, 32, pixel(255, 255, 255, 255));
+        generator.draw(&mut image, &ColorContext::default())?;
+        Ok(image)
+    }
+
+    fn render_kind(kind: &str, seed: &str) -> RgbaImage {
+        render(&format!(
+            r#"
+            type = "{}"
+            seed = "{}"
+            palette = ["264653", "2a9d8f", "e9c46a", "f4a261", "e76f51"]
+            "#,
+            kind, seed
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn seeds_give_stable_images() {
+        for kind in KINDS.iter() {
+            let image = render_kind(kind, "Hello, world");
+            assert_eq!(image, render_kind(kind, "Hello, world"), "{}", kind);
+            assert_ne!(image, render_kind(kind, "Another post"), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn single_color_palettes() {
+        for kind in KINDS.iter() {
+            let image = render(&format!(
+                "type = \"{}\"\nseed = \"x\"\npalette = [[10, 20, 30]]",
+                kind
+            ))
+            .unwrap();
+            assert!(
+                image.pixels().any(|p| *p == pixel(10, 20, 30, 255)),
+                "{}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn settings() {
+        for kind in ["grid", "triangulation"].iter() {
+            let config = format!(
+                "type = \"{}\"\nseed = \"x\"\ncell_size = 0\npalette = [\"000000\", \"ffffff\"]",
+                kind
+            );
+            assert!(render(&config).is_ok(), "{}", kind);
+        }
+
+        let err = render("type = \"grid\"\nseed = \"x\"\npalette = []").unwrap_err();
+        assert!(err.to_string().contains("palette"), "{}", err);
+    }
+
+    #[test]
+    fn palette_interpolation() {
+        let palette = Palette(vec![pixel(0, 0, 0, 255), pixel(200, 100, 0, 255)]);
+        assert_eq!(palette.at(-1.0), pixel(0, 0, 0, 255));
+        assert_eq!(palette.at(0.5), pixel(100, 50, 0, 255));
+        assert_eq!(palette.at(2.0), pixel(200, 100, 0, 255));
+
+        let single = Palette(vec![pixel(1, 2, 3, 255)]);
+        assert_eq!(single.at(0.7), pixel(1, 2, 3, 255));
+        let mut rng = Rng::from_seed("x");
+        assert_eq!(
+            single.pick_other(&mut rng, pixel(1, 2, 3, 255)),
+            pixel(1, 2, 3, 255)
+        );
+    }
+}
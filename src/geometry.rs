@@ -0,0 +1,540 @@
+use crate::Rect;
+use serde_derive::Deserialize;
+
+/// A box in continuous pixel coordinates, optionally with rounded corners. Unlike `Rect`, the right
+/// and bottom edges are exclusive, so a `Rect` covering pixels 0 through 9 becomes a box from 0.0 to 10.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct RoundedRect {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    /// Corner radii, in the order top left, top right, bottom right, bottom left.
+    pub radii: [f32; 4],
+}
+
+impl RoundedRect {
+    pub fn new(rect: &Rect, radii: [f32; 4]) -> RoundedRect {
+        RoundedRect {
+            left: rect.left as f32,
+            top: rect.top as f32,
+            right: rect.right as f32 + 1.0,
+            bottom: rect.bottom as f32 + 1.0,
+            radii,
+        }
+        .normalized()
+    }
+
+    /// The same box with different corner radii.
+    pub fn with_radii(self, radii: [f32; 4]) -> RoundedRect {
+        RoundedRect { radii, ..self }.normalized()
+    }
+
+    pub fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f32 {
+        self.bottom - self.top
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() <= 0.0 || self.height() <= 0.0
+    }
+
+    /// Scale down the radii if adjacent corners would overlap, the same way CSS does.
+    fn normalized(mut self) -> RoundedRect {
+        let [tl, tr, br, bl] = self.radii;
+        let (width, height) = (self.width().max(0.0), self.height().max(0.0));
+        let factor = [
+            width / (tl + tr),
+            width / (bl + br),
+            height / (tl + bl),
+            height / (tr + br),
+        ]
+        .iter()
+        .filter(|f| f.is_finite())
+        .fold(1.0f32, |acc, f| acc.min(*f));
+
+        for r in self.radii.iter_mut() {
+            *r = (*r * factor).max(0.0);
+        }
+        self
+    }
+
+    pub fn offset(self, x: f32, y: f32) -> RoundedRect {
+        RoundedRect {
+            left: self.left + x,
+            right: self.right + x,
+            top: self.top + y,
+            bottom: self.bottom + y,
+            ..self
+        }
+    }
+
+    /// Grow the box by `amount` on every side, or shrink it if `amount` is negative. Rounded corners
+    /// grow and shrink with it, as with a CSS box-shadow spread.
+    pub fn spread(self, amount: f32) -> RoundedRect {
+        let mut radii = self.radii;
+        for r in radii.iter_mut() {
+            if *r > 0.0 {
+                *r = (*r + amount).max(0.0);
+            }
+        }
+
+        RoundedRect {
+            left: self.left - amount,
+            right: self.right + amount,
+            top: self.top - amount,
+            bottom: self.bottom + amount,
+            radii,
+        }
+        .normalized()
+    }
+
+    /// Shrink each side of the box by a different amount, such as the inside edge of a border.
+    pub fn inset(self, top: f32, right: f32, bottom: f32, left: f32) -> RoundedRect {
+        let [tl, tr, br, bl] = self.radii;
+        RoundedRect {
+            left: self.left + left,
+            right: self.right - right,
+            top: self.top + top,
+            bottom: self.bottom - bottom,
+            radii: [
+                (tl - left.max(top)).max(0.0),
+                (tr - right.max(top)).max(0.0),
+                (br - right.max(bottom)).max(0.0),
+                (bl - left.max(bottom)).max(0.0),
+            ],
+        }
+        .normalized()
+    }
+
+    /// Signed distance from the point to the edge of the box. Negative values are inside the box.
+    fn distance(&self, px: f32, py: f32) -> f32 {
+        let cx = (self.left + self.right) / 2.0;
+        let cy = (self.top + self.bottom) / 2.0;
+        let radius = match (px < cx, py < cy) {
+            (true, true) => self.radii[0],
+            (false, true) => self.radii[1],
+            (false, false) => self.radii[2],
+            (true, false) => self.radii[3],
+        };
+
+        let qx = (px - cx).abs() - (self.width() / 2.0 - radius);
+        let qy = (py - cy).abs() - (self.height() / 2.0 - radius);
+        let outside = (qx.max(0.0).powi(2) + qy.max(0.0).powi(2)).sqrt();
+        outside + qx.max(qy).min(0.0) - radius
+    }
+
+    /// How much of the pixel at `x`, `y` is inside the box, from 0 to 1. Partially covered pixels
+    /// along the edges give the box its antialiasing.
+    pub fn coverage(&self, x: u32, y: u32) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let px = x as f32 + 0.5;
+        let py = y as f32 + 0.5;
+        if px < self.left - 1.0
+            || px > self.right + 1.0
+            || py < self.top - 1.0
+            || py > self.bottom + 1.0
+        {
+            return 0.0;
+        }
+
+        (0.5 - self.distance(px, py)).clamp(0.0, 1.0)
+    }
+
+    /// The pixels touched by the box after growing it by `margin`, clipped to an image of the given size.
+    /// Returns `None` if the box falls entirely outside the image.
+    pub fn pixel_bounds(&self, margin: f32, width: u32, height: u32) -> Option<Rect> {
+        let left = (self.left - margin).floor().max(0.0);
+        let top = (self.top - margin).floor().max(0.0);
+        let right = (self.right + margin).ceil().min(width as f32) - 1.0;
+        let bottom = (self.bottom + margin).ceil().min(height as f32) - 1.0;
+
+        if right < left || bottom < top {
+            None
+        } else {
+            Some(Rect {
+                left: left as u32,
+                top: top as u32,
+                right: right as u32,
+                bottom: bottom as u32,
+            })
+        }
+    }
+}
+
+/// An axis-aligned ellipse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Ellipse {
+    pub cx: f32,
+    pub cy: f32,
+    pub rx: f32,
+    pub ry: f32,
+}
+
+impl Ellipse {
+    /// The ellipse that fills a box.
+    pub fn inside(area: &RoundedRect) -> Ellipse {
+        Ellipse {
+            cx: (area.left + area.right) / 2.0,
+            cy: (area.top + area.bottom) / 2.0,
+            rx: area.width() / 2.0,
+            ry: area.height() / 2.0,
+        }
+    }
+
+    /// Approximate signed distance to the edge of the ellipse. Negative values are inside.
+    fn distance(&self, px: f32, py: f32) -> f32 {
+        if self.rx <= 0.0 || self.ry <= 0.0 {
+            return f32::INFINITY;
+        }
+
+        let (dx, dy) = (px - self.cx, py - self.cy);
+        if (self.rx - self.ry).abs() < f32::EPSILON {
+            return (dx * dx + dy * dy).sqrt() - self.rx;
+        }
+
+        // Divide the implicit function by the length of its gradient to get close to the true distance.
+        let (rx2, ry2) = (self.rx * self.rx, self.ry * self.ry);
+        let f = dx * dx / rx2 + dy * dy / ry2 - 1.0;
+        let gx = 2.0 * dx / rx2;
+        let gy = 2.0 * dy / ry2;
+        let gradient = (gx * gx + gy * gy).sqrt();
+        if gradient <= 0.0 {
+            -self.rx.min(self.ry)
+        } else {
+            f / gradient
+        }
+    }
+
+    pub fn coverage(&self, x: u32, y: u32) -> f32 {
+        (0.5 - self.distance(x as f32 + 0.5, y as f32 + 0.5)).clamp(0.0, 1.0)
+    }
+}
+
+/// A connected run of line segments within a `Path`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Subpath {
+    pub points: Vec<(f32, f32)>,
+    /// Whether the last point connects back to the first.
+    pub closed: bool,
+}
+
+/// How the ends of an open line are drawn.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LineCap {
+    /// End the line exactly at its end point.
+    #[default]
+    Butt,
+    /// Add a half circle to the end of the line.
+    Round,
+    /// Extend the line past its end point by half of its width.
+    Square,
+}
+
+/// A shape made of straight line segments. Curves are flattened into segments before they get here.
+/// Filling uses the even-odd rule, and treats every subpath as closed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Path {
+    pub subpaths: Vec<Subpath>,
+}
+
+/// The distance from a point to a stroke, and how far along the path the nearest part of the stroke is.
+pub(crate) struct StrokeDistance {
+    pub distance: f32,
+    pub along: f32,
+}
+
+impl Path {
+    pub fn polygon(points: Vec<(f32, f32)>) -> Path {
+        Path {
+            subpaths: vec![Subpath {
+                points,
+                closed: true,
+            }],
+        }
+    }
+
+    /// Approximate a rounded rectangle with line segments.
+    pub fn rounded_rect(r: &RoundedRect) -> Path {
+        let corners = [
+            (
+                r.left + r.radii[0],
+                r.top + r.radii[0],
+                r.radii[0],
+                180.0f32,
+            ),
+            (r.right - r.radii[1], r.top + r.radii[1], r.radii[1], 270.0),
+            (r.right - r.radii[2], r.bottom - r.radii[2], r.radii[2], 0.0),
+            (r.left + r.radii[3], r.bottom - r.radii[3], r.radii[3], 90.0),
+        ];
+
+        let mut points = Vec::new();
+        for (cx, cy, radius, start) in corners.iter() {
+            let steps = arc_steps(*radius, 0.25);
+            for i in 0..=steps {
+                let angle = (start + 90.0 * i as f32 / steps as f32).to_radians();
+                points.push((cx + radius * angle.cos(), cy + radius * angle.sin()));
+            }
+        }
+        Path::polygon(points)
+    }
+
+    /// Approximate an ellipse with line segments.
+    pub fn ellipse(e: &Ellipse) -> Path {
+        let steps = arc_steps(e.rx.max(e.ry), 1.0);
+        let points = (0..steps)
+            .map(|i| {
+                let angle = std::f32::consts::TAU * i as f32 / steps as f32;
+                (e.cx + e.rx * angle.cos(), e.cy + e.ry * angle.sin())
+            })
+            .collect();
+        Path::polygon(points)
+    }
+
+    /// Each segment in the path, with whether its start and end are the open ends of a subpath.
+    fn segments(&self, close_all: bool) -> impl Iterator<Item = Segment> + '_ {
+        self.subpaths.iter().flat_map(move |subpath| {
+            let points = &subpath.points;
+            let closed = subpath.closed || close_all;
+            let count = match points.len() {
+                0 => 0,
+                1 => 1,
+                n if closed => n,
+                n => n - 1,
+            };
+
+            (0..count).map(move |i| Segment {
+                start: points[i],
+                end: points[(i + 1) % points.len()],
+                start_is_cap: !closed && i == 0,
+                end_is_cap: !closed && i + 1 == count,
+            })
+        })
+    }
+
+    /// Signed distance to the edge of the filled path. Negative values are inside.
+    pub fn fill_distance(&self, px: f32, py: f32) -> f32 {
+        let mut inside = false;
+        let mut nearest = f32::INFINITY;
+        for Segment { start, end, .. } in self.segments(true) {
+            let ((x0, y0), (x1, y1)) = (start, end);
+            if (y0 > py) != (y1 > py) && px < x0 + (py - y0) * (x1 - x0) / (y1 - y0) {
+                inside = !inside;
+            }
+
+            nearest = nearest.min(segment_distance((px, py), start, end));
+        }
+
+        if inside {
+            -nearest
+        } else {
+            nearest
+        }
+    }
+
+    pub fn coverage(&self, x: u32, y: u32) -> f32 {
+        (0.5 - self.fill_distance(x as f32 + 0.5, y as f32 + 0.5)).clamp(0.0, 1.0)
+    }
+
+    /// Signed distance to the outside of a stroke of the given width centered on the path.
+    pub fn stroke_distance(&self, px: f32, py: f32, width: f32, cap: LineCap) -> StrokeDistance {
+        let half = width / 2.0;
+        let mut best = StrokeDistance {
+            distance: f32::INFINITY,
+            along: 0.0,
+        };
+        let mut length_so_far = 0.0;
+
+        for segment in self.segments(false) {
+            let (dx, dy) = (
+                segment.end.0 - segment.start.0,
+                segment.end.1 - segment.start.1,
+            );
+            let length = (dx * dx + dy * dy).sqrt();
+            let (ux, uy) = if length > 0.0 {
+                (dx / length, dy / length)
+            } else {
+                (1.0, 0.0)
+            };
+            let (rx, ry) = (px - segment.start.0, py - segment.start.1);
+            let t = rx * ux + ry * uy;
+            let perpendicular = (rx * uy - ry * ux).abs();
+
+            let overshoot = if t < 0.0 && segment.start_is_cap {
+                Some(-t)
+            } else if t > length && segment.end_is_cap {
+                Some(t - length)
+            } else {
+                None
+            };
+
+            let distance = match (overshoot, cap) {
+                (Some(o), LineCap::Butt) => (perpendicular - half).max(o),
+                (Some(o), LineCap::Square) => (perpendicular - half).max(o - half),
+                _ => segment_distance((px, py), segment.start, segment.end) - half,
+            };
+
+            if distance < best.distance {
+                best = StrokeDistance {
+                    distance,
+                    along: length_so_far + t.clamp(0.0, length),
+                };
+            }
+            length_so_far += length;
+        }
+
+        best
+    }
+
+    /// The smallest box containing every point in the path.
+    pub fn bounds(&self) -> RoundedRect {
+        let mut bounds = RoundedRect {
+            left: f32::INFINITY,
+            top: f32::INFINITY,
+            right: f32::NEG_INFINITY,
+            bottom: f32::NEG_INFINITY,
+            radii: [0.0; 4],
+        };
+        for (x, y) in self.subpaths.iter().flat_map(|s| s.points.iter()) {
+            bounds.left = bounds.left.min(*x);
+            bounds.right = bounds.right.max(*x);
+            bounds.top = bounds.top.min(*y);
+            bounds.bottom = bounds.bottom.max(*y);
+        }
+        bounds
+    }
+}
+
+struct Segment {
+    start: (f32, f32),
+    end: (f32, f32),
+    start_is_cap: bool,
+    end_is_cap: bool,
+}
+
+/// How many segments to use for `turns` of a circle so that the segments stay within a tenth of a
+/// pixel of the true curve.
+fn arc_steps(radius: f32, turns: f32) -> usize {
+    if radius <= 0.0 {
+        return 1;
+    }
+
+    ((std::f32::consts::PI * (radius * 5.0).sqrt() * turns).ceil() as usize).max(4)
+}
+
+/// Distance from a point to the line segment between `a` and `b`.
+pub(crate) fn segment_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let (nearest_x, nearest_y) = (a.0 + dx * t, a.1 + dy * t);
+    ((p.0 - nearest_x).powi(2) + (p.1 - nearest_y).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(size: u32) -> Rect {
+        Rect {
+            left: 0,
+            top: 0,
+            right: size - 1,
+            bottom: size - 1,
+        }
+    }
+
+    #[test]
+    fn rounded_corners_are_antialiased() {
+        let shape = RoundedRect::new(&rect(20), [10.0, 0.0, 0.0, 0.0]);
+        // Outside the arc in the rounded corner, but inside the square corners.
+        assert_eq!(shape.coverage(0, 0), 0.0);
+        assert_eq!(shape.coverage(19, 0), 1.0);
+        assert_eq!(shape.coverage(0, 19), 1.0);
+        assert_eq!(shape.coverage(10, 10), 1.0);
+
+        // A pixel on the arc is partially covered.
+        let edge = shape.coverage(2, 3);
+        assert!(edge > 0.0 && edge < 1.0, "coverage was {}", edge);
+
+        // Pixels past the edges are never covered.
+        assert_eq!(shape.coverage(20, 10), 0.0);
+        assert_eq!(shape.coverage(10, 20), 0.0);
+    }
+
+    #[test]
+    fn radii_that_overlap_are_scaled_down() {
+        let shape = RoundedRect::new(&rect(20), [30.0, 10.0, 0.0, 0.0]);
+        assert_eq!(shape.radii, [15.0, 5.0, 0.0, 0.0]);
+
+        let shape = RoundedRect::new(&rect(20), [-5.0, 5.0, 5.0, 5.0]);
+        assert_eq!(shape.radii, [0.0, 5.0, 5.0, 5.0]);
+    }
+
+    #[test]
+    fn spread_and_inset_adjust_the_radii() {
+        let shape = RoundedRect::new(&rect(40), [10.0, 0.0, 10.0, 10.0]);
+
+        let grown = shape.spread(5.0);
+        assert_eq!((grown.left, grown.right), (-5.0, 45.0));
+        // Square corners stay square.
+        assert_eq!(grown.radii, [15.0, 0.0, 15.0, 15.0]);
+
+        let inner = shape.inset(2.0, 2.0, 4.0, 2.0);
+        assert_eq!((inner.top, inner.bottom), (2.0, 36.0));
+        assert_eq!(inner.radii, [8.0, 0.0, 6.0, 6.0]);
+        assert!(shape.inset(20.0, 20.0, 20.0, 20.0).is_empty());
+    }
+
+    #[test]
+    fn pixel_bounds_are_clipped_to_the_image() {
+        let shape = RoundedRect::new(&rect(10), [0.0; 4]).offset(-4.0, 2.0);
+        let bounds = shape.pixel_bounds(1.0, 8, 8).unwrap();
+        assert_eq!(
+            (bounds.left, bounds.top, bounds.right, bounds.bottom),
+            (0, 1, 6, 7)
+        );
+        assert!(shape.offset(20.0, 0.0).pixel_bounds(1.0, 8, 8).is_none());
+    }
+
+    #[test]
+    fn polygons_use_the_even_odd_rule() {
+        // A five-pointed star, whose center is outside under the even-odd rule.
+        let points = (0..5)
+            .map(|i| {
+                let angle = (i as f32 * 144.0).to_radians();
+                (50.0 + 40.0 * angle.sin(), 50.0 - 40.0 * angle.cos())
+            })
+            .collect();
+        let star = Path::polygon(points);
+        assert_eq!(star.coverage(49, 49), 0.0);
+        assert_eq!(star.coverage(49, 14), 1.0);
+        assert_eq!(star.coverage(5, 5), 0.0);
+    }
+
+    #[test]
+    fn ellipse_coverage() {
+        let ellipse = Ellipse {
+            cx: 20.0,
+            cy: 10.0,
+            rx: 20.0,
+            ry: 10.0,
+        };
+        assert_eq!(ellipse.coverage(19, 9), 1.0);
+        assert_eq!(ellipse.coverage(0, 0), 0.0);
+        let empty = Ellipse { rx: 0.0, ..ellipse };
+        assert_eq!(empty.coverage(19, 9), 0.0);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::blend_mode::{self, BlendMode};
+use crate::generator::{hash_seed, mix64};
+use crate::geometry::RoundedRect;
+use crate::pixel;
+use image::RgbaImage;
+use serde_derive::Deserialize;
+
+/// Film grain noise, which adds texture and hides banding in smooth gradients after the image is
+/// compressed. The same seed always produces the same noise.
+#[derive(Debug, Deserialize)]
+pub struct Grain {
+    /// How strong the noise is, from 0 to 1.
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+    #[serde(default)]
+    pub seed: String,
+    /// Use the same noise for every color channel. Otherwise each channel gets its own noise.
+    #[serde(default = "crate::bool_true")]
+    pub monochrome: bool,
+    /// The size of each grain in pixels.
+    #[serde(default = "default_size")]
+    pub size: u32,
+    /// How the noise combines with the image. Defaults to overlay, which leaves the image unchanged
+    /// wherever the noise is middle gray.
+    #[serde(default = "default_blend_mode")]
+    pub blend_mode: BlendMode,
+}
+
+fn default_intensity() -> f32 {
+    0.1
+}
+
+fn default_size() -> u32 {
+    1
+}
+
+fn default_blend_mode() -> BlendMode {
+    BlendMode::Overlay
+}
+
+/// A noise value from 0 to 255 for a channel of a grain cell. Two random values are averaged so that
+/// values near the middle are more common, which looks more like real grain.
+fn noise(seed: u64, x: u32, y: u32, channel: u64) -> u8 {
+    let h = mix64(seed ^ mix64(((x as u64) << 32 | y as u64) ^ mix64(channel)));
+    let a = (h & 0xffff) as f32 / 65535.0;
+    let b = ((h >> 16) & 0xffff) as f32 / 65535.0;
+    ((a + b) / 2.0 * 255.0).round() as u8
+}
+
+/// Add grain to `image`, limited to `area` if it's given. The transparency of the image is not changed.
+pub(crate) fn apply_grain(image: &mut RgbaImage, grain: &Grain, area: Option<&RoundedRect>) {
+    let seed = hash_seed(&grain.seed);
+    let size = grain.size.max(1);
+    let intensity = grain.intensity.clamp(0.0, 1.0);
+
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        let coverage = area.map(|a| a.coverage(x, y)).unwrap_or(1.0);
+        if coverage <= 0.0 || p[3] == 0 {
+            continue;
+        }
+
+        let (gx, gy) = (x / size, y / size);
+        let noise_pixel = if grain.monochrome {
+            let n = noise(seed, gx, gy, 0);
+            pixel(n, n, n, 255)
+        } else {
+            pixel(
+                noise(seed, gx, gy, 0),
+                noise(seed, gx, gy, 1),
+                noise(seed, gx, gy, 2),
+                255,
+            )
+        };
+
+        let alpha = p[3];
+        let mut opaque = *p;
+        opaque[3] = 255;
+        *p = blend_mode::blend_pixel(opaque, noise_pixel, grain.blend_mode, intensity * coverage);
+        p[3] = alpha;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    fn grain(config: &str) -> Grain {
+        toml::from_str(config).unwrap()
+    }
+
+    fn gray() -> RgbaImage {
+        RgbaImage::from_pixel(16, 16, pixel(128, 128, 128, 255))
+    }
+
+    fn grainy(config: &str) -> RgbaImage {
+        let mut image = gray();
+        apply_grain(&mut image, &grain(config), None);
+        image
+    }
+
+    #[test]
+    fn seeds_give_stable_noise() {
+        let image = grainy("intensity = 0.5\nseed = \"a\"");
+        assert_ne!(image, gray());
+        assert_eq!(image, grainy("intensity = 0.5\nseed = \"a\""));
+        assert_ne!(image, grainy("intensity = 0.5\nseed = \"b\""));
+        assert_eq!(grainy("intensity = 0"), gray());
+    }
+
+    #[test]
+    fn monochrome_and_size() {
+        let image = grainy("intensity = 1");
+        assert!(image.pixels().all(|p| p[0] == p[1] && p[1] == p[2]));
+
+        let image = grainy("intensity = 1\nmonochrome = false");
+        assert!(image.pixels().any(|p| p[0] != p[1] || p[1] != p[2]));
+
+        let image = grainy("intensity = 1\nsize = 4");
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(3, 3));
+        assert_eq!(image.get_pixel(4, 8), image.get_pixel(7, 11));
+    }
+
+    #[test]
+    fn grain_keeps_transparency_and_stays_in_the_area() {
+        let mut image = RgbaImage::from_fn(16, 16, |x, _| {
+            if x < 8 {
+                pixel(128, 128, 128, 100)
+            } else {
+                pixel(0, 0, 0, 0)
+            }
+        });
+        let area = RoundedRect::new(
+            &Rect {
+                left: 0,
+                top: 0,
+                right: 15,
+                bottom: 7,
+            },
+            [0.0; 4],
+        );
+        apply_grain(&mut image, &grain("intensity = 1"), Some(&area));
+
+        assert!(image
+            .pixels()
+            .all(|p| p[3] == 100 || *p == pixel(0, 0, 0, 0)));
+        assert!((0..8).all(|y| image.get_pixel(3, y)[3] == 100));
+        assert!((8..16).all(|y| *image.get_pixel(3, y) == pixel(128, 128, 128, 100)));
+        assert!((0..8).any(|y| *image.get_pixel(3, y) != pixel(128, 128, 128, 100)));
+    }
+}
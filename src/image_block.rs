@@ -0,0 +1,462 @@
+use crate::blend_mode::{self, BlendMode};
+use crate::block_box::BlockBox;
+use crate::border::BlockBorder;
+use crate::decode::decode_image;
+use crate::mask::Mask;
+#[cfg(feature = "svg")]
+use crate::svg::SvgImage;
+use crate::{blend, shadow, Color, ColorContext, HAlign, Pixel, Radius, Rect, VAlign};
+use anyhow::Result;
+use image::{imageops::FilterType, DynamicImage, GenericImageView, RgbaImage};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// An image placed on the card, such as an avatar, logo or icon.
+#[derive(Debug, Deserialize)]
+pub struct ImageBlock<'a> {
+    /// The path of the image file. SVG files are rasterized at the size they're drawn, and need the
+    /// `svg` feature.
+    pub path: PathBuf,
+    /// For SVG images, the color to use wherever the SVG uses `currentColor`.
+    pub color: Option<Color<'a>>,
+    pub rect: Rect,
+    #[serde(default)]
+    pub fit: ImageFit,
+    /// Where to place the image within the rect when it doesn't fill it exactly. Defaults to center.
+    #[serde(default = "center_h")]
+    pub h_align: HAlign,
+    #[serde(default = "center_v")]
+    pub v_align: VAlign,
+    pub border: Option<BlockBorder<'a>>,
+    /// Round the corners of the image, its border and its shadow.
+    pub radius: Option<Radius>,
+    /// Clip the image to a shape. Unlike `radius`, this doesn't affect the border or shadow.
+    pub mask: Option<Mask>,
+    #[serde(default = "crate::one")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    /// Elements with a higher `z_index` are drawn on top of those with a lower one.
+    #[serde(default)]
+    pub z_index: i32,
+}
+
+fn center_h() -> HAlign {
+    HAlign::Center
+}
+
+fn center_v() -> VAlign {
+    VAlign::Center
+}
+
+/// How an image is sized to fit its rect, like the CSS `object-fit` property.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageFit {
+    /// Scale the image to cover the whole rect, cropping whatever doesn't fit.
+    #[default]
+    Cover,
+    /// Scale the image to fit entirely inside the rect.
+    Contain,
+    /// Stretch the image to the size of the rect.
+    Fill,
+    /// Draw the image at its original size.
+    None,
+}
+
+/// Load an image from disk. SVG images are rasterized at their own size.
+pub fn load_image(path: &Path) -> Result<DynamicImage> {
+    let source = Source::open(path, None)?;
+    let (width, height) = source.size();
+    source.into_image(width, height)
+}
+
+/// Load the background image. If `size` is given, SVG backgrounds are rasterized at the smallest size
+/// that covers it without changing their aspect ratio, so that they don't need to be scaled up later.
+/// Other images are loaded at their own size.
+pub fn load_background(path: &Path, size: Option<(u32, u32)>) -> Result<DynamicImage> {
+    let source = Source::open(path, None)?;
+    let (width, height) = source.background_size(size);
+    source.into_image(width, height)
+}
+
+/// Images that have already been decoded, which can be shared by any number of cards so that each file is
+/// only decoded once. SVG images are cached separately for each `currentColor`. The cache holds up to
+/// `MAX_CACHED_IMAGES` images, and drops the one used longest ago to make room for another.
+#[derive(Default)]
+pub struct ImageCache {
+    entries: Mutex<CacheEntries>,
+}
+
+impl fmt::Debug for ImageCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ImageCache")
+            .field("images", &self.entries.lock().unwrap().sources.len())
+            .finish()
+    }
+}
+
+/// How many images an `ImageCache` holds at most. Cards that fill in image paths from variables can use
+/// a different image each time, so the cache can't keep them all.
+const MAX_CACHED_IMAGES: usize = 32;
+
+/// An image's path, and the `currentColor` it was loaded with.
+type ImageKey = (PathBuf, Option<[u8; 4]>);
+
+#[derive(Default)]
+struct CacheEntries {
+    /// Each image, with the value of `uses` when it was last used.
+    sources: HashMap<ImageKey, (Arc<Source>, u64)>,
+    uses: u64,
+}
+
+impl CacheEntries {
+    fn get(&mut self, key: &ImageKey) -> Option<Arc<Source>> {
+        self.uses += 1;
+        let uses = self.uses;
+        self.sources.get_mut(key).map(|(source, last_used)| {
+            *last_used = uses;
+            source.clone()
+        })
+    }
+
+    fn insert(&mut self, key: ImageKey, source: Arc<Source>) -> Arc<Source> {
+        // Another thread may have loaded the same image in the meantime.
+        if let Some(existing) = self.get(&key) {
+            return existing;
+        }
+
+        if self.sources.len() >= MAX_CACHED_IMAGES {
+            let oldest = self
+                .sources
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.sources.remove(&oldest);
+            }
+        }
+
+        self.sources.insert(key, (source.clone(), self.uses));
+        source
+    }
+}
+
+fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("svg") || e.eq_ignore_ascii_case("svgz"))
+        .unwrap_or(false)
+}
+
+/// The contents of an image file, which may be a vector image that hasn't been rasterized yet.
+pub(crate) enum Source {
+    Raster(DynamicImage),
+    #[cfg(feature = "svg")]
+    Svg(SvgImage),
+}
+
+impl Source {
+    #[cfg_attr(not(feature = "svg"), allow(unused_variables))]
+    pub fn open(path: &Path, current_color: Option<Pixel>) -> Result<Source> {
+        if is_svg(path) {
+            #[cfg(feature = "svg")]
+            return Ok(Source::Svg(SvgImage::open(path, current_color)?));
+
+            #[cfg(not(feature = "svg"))]
+            return Err(anyhow::anyhow!(
+                "Can not open {:?} because SVG support is not enabled. Build with the `svg` feature.",
+                path
+            ));
+        }
+
+        Ok(Source::Raster(decode_image(path)?))
+    }
+
+    /// Open an image, or reuse it if it's already in `cache`.
+    pub fn load(
+        path: &Path,
+        current_color: Option<Pixel>,
+        cache: Option<&ImageCache>,
+    ) -> Result<Arc<Source>> {
+        let cache = match cache {
+            Some(cache) => cache,
+            None => return Ok(Arc::new(Source::open(path, current_color)?)),
+        };
+
+        let key = (path.to_path_buf(), current_color.map(|c| c.0));
+        if let Some(source) = cache.entries.lock().unwrap().get(&key) {
+            return Ok(source);
+        }
+
+        // Decode without holding the lock, so that other threads aren't blocked on it.
+        let source = Arc::new(Source::open(path, current_color)?);
+        Ok(cache.entries.lock().unwrap().insert(key, source))
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        match self {
+            Source::Raster(image) => image.dimensions(),
+            #[cfg(feature = "svg")]
+            Source::Svg(svg) => svg.size(),
+        }
+    }
+
+    /// The size to draw a background at. When the card size is known, SVG backgrounds are rasterized at the
+    /// smallest size that covers it without changing their aspect ratio. Other images keep their own size.
+    pub fn background_size(&self, card_size: Option<(u32, u32)>) -> (u32, u32) {
+        let (source_width, source_height) = self.size();
+        match card_size {
+            Some((width, height)) if !matches!(self, Source::Raster(_)) => {
+                let scale =
+                    (width as f32 / source_width as f32).max(height as f32 / source_height as f32);
+                (
+                    ((source_width as f32 * scale).round() as u32).max(width),
+                    ((source_height as f32 * scale).round() as u32).max(height),
+                )
+            }
+            _ => (source_width, source_height),
+        }
+    }
+
+    /// Scale the image to the given size.
+    pub fn render(&self, width: u32, height: u32) -> Result<RgbaImage> {
+        match self {
+            Source::Raster(image) if image.dimensions() == (width, height) => Ok(image.to_rgba8()),
+            Source::Raster(image) => Ok(image::imageops::resize(
+                &image.to_rgba8(),
+                width,
+                height,
+                FilterType::CatmullRom,
+            )),
+            #[cfg(feature = "svg")]
+            Source::Svg(svg) => svg.render(width, height),
+        }
+    }
+
+    fn into_image(self, width: u32, height: u32) -> Result<DynamicImage> {
+        match self {
+            Source::Raster(image) if image.dimensions() == (width, height) => Ok(image),
+            source => Ok(DynamicImage::ImageRgba8(source.render(width, height)?)),
+        }
+    }
+}
+
+fn align_offset(free_space: f32, fraction: f32) -> i64 {
+    (free_space * fraction).round() as i64
+}
+
+/// Scale `image` according to `fit` and position it within `area`. Returns the scaled image and the
+/// position of its top left corner, which may be outside of `area` if the image is cropped.
+fn place_image(
+    image: &Source,
+    area: &Rect,
+    fit: ImageFit,
+    h_align: HAlign,
+    v_align: VAlign,
+) -> Result<(RgbaImage, i64, i64)> {
+    let area_width = (area.right - area.left + 1) as f32;
+    let area_height = (area.bottom - area.top + 1) as f32;
+    let (image_width, image_height) = image.size();
+    let (image_width, image_height) = (image_width as f32, image_height as f32);
+
+    let (width, height) = match fit {
+        ImageFit::Fill => (area_width, area_height),
+        ImageFit::None => (image_width, image_height),
+        ImageFit::Cover | ImageFit::Contain => {
+            let x_scale = area_width / image_width;
+            let y_scale = area_height / image_height;
+            let scale = if fit == ImageFit::Cover {
+                x_scale.max(y_scale)
+            } else {
+                x_scale.min(y_scale)
+            };
+            (image_width * scale, image_height * scale)
+        }
+    };
+
+    let (width, height) = (
+        width.round().max(1.0) as u32,
+        height.round().max(1.0) as u32,
+    );
+    let scaled = image.render(width, height)?;
+
+    let h_fraction = match h_align {
+        HAlign::Left => 0.0,
+        HAlign::Center => 0.5,
+        HAlign::Right => 1.0,
+    };
+    let v_fraction = match v_align {
+        VAlign::Top => 0.0,
+        VAlign::Center => 0.5,
+        VAlign::Bottom => 1.0,
+    };
+
+    let left = area.left as i64 + align_offset(area_width - width as f32, h_fraction);
+    let top = area.top as i64 + align_offset(area_height - height as f32, v_fraction);
+    Ok((scaled, left, top))
+}
+
+pub(crate) fn draw_image_block(
+    bg: &mut RgbaImage,
+    block: &ImageBlock,
+    colors: &ColorContext,
+    images: Option<&ImageCache>,
+) -> Result<()> {
+    let (width, height) = bg.dimensions();
+    let block_box = BlockBox::new(
+        &block.rect,
+        block.border.as_ref(),
+        block.radius,
+        (width, height),
+        colors,
+    )?;
+    let box_shadows = block
+        .border
+        .as_ref()
+        .map(|b| b.shadow.as_slice())
+        .unwrap_or_default();
+
+    let mut layer = RgbaImage::new(width, height);
+    shadow::draw_outer_shadows(&mut layer, box_shadows, &block_box.border_box, colors)?;
+
+    let color = block
+        .color
+        .as_ref()
+        .map(|c| c.resolve(colors))
+        .transpose()?;
+    let source = Source::load(&block.path, color, images)?;
+    let area = block_box.inside_border(&block.rect);
+    let (scaled, left, top) = place_image(&source, &area, block.fit, block.h_align, block.v_align)?;
+    let mask = block
+        .mask
+        .as_ref()
+        .map(|m| m.shape(&block_box.padding_box, images))
+        .transpose()?;
+
+    // Copy the image into the padding box, clipping it to the rounded corners.
+    for y in area.top..=area.bottom.min(height - 1) {
+        for x in area.left..=area.right.min(width - 1) {
+            let (source_x, source_y) = (x as i64 - left, y as i64 - top);
+            if source_x < 0
+                || source_y < 0
+                || source_x >= scaled.width() as i64
+                || source_y >= scaled.height() as i64
+            {
+                continue;
+            }
+
+            let mut coverage = block_box.padding_box.coverage(x, y);
+            if let Some(mask) = mask.as_ref() {
+                coverage *= mask.coverage(x, y);
+            }
+
+            if coverage > 0.0 {
+                let p = *scaled.get_pixel(source_x as u32, source_y as u32);
+                let dest = layer.get_pixel_mut(x, y);
+                *dest = blend(*dest, p, coverage);
+            }
+        }
+    }
+
+    let border = block_box.render(width, height, None);
+    image::imageops::overlay(&mut layer, &border, 0, 0);
+    shadow::draw_inset_shadows(&mut layer, box_shadows, &block_box.padding_box, colors)?;
+
+    blend_mode::composite(bg, &layer, 0, 0, block.blend_mode, block.opacity);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel;
+
+    /// A 40x20 image.
+    fn wide_image() -> Source {
+        Source::Raster(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            40,
+            20,
+            pixel(255, 0, 0, 255),
+        )))
+    }
+
+    fn area() -> Rect {
+        Rect {
+            left: 10,
+            top: 10,
+            right: 29,
+            bottom: 29,
+        }
+    }
+
+    fn place(fit: ImageFit, h_align: HAlign, v_align: VAlign) -> (u32, u32, i64, i64) {
+        let (scaled, left, top) =
+            place_image(&wide_image(), &area(), fit, h_align, v_align).unwrap();
+        (scaled.width(), scaled.height(), left, top)
+    }
+
+    #[test]
+    fn fit_modes() {
+        assert_eq!(
+            place(ImageFit::Cover, HAlign::Center, VAlign::Center),
+            (40, 20, 0, 10)
+        );
+        assert_eq!(
+            place(ImageFit::Contain, HAlign::Center, VAlign::Center),
+            (20, 10, 10, 15)
+        );
+        assert_eq!(
+            place(ImageFit::Fill, HAlign::Center, VAlign::Center),
+            (20, 20, 10, 10)
+        );
+        assert_eq!(
+            place(ImageFit::None, HAlign::Center, VAlign::Center),
+            (40, 20, 0, 10)
+        );
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(
+            place(ImageFit::Cover, HAlign::Left, VAlign::Top),
+            (40, 20, 10, 10)
+        );
+        assert_eq!(
+            place(ImageFit::Cover, HAlign::Right, VAlign::Top),
+            (40, 20, -10, 10)
+        );
+        assert_eq!(
+            place(ImageFit::Contain, HAlign::Center, VAlign::Bottom),
+            (20, 10, 10, 20)
+        );
+    }
+
+    #[cfg(not(feature = "svg"))]
+    #[test]
+    fn svgs_need_the_svg_feature() {
+        let err = load_image(Path::new("icon.svg")).unwrap_err();
+        assert!(err.to_string().contains("`svg` feature"), "{}", err);
+    }
+
+    #[test]
+    fn cache_drops_the_least_recently_used_image() {
+        let key = |i: usize| (PathBuf::from(format!("{}.png", i)), None);
+        let source = || Arc::new(Source::Raster(DynamicImage::new_rgba8(1, 1)));
+
+        let mut entries = CacheEntries::default();
+        for i in 0..MAX_CACHED_IMAGES {
+            entries.insert(key(i), source());
+        }
+        assert!(entries.get(&key(0)).is_some());
+
+        entries.insert(key(MAX_CACHED_IMAGES), source());
+        assert_eq!(entries.sources.len(), MAX_CACHED_IMAGES);
+        assert!(entries.get(&key(0)).is_some());
+        assert!(entries.get(&key(1)).is_none());
+        assert!(entries.get(&key(MAX_CACHED_IMAGES)).is_some());
+    }
+}
@@ -1,45 +1,79 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
 use glyph_brush_layout::{
     ab_glyph::{Font, FontRef, PxScale},
     FontId, GlyphPositioner, Layout, LineBreaker, SectionGeometry, SectionGlyph, SectionText,
 };
 use image::{GenericImageView, ImageBuffer, Rgba};
+use serde::{Deserialize as _, Deserializer};
 use serde_derive::Deserialize;
 use std::borrow::Cow;
-use std::convert::TryFrom;
+
+mod auto_color;
+mod backdrop;
+mod background;
+mod blend_mode;
+mod block_box;
+mod border;
+mod color;
+mod contrast;
+mod decode;
+mod each_color;
+mod filter;
+mod fit;
+mod generator;
+mod geometry;
+mod grain;
+mod image_block;
+mod mask;
+mod paint;
+mod palette;
+mod shadow;
+mod shape;
+mod smart_crop;
+#[cfg(feature = "svg")]
+mod svg;
+mod svg_path;
+mod swatch;
+mod template;
+mod variables;
+
+use block_box::BlockBox;
+use color::ColorContext;
+use contrast::TextCoverage;
+use each_color::EachColor;
+use image::RgbaImage;
+
+pub use auto_color::{AutoColor, Scrim, TextColor};
+pub use backdrop::Backdrop;
+pub use background::{Background, GeneratedBackground, Pattern};
+pub use blend_mode::BlendMode;
+pub use border::{BlockBorder, BorderSide, BorderStyle};
+pub use color::Color;
+pub use contrast::{TextContrast, WcagLevel};
+pub use filter::Filter;
+pub use fit::{BackgroundFit, CropWindow, FitMode, ResizeFilter};
+pub use generator::{Generator, GeneratorKind};
+pub use geometry::LineCap;
+pub use grain::Grain;
+pub use image_block::{load_background, load_image, ImageBlock, ImageCache, ImageFit};
+pub use mask::Mask;
+pub use paint::{ColorStop, Gradient, GradientShape, Paint};
+pub use palette::Palette;
+pub use shadow::BoxShadow;
+pub use shape::{Shape, ShapeBlock, Stroke};
+pub use swatch::{extract_swatches, Swatches};
+pub use template::Template;
+pub use variables::expand_variables;
 
 type Pixel = image::Rgba<u8>;
 
+const DEFAULT_SHADOW_COLOR: Pixel = pixel(0, 0, 0, 25);
+const TRANSPARENT: Pixel = pixel(0, 0, 0, 0);
+
 const fn pixel(red: u8, green: u8, blue: u8, alpha: u8) -> Pixel {
     Rgba([red, green, blue, alpha])
 }
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
-pub enum Color<'a> {
-    Rgb(u8, u8, u8),
-    Rgba(u8, u8, u8, u8),
-    RgbString(Cow<'a, str>),
-}
-
-impl<'a> Default for Color<'a> {
-    fn default() -> Color<'a> {
-        Color::Rgb(0, 0, 0)
-    }
-}
-
-impl<'a> TryFrom<&Color<'a>> for Pixel {
-    type Error = anyhow::Error;
-
-    fn try_from(val: &Color) -> Result<Pixel> {
-        match val {
-            Color::Rgb(r, g, b) => Ok(pixel(*r, *g, *b, 255)),
-            Color::Rgba(r, g, b, a) => Ok(pixel(*r, *g, *b, *a)),
-            Color::RgbString(s) => parse_color(s),
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct FontDef<'a> {
     pub name: Cow<'a, str>,
@@ -48,25 +82,38 @@ pub struct FontDef<'a> {
 
 #[derive(Debug)]
 pub struct OverlayOptions<'a> {
-    pub background: image::DynamicImage,
+    pub background: Background<'a>,
+    /// The size of the card. If this is not set, the card is the size of the background.
+    pub size: Option<(u32, u32)>,
+    /// How the background is scaled to `size`.
+    pub background_fit: BackgroundFit<'a>,
+    /// Filters applied to the background before anything is drawn on it.
+    pub background_filters: &'a [Filter<'a>],
     pub blocks: &'a [Block<'a>],
+    pub images: &'a [ImageBlock<'a>],
+    pub shapes: &'a [ShapeBlock<'a>],
     pub fonts: &'a [FontDef<'a>],
+    /// Grain added to the whole card after everything else is drawn.
+    pub grain: Option<&'a Grain>,
+    /// Measure the contrast of each text run for `RenderInfo::text_contrast`. This costs an extra pass
+    /// over the card, so it's off unless the contrast is needed.
+    pub measure_contrast: bool,
+    /// Named colors that colors anywhere in the card can refer to as `"$name"`.
+    pub palette: Option<&'a Palette<'a>>,
+    /// Where to keep decoded images, to reuse them when rendering more cards. Without a cache, every
+    /// image is decoded each time it's drawn.
+    pub image_cache: Option<&'a ImageCache>,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum HAlign {
+    #[default]
     Left,
     Center,
     Right,
 }
 
-impl Default for HAlign {
-    fn default() -> HAlign {
-        HAlign::Left
-    }
-}
-
 impl From<HAlign> for glyph_brush_layout::HorizontalAlign {
     fn from(v: HAlign) -> glyph_brush_layout::HorizontalAlign {
         match v {
@@ -77,20 +124,15 @@ impl From<HAlign> for glyph_brush_layout::HorizontalAlign {
     }
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum VAlign {
+    #[default]
     Top,
     Center,
     Bottom,
 }
 
-impl Default for VAlign {
-    fn default() -> VAlign {
-        VAlign::Top
-    }
-}
-
 impl From<VAlign> for glyph_brush_layout::VerticalAlign {
     fn from(v: VAlign) -> glyph_brush_layout::VerticalAlign {
         match v {
@@ -101,19 +143,33 @@ impl From<VAlign> for glyph_brush_layout::VerticalAlign {
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct BlockBorder<'a> {
-    #[serde(default)]
-    pub width: u32,
-    #[serde(default)]
-    pub color: Color<'a>,
-    pub shadow: Option<Shadow<'a>>,
-}
-
 fn bool_true() -> bool {
     true
 }
 
+fn one() -> f32 {
+    1.0
+}
+
+/// Deserialize either a single value or an array of values.
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(v) => vec![v],
+        OneOrMany::Many(v) => v,
+    })
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Block<'a> {
     pub min_size: f32,
@@ -121,9 +177,24 @@ pub struct Block<'a> {
     pub text: Vec<Text<'a>>,
     pub rect: Rect,
     pub shadow: Option<Shadow<'a>>,
-    pub background: Option<Color<'a>>,
+    pub background: Option<Paint<'a>>,
+    /// Add grain to the block's background.
+    pub grain: Option<Grain>,
     pub border: Option<BlockBorder<'a>>,
     pub padding: Option<Rect>,
+    /// Round the corners of the block's background, border and border shadow.
+    pub radius: Option<Radius>,
+    /// Blur or otherwise filter the image behind the block before drawing it.
+    pub backdrop: Option<Backdrop<'a>>,
+    /// The opacity of the entire block, including its shadows, from 0 to 1.
+    #[serde(default = "one")]
+    pub opacity: f32,
+    /// How the block's colors combine with the image beneath it.
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    /// Elements with a higher `z_index` are drawn on top of those with a lower one.
+    #[serde(default)]
+    pub z_index: i32,
     // /// Wrap the text. Defaults to true
     #[serde(default = "bool_true")]
     pub wrap: bool,
@@ -132,9 +203,12 @@ pub struct Block<'a> {
     #[serde(default)]
     pub v_align: VAlign,
 
-    /// Text runs in a block that do not have their own color will inherit it from this color.
+    /// Text runs in a block that do not have their own color will inherit it from this color. Set it to
+    /// `"auto"` to choose whichever of the `auto_color` candidates contrasts best with the background.
     #[serde(default)]
-    pub color: Color<'a>,
+    pub color: TextColor<'a>,
+    /// The candidates and minimum contrast used when `color` is `"auto"`.
+    pub auto_color: Option<AutoColor<'a>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -160,6 +234,38 @@ pub struct Rect {
     pub right: u32,
 }
 
+/// The corner radius of a box, either the same for every corner or set separately for each one.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Radius {
+    Uniform(f32),
+    Corners {
+        #[serde(default)]
+        top_left: f32,
+        #[serde(default)]
+        top_right: f32,
+        #[serde(default)]
+        bottom_right: f32,
+        #[serde(default)]
+        bottom_left: f32,
+    },
+}
+
+impl Radius {
+    /// The radii in the order top left, top right, bottom right, bottom left.
+    fn corners(&self) -> [f32; 4] {
+        match *self {
+            Radius::Uniform(r) => [r; 4],
+            Radius::Corners {
+                top_left,
+                top_right,
+                bottom_right,
+                bottom_left,
+            } => [top_left, top_right, bottom_right, bottom_left],
+        }
+    }
+}
+
 fn pt_size_to_px_scale<F: Font>(font: &F, pt_size: f32, screen_scale_factor: f32) -> PxScale {
     let px_per_em = pt_size * screen_scale_factor * (96.0 / 72.0);
     let units_per_em = font.units_per_em().unwrap();
@@ -167,12 +273,22 @@ fn pt_size_to_px_scale<F: Font>(font: &F, pt_size: f32, screen_scale_factor: f32
     PxScale::from(px_per_em * height / units_per_em)
 }
 
+/// A line of text runs, each with the index of the block run it came from, and the glyphs laid out for
+/// it.
+type FittedLine<'a> = (Vec<(usize, Cow<'a, Text<'a>>)>, Vec<SectionGlyph>);
+
+/// Lay out the block's text in `rect`, and return the lines along with the font size that was chosen.
 fn fit_glyphs<'a>(
     fonts: &[FontDef],
     rect: &Rect,
     options: &'a Block,
-) -> Result<Vec<(Vec<Cow<'a, Text<'a>>>, Vec<SectionGlyph>)>> {
-    println!("Rect {:?}", rect);
+) -> Result<(Vec<FittedLine<'a>>, f32)> {
+    // Runs can be empty, for example when a variable expands to nothing. Those have no glyphs to fit, so
+    // they're left out, and a block with nothing but empty runs has no lines.
+    if options.text.iter().all(|t| t.text.is_empty()) {
+        return Ok((Vec::new(), options.min_size));
+    }
+
     let text_width = rect.right - rect.left;
     let text_height = rect.bottom - rect.top;
 
@@ -189,7 +305,13 @@ fn fit_glyphs<'a>(
         };
 
         // Just a single line here and the layout algorithm will handle the wrapping.
-        let text = options.text.iter().map(Cow::Borrowed).collect::<Vec<_>>();
+        let text = options
+            .text
+            .iter()
+            .map(Cow::Borrowed)
+            .enumerate()
+            .filter(|(_, t)| !t.text.is_empty())
+            .collect::<Vec<_>>();
         let lines = vec![text];
 
         (layout, lines, options.max_size)
@@ -206,20 +328,21 @@ fn fit_glyphs<'a>(
 
         let mut lines = vec![];
         let mut current_line = Vec::new();
-        for text in &options.text {
+        for (run_index, text) in options.text.iter().enumerate() {
             let mut last_index = 0;
-            println!("Text {}", text.text);
             for index in line_breaker.line_breaks(&text.text) {
                 if let glyph_brush_layout::LineBreak::Hard(offset) = index {
-                    println!("Break at offset {}", offset);
                     let t = text.text[last_index..offset].trim_matches('\n');
 
                     if !t.is_empty() {
-                        current_line.push(Cow::Owned(Text {
-                            text: Cow::from(t),
-                            font: text.font.clone(),
-                            color: text.color.clone(),
-                        }));
+                        current_line.push((
+                            run_index,
+                            Cow::Owned(Text {
+                                text: Cow::from(t),
+                                font: text.font.clone(),
+                                color: text.color.clone(),
+                            }),
+                        ));
                     }
                     lines.push(current_line);
                     current_line = Vec::new();
@@ -228,15 +351,20 @@ fn fit_glyphs<'a>(
             }
 
             if last_index == 0 {
-                current_line.push(Cow::Borrowed(text));
+                if !text.text.is_empty() {
+                    current_line.push((run_index, Cow::Borrowed(text)));
+                }
             } else if last_index < text.text.len() {
                 let t = text.text[last_index..].trim_matches('\n');
                 if !t.is_empty() {
-                    current_line.push(Cow::Owned(Text {
-                        text: Cow::from(t),
-                        font: text.font.clone(),
-                        color: text.color.clone(),
-                    }));
+                    current_line.push((
+                        run_index,
+                        Cow::Owned(Text {
+                            text: Cow::from(t),
+                            font: text.font.clone(),
+                            color: text.color.clone(),
+                        }),
+                    ));
                 }
             }
         }
@@ -273,13 +401,13 @@ fn fit_glyphs<'a>(
         .map(|line| {
             let sections = line
                 .iter()
-                .map(|t| {
+                .map(|(_, t)| {
                     Ok(SectionText {
                         text: &t.text,
                         font_id: fonts
                             .iter()
                             .position(|f| f.name == t.font)
-                            .map(|index| FontId(index))
+                            .map(FontId)
                             .ok_or_else(|| anyhow!("Could not find font named {}", t.font))?,
                         scale: PxScale::from(0.0), // This will be filled in below
                     })
@@ -289,7 +417,6 @@ fn fit_glyphs<'a>(
             Ok(sections)
         })
         .collect::<Result<Vec<_>>>()?;
-    println!("Sections {:?}", line_sections);
 
     let font_refs = fonts.iter().map(|f| &f.font).collect::<Vec<_>>();
     for sections in line_sections.as_mut_slice().iter_mut() {
@@ -302,43 +429,43 @@ fn fit_glyphs<'a>(
 
         let text_length = sections
             .iter()
-            .fold(0, |acc, section| acc + section.text.len());
-        let last_section_byte_index = sections.last().unwrap().text.len() - 1;
+            .fold(0, |acc, section| acc + section.text.chars().count());
+        // The byte index of the start of the last character, which may take more than one byte.
+        let last_section_byte_index = sections
+            .last()
+            .unwrap()
+            .text
+            .char_indices()
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(0);
         while font_size >= options.min_size {
             // println!("Trying font size {font_size}", font_size = font_size);
             for i in sections.iter_mut() {
                 i.scale = pt_size_to_px_scale(&font_refs.as_slice()[i.font_id], font_size, 1.0);
             }
 
-            let glyphs = layout.calculate_glyphs(font_refs.as_slice(), &geometry, &sections);
+            let glyphs = layout.calculate_glyphs(font_refs.as_slice(), &geometry, sections);
 
             let fits = if options.wrap {
                 // When wrapping, the text fits if it doesn't exceed the vertical size available.
                 // calculate_glyphs handles fitting the text horizontally.
-                let last_glyph = glyphs.last().unwrap();
-                println!(
-                    "size {}, {} sections, {:?}",
-                    font_size,
-                    sections.len(),
-                    last_glyph
-                );
-                let text_bottom = last_glyph.glyph.position.y;
-                last_glyph.section_index == sections.len() - 1
-                    && last_glyph.byte_index == last_section_byte_index
-                    && text_bottom < rect.bottom as f32
+                match glyphs.last() {
+                    Some(last_glyph) => {
+                        let text_bottom = last_glyph.glyph.position.y;
+                        last_glyph.section_index == sections.len() - 1
+                            && last_glyph.byte_index == last_section_byte_index
+                            && text_bottom < rect.bottom as f32
+                    }
+                    // Nothing was laid out, so there's nothing that can overflow.
+                    None => true,
+                }
             } else {
                 // In non-wrapping mode, a line fits if we can render all of its glyphs.
-                println!(
-                    "size {} rendered {} glyphs out of {}",
-                    font_size,
-                    glyphs.len(),
-                    text_length
-                );
                 glyphs.len() == text_length
             };
 
             if fits {
-                println!("Chose font size {}", font_size);
                 break;
             } else {
                 font_size -= 4.0;
@@ -351,9 +478,13 @@ fn fit_glyphs<'a>(
     }
 
     // Go back through and render all the lines with the chosen font size.
-    let sizing_font_id = line_sections[0][0].font_id;
+    let sizing_font_id = match line_sections.iter().flatten().next() {
+        Some(section) => section.font_id,
+        // Only line breaks, so there's nothing to draw.
+        None => return Ok((Vec::new(), font_size)),
+    };
     let sizing_font = &font_refs.as_slice()[sizing_font_id];
-    let line_height = pt_size_to_px_scale(&sizing_font, font_size, 1.0);
+    let line_height = pt_size_to_px_scale(sizing_font, font_size, 1.0);
     let result_glyphs = line_sections
         .into_iter()
         .enumerate()
@@ -373,241 +504,682 @@ fn fit_glyphs<'a>(
         .collect::<Vec<_>>();
 
     // And return each line's glyphs with the line that configured it.
-    let result = lines
-        .into_iter()
-        .zip(result_glyphs.into_iter())
-        .collect::<Vec<_>>();
+    let result = lines.into_iter().zip(result_glyphs).collect::<Vec<_>>();
 
-    Ok(result)
+    Ok((result, font_size))
 }
 
-fn blend(dest: Pixel, src: Pixel, src_alpha: f32) -> Pixel {
+/// Draw `src` over `dest`, with the source's alpha scaled by `coverage`.
+fn blend(dest: Pixel, src: Pixel, coverage: f32) -> Pixel {
+    let src_alpha = (src[3] as f32 / 255.0) * coverage;
     if src_alpha >= 1.0 {
         return src;
     }
 
+    let dest_alpha = (dest[3] as f32 / 255.0) * (1.0 - src_alpha);
+    let out_alpha = src_alpha + dest_alpha;
+    if out_alpha <= 0.0 {
+        return TRANSPARENT;
+    }
+
+    let channel = |i: usize| {
+        ((src[i] as f32 * src_alpha + dest[i] as f32 * dest_alpha) / out_alpha).round() as u8
+    };
+
     pixel(
-        ((dest[0] as f32) * (1.0 - src_alpha) + (src[0] as f32) * src_alpha) as u8,
-        ((dest[1] as f32) * (1.0 - src_alpha) + (src[1] as f32) * src_alpha) as u8,
-        ((dest[2] as f32) * (1.0 - src_alpha) + (src[2] as f32) * src_alpha) as u8,
-        ((dest[3] as f32) * (1.0 - src_alpha) + (src_alpha * 255.0)) as u8,
+        channel(0),
+        channel(1),
+        channel(2),
+        (out_alpha * 255.0).round() as u8,
     )
 }
 
-fn parse_color(color: &str) -> Result<Pixel> {
-    let hex = if color.starts_with('#') {
-        &color[1..]
-    } else {
-        color
-    };
+/// Combine two colors that each cover a separate part of the same pixel, such as the inside edge of a
+/// border and the background next to it.
+fn mix(a: Pixel, a_coverage: f32, b: Pixel, b_coverage: f32) -> Pixel {
+    let a_alpha = (a[3] as f32 / 255.0) * a_coverage;
+    let b_alpha = (b[3] as f32 / 255.0) * b_coverage;
+    let out_alpha = (a_alpha + b_alpha).min(1.0);
+    if out_alpha <= 0.0 {
+        return TRANSPARENT;
+    }
+
+    let channel =
+        |i: usize| ((a[i] as f32 * a_alpha + b[i] as f32 * b_alpha) / out_alpha).round() as u8;
 
-    let mut color = u32::from_str_radix(color, 16).context("color")?;
-    let mut alpha: u8 = 255;
-    if hex.len() == 8 {
-        alpha = (color & 0xFF) as u8;
-        color = color >> 8;
+    pixel(
+        channel(0),
+        channel(1),
+        channel(2),
+        (out_alpha * 255.0).round() as u8,
+    )
+}
+
+/// Something drawn on the card.
+#[derive(Clone, Copy)]
+enum Element<'a> {
+    Shape(&'a ShapeBlock<'a>),
+    Image(&'a ImageBlock<'a>),
+    /// A text block and its index in `OverlayOptions::blocks`.
+    Text((usize, &'a Block<'a>)),
+}
+
+impl<'a> Element<'a> {
+    fn z_index(&self) -> i32 {
+        match self {
+            Element::Shape(s) => s.z_index,
+            Element::Image(i) => i.z_index,
+            Element::Text((_, b)) => b.z_index,
+        }
     }
+}
 
-    if hex.len() == 6 || hex.len() == 8 {
-        let red: u8 = ((color >> 16) & 0xFF) as u8;
-        let green: u8 = ((color >> 8) & 0xFF) as u8;
-        let blue: u8 = ((color) & 0xFF) as u8;
+/// Details about how a card was rendered.
+#[derive(Debug, Default)]
+pub struct RenderInfo {
+    /// The part of the background image that was used, if it was cropped to fit the card.
+    pub background_crop: Option<CropWindow>,
+    /// The colors picked from the background, which colors in the card can refer to as `"@vibrant"` and
+    /// so on. They are only picked if some color in the card refers to them.
+    pub swatches: Option<Swatches>,
+    /// The contrast of each text run against what's around it on the finished card. Only measured when
+    /// `OverlayOptions::measure_contrast` is set.
+    pub text_contrast: Vec<TextContrast>,
+}
 
-        Ok(pixel(red, green, blue, alpha))
-    } else {
-        Err(anyhow!("Color must be 6 or 8 hex digits"))
+impl RenderInfo {
+    /// The text runs that don't have enough contrast to meet the WCAG level.
+    pub fn contrast_failures(&self, level: WcagLevel) -> Vec<&TextContrast> {
+        self.text_contrast
+            .iter()
+            .filter(|c| !c.passes(level))
+            .collect()
     }
 }
 
 // TODO Proper library errors instead of anyhow
 pub fn overlay_text(options: &OverlayOptions) -> Result<ImageBuffer<Pixel, Vec<u8>>> {
-    let mut bg = options.background.to_rgba8();
+    overlay_text_with_info(options).map(|(image, _)| image)
+}
+
+/// Render the card like `overlay_text`, and also return details about how it was rendered.
+pub fn overlay_text_with_info(options: &OverlayOptions) -> Result<(RgbaImage, RenderInfo)> {
+    check_references(options, options.palette)?;
+
+    let mut info = RenderInfo::default();
+    // The background can't use swatches, because they are picked from it.
+    let colors = ColorContext {
+        swatches: None,
+        palette: options.palette,
+    };
+    let mut bg = options
+        .background
+        .render(options.size, &colors, options.image_cache)?;
+    if let Some((width, height)) = options.size {
+        let (fitted, crop) =
+            fit::fit_background(&bg, width, height, &options.background_fit, &colors)?;
+        bg = fitted;
+        info.background_crop = crop;
+    }
+
+    // The swatches come from the fitted background, so that they match the part of it that's visible.
+    // They are only picked if something drawn on the card refers to them.
+    let swatches = uses_swatches(options).then(|| extract_swatches(&bg));
+    let colors = ColorContext {
+        swatches: swatches.as_ref(),
+        ..colors
+    };
+    draw_card(options, &mut bg, &mut info, &colors)?;
+    info.swatches = swatches;
+
+    Ok((bg, info))
+}
+
+/// Whether any of the filters or elements drawn after the background refer to a swatch, directly or
+/// through the palette.
+fn uses_swatches(options: &OverlayOptions) -> bool {
+    let mut found = false;
+    let mut check = |color: &Color| found |= color.uses_swatches(options.palette);
+    options.background_filters.each_color(&mut check);
+    options.shapes.each_color(&mut check);
+    options.images.each_color(&mut check);
+    options.blocks.each_color(&mut check);
+    found
+}
+
+/// Check every palette reference in `colors` up front, so that unknown names and palette colors that refer
+/// to themselves are reported even for colors the card never ends up drawing.
+fn check_references(colors: &dyn EachColor, palette: Option<&Palette>) -> Result<()> {
+    let mut result = Ok(());
+    colors.each_color(&mut |color| {
+        if let (Color::RgbString(s), Ok(())) = (color, &result) {
+            result = color::check_reference(s, palette);
+        }
+    });
+    result
+}
+
+/// Filter the background and draw everything on it.
+fn draw_card(
+    options: &OverlayOptions,
+    bg: &mut RgbaImage,
+    info: &mut RenderInfo,
+    colors: &ColorContext,
+) -> Result<()> {
+    filter::apply_filters(bg, options.background_filters, colors)?;
+
+    // Draw lower z-indexes first. Elements with the same z-index are drawn in the order they're listed, with
+    // shapes first, then images, then text blocks.
+    let mut elements = options
+        .shapes
+        .iter()
+        .map(Element::Shape)
+        .chain(options.images.iter().map(Element::Image))
+        .chain(options.blocks.iter().enumerate().map(Element::Text))
+        .collect::<Vec<_>>();
+    elements.sort_by_key(|e| e.z_index());
+
+    let mut coverage = if options.measure_contrast {
+        Some(TextCoverage::new(bg.width(), bg.height()))
+    } else {
+        None
+    };
+    for element in elements {
+        match element {
+            Element::Shape(shape) => shape::draw_shape(bg, shape, colors)?,
+            Element::Image(image) => {
+                image_block::draw_image_block(bg, image, colors, options.image_cache)?
+            }
+            Element::Text((index, block)) => {
+                draw_block(bg, block, index, options.fonts, coverage.as_mut(), colors)?
+            }
+        }
+    }
+
+    if let Some(grain) = options.grain {
+        grain::apply_grain(bg, grain, None);
+    }
+
+    // Measure the contrast once everything is drawn, so that it includes elements drawn over the text and
+    // the grain.
+    if let Some(coverage) = coverage {
+        info.text_contrast = coverage.measure(bg);
+    }
+
+    Ok(())
+}
+
+/// Draw a text block, and record where its glyphs are in `coverage` if the contrast is being measured.
+fn draw_block(
+    bg: &mut RgbaImage,
+    block: &Block,
+    block_index: usize,
+    fonts: &[FontDef],
+    mut coverage: Option<&mut TextCoverage>,
+    colors: &ColorContext,
+) -> Result<()> {
     let (width, height) = bg.dimensions();
+    let font_refs = fonts.iter().map(|f| &f.font).collect::<Vec<_>>();
+
+    let shadow_color = block
+        .shadow
+        .as_ref()
+        .and_then(|s| s.color.as_ref())
+        .map(|c| c.resolve(colors))
+        .transpose()?
+        .unwrap_or(DEFAULT_SHADOW_COLOR);
+
+    let block_box = BlockBox::new(
+        &block.rect,
+        block.border.as_ref(),
+        block.radius,
+        (width, height),
+        colors,
+    )?;
+    let box_shadows = block
+        .border
+        .as_ref()
+        .map(|b| b.shadow.as_slice())
+        .unwrap_or_default();
+    // Everything in the block is drawn to this layer, so that the block's opacity and blend mode
+    // apply to all of it at once.
+    let mut layer = image::RgbaImage::new(width, height);
+    shadow::draw_outer_shadows(&mut layer, box_shadows, &block_box.border_box, colors)?;
+
+    if let Some(backdrop) = block.backdrop.as_ref() {
+        backdrop::apply_backdrop(bg, backdrop, &block_box.border_box, colors)?;
+    }
 
-    let font_refs = options.fonts.iter().map(|f| &f.font).collect::<Vec<_>>();
-    const DEFAULT_SHADOW_COLOR: Pixel = pixel(0, 0, 0, 25);
-    const TRANSPARENT: Pixel = pixel(0, 0, 0, 0);
-
-    for block in options.blocks {
-        let mut rect = block.rect.clone();
-        if rect.left > width || rect.right > width || rect.top > height || rect.bottom > height {
-            return Err(anyhow!(
-                "Text rect {rect:?} does not fit in image of size {width}x{height}",
-                rect = rect,
-                width = width,
-                height = height
-            ));
-        } else if rect.left >= rect.right || rect.top > rect.bottom {
-            return Err(anyhow!("rect must not have a negative size"));
+    let bg_painter = block
+        .background
+        .as_ref()
+        .map(|b| b.painter(&block_box.padding_box, colors))
+        .transpose()?;
+    let mut text_image = block_box.render(width, height, bg_painter.as_ref());
+    if let Some(grain) = block.grain.as_ref() {
+        grain::apply_grain(&mut text_image, grain, Some(&block_box.padding_box));
+    }
+    shadow::draw_inset_shadows(&mut text_image, box_shadows, &block_box.padding_box, colors)?;
+
+    let mut shadow_image = block
+        .shadow
+        .as_ref()
+        .map(|s| (s, image::RgbaImage::new(width, height)));
+
+    let mut rect = block_box.inside_border(&block.rect);
+
+    if let Some(padding) = block.padding.as_ref() {
+        rect.left += padding.left;
+        rect.right -= padding.right;
+        rect.top += padding.top;
+        rect.bottom -= padding.bottom;
+    }
+
+    // A block without any text still draws its box and shadows, so keep going even if there are no lines.
+    let (lines, font_size) = fit_glyphs(fonts, &rect, block)?;
+
+    let block_color = match &block.color {
+        TextColor::Color(color) => color.resolve(colors)?,
+        TextColor::Auto => {
+            let default_auto = AutoColor::default();
+            let auto = block.auto_color.as_ref().unwrap_or(&default_auto);
+            let chosen = auto_color::choose_color(bg, &text_image, block, &rect, auto, colors)?;
+            if let Some((color, opacity)) = chosen.scrim {
+                auto_color::draw_scrim(&mut text_image, &block_box.padding_box, color, opacity);
+            }
+            chosen.color
+        }
+    };
+
+    let lines_bottom = lines
+        .last()
+        .and_then(|(_, glyphs)| glyphs.last())
+        .map(|g| g.glyph.position.y)
+        .unwrap_or(rect.bottom as f32);
+    let first_glyph = lines.iter().find_map(|(_, glyphs)| glyphs.first());
+    let start_y = match (block.v_align, first_glyph) {
+        (_, None) | (VAlign::Top, _) => 0,
+        (VAlign::Center, Some(first_glyph)) => {
+            let rect_height = rect.bottom - rect.top;
+            let lines_top = first_glyph.glyph.position.y - first_glyph.glyph.scale.y;
+            (rect_height / 2) - (((lines_bottom - lines_top - 1.0) / 2.0) as u32)
         }
+        (VAlign::Bottom, Some(_)) => rect.bottom - (lines_bottom as u32),
+    };
+
+    if let Some(coverage) = coverage.as_deref_mut() {
+        coverage.start_block(block_index, &block.text, font_size);
+    }
 
-        let shadow_color = block
-            .shadow
-            .as_ref()
-            .and_then(|s| s.color.as_ref())
-            .map(|s| Pixel::try_from(s))
-            .transpose()?
-            .unwrap_or(DEFAULT_SHADOW_COLOR);
-
-        let border_pixel = block
-            .border
-            .as_ref()
-            .map(|b| Pixel::try_from(&b.color))
-            .transpose()?
-            .unwrap_or_else(|| pixel(0, 0, 0, 255));
-        let border_width = block.border.as_ref().map(|b| b.width).unwrap_or(0);
-
-        if let Some(s) = block.border.as_ref().and_then(|b| b.shadow.as_ref()) {
-            let shadow_bg_pixel = s
+    for (texts, glyphs) in lines {
+        for glyph in glyphs {
+            // println!("{:?}", glyph);
+            let (run_index, run) = &texts[glyph.section_index];
+            let color = run
                 .color
                 .as_ref()
-                .map(Pixel::try_from)
+                .map(|c| c.resolve(colors))
                 .transpose()?
-                .unwrap_or(DEFAULT_SHADOW_COLOR);
-            let shadow_top = rect.top + s.y;
-            let shadow_bottom = rect.bottom + s.y;
-            let shadow_left = rect.left + s.x;
-            let shadow_right = rect.right + s.x;
-
-            let shadow_bg_image = image::RgbaImage::from_fn(width, height, |x, y| {
-                if y >= shadow_top && y <= shadow_bottom && x >= shadow_left && x <= shadow_right {
-                    shadow_bg_pixel
-                } else {
-                    TRANSPARENT
-                }
-            });
-
-            let mut shadow_bg_image = s
-                .blur
-                .map(|blur_sigma| image::imageops::blur(&shadow_bg_image, blur_sigma))
-                .unwrap_or(shadow_bg_image);
-
-            // The shadow should not show through if the block is transparent, so clear out all the pixels for the
-            // block's rect.
-            let bg_placeholder = image::RgbaImage::from_pixel(
-                rect.right - rect.left + 1,
-                rect.bottom - rect.top + 1,
-                TRANSPARENT,
-            );
-            image::imageops::replace(&mut shadow_bg_image, &bg_placeholder, rect.left, rect.top);
-
-            image::imageops::overlay(&mut bg, &shadow_bg_image, 0, 0);
-        }
+                .unwrap_or(block_color);
+            let glyph_font = &font_refs.as_slice()[glyph.font_id];
+            if let Some(g) = glyph_font.outline_glyph(glyph.glyph) {
+                // println!("{:?}", g.px_bounds());
+                let r = g.px_bounds();
+                let x_base = r.min.x as u32;
+                let y_base = start_y + r.min.y as u32;
+                g.draw(|x, y, c| {
+                    // println!("{x}, {y}, {c}", x = x, y = y, c = c);
+                    let (px, py) = (x_base + x, y_base + y);
+                    let dest = text_image.get_pixel_mut(px, py);
+                    *dest = blend(*dest, color, c);
+                    if let Some(coverage) = coverage.as_deref_mut() {
+                        coverage.add(px, py, *run_index, c);
+                    }
 
-        let bg_pixel = block
-            .background
-            .as_ref()
-            .map(Pixel::try_from)
-            .transpose()?
-            .unwrap_or_else(|| pixel(0, 0, 0, 0));
-        let border_left = rect.left + border_width;
-        let border_right = rect.right - border_width;
-        let border_top = rect.top + border_width;
-        let border_bottom = rect.bottom - border_width;
-        let mut text_image = image::RgbaImage::from_fn(width, height, |x, y| {
-            if x < rect.left || x > rect.right || y < rect.top || y > rect.bottom {
-                TRANSPARENT
-            } else if x < border_left || x > border_right || y < border_top || y > border_bottom {
-                border_pixel
-            } else {
-                bg_pixel
+                    if let Some((s, i)) = shadow_image.as_mut() {
+                        let shadow_x = x_base + x + s.x;
+                        let shadow_y = y_base + y + s.y;
+                        if i.in_bounds(shadow_x, shadow_y) {
+                            let pixel = if c < 1.0 {
+                                let mut p = shadow_color;
+                                p[3] = ((p[3] as f32) * c) as u8;
+                                p
+                            } else {
+                                color
+                            };
+
+                            i.put_pixel(shadow_x, shadow_y, pixel);
+                        }
+                    }
+                })
             }
-        });
-        let mut shadow_image = block
-            .shadow
-            .as_ref()
-            .map(|s| (s, image::RgbaImage::new(width, height)));
-
-        if let Some(border) = block.border.as_ref() {
-            rect.left += border.width;
-            rect.right -= border.width;
-            rect.top += border.width;
-            rect.bottom -= border.width;
         }
+    }
 
-        if let Some(padding) = block.padding.as_ref() {
-            rect.left += padding.left;
-            rect.right -= padding.right;
-            rect.top += padding.top;
-            rect.bottom -= padding.bottom;
-        }
+    if let Some((s, i)) = shadow_image {
+        let i = s
+            .blur
+            .map(|blur_sigma| image::imageops::blur(&i, blur_sigma))
+            .unwrap_or(i);
+        image::imageops::overlay(&mut layer, &i, 0, 0);
+    }
 
-        let lines = fit_glyphs(&options.fonts, &rect, block)?;
-        if lines.is_empty() {
-            continue;
+    image::imageops::overlay(&mut layer, &text_image, 0, 0);
+    blend_mode::composite(bg, &layer, 0, 0, block.blend_mode, block.opacity);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_without_text_still_draw_their_box() {
+        let blocks: Vec<Block> = toml::from_str::<toml::Value>(
+            r#"
+            [[blocks]]
+            min_size = 10
+            max_size = 20
+            text = []
+            rect = { left = 10, top = 10, right = 29, bottom = 29 }
+            background = [0, 0, 255]
+            border = { width = 2, color = [255, 0, 0], shadow = [
+                { x = 4, y = 4, color = [0, 0, 0] },
+                { spread = 5, inset = true, color = [0, 255, 0] },
+            ] }
+            "#,
+        )
+        .unwrap()["blocks"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        let image = overlay_text(&OverlayOptions {
+            background: image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                40,
+                40,
+                pixel(255, 255, 255, 255),
+            ))
+            .into(),
+            size: None,
+            background_fit: BackgroundFit::default(),
+            background_filters: &[],
+            blocks: &blocks,
+            images: &[],
+            shapes: &[],
+            fonts: &[],
+            grain: None,
+            measure_contrast: false,
+            palette: None,
+            image_cache: None,
+        })
+        .unwrap();
+
+        assert_eq!(*image.get_pixel(5, 5), pixel(255, 255, 255, 255));
+        assert_eq!(*image.get_pixel(32, 32), pixel(0, 0, 0, 255));
+        assert_eq!(*image.get_pixel(10, 20), pixel(255, 0, 0, 255));
+        assert_eq!(*image.get_pixel(13, 20), pixel(0, 255, 0, 255));
+        assert_eq!(*image.get_pixel(20, 20), pixel(0, 0, 255, 255));
+    }
+
+    #[test]
+    fn runs_that_expand_to_nothing_are_skipped() {
+        let variables = std::iter::once(("subtitle".to_string(), String::new())).collect();
+        for wrap in [true, false] {
+            let config = expand_variables(
+                &format!(
+                    r#"
+                    [[blocks]]
+                    min_size = 10
+                    max_size = 20
+                    wrap = {}
+                    text = [{{ text = "{{{{subtitle}}}}", font = "body" }}]
+                    rect = {{ left = 0, top = 0, right = 19, bottom = 19 }}
+                    background = [0, 0, 255]
+                    "#,
+                    wrap
+                ),
+                &variables,
+            )
+            .unwrap();
+            let blocks: Vec<Block> = toml::from_str::<toml::Value>(&config).unwrap()["blocks"]
+                .clone()
+                .try_into()
+                .unwrap();
+
+            let image = overlay_text(&OverlayOptions {
+                background: image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                    20,
+                    20,
+                    pixel(255, 255, 255, 255),
+                ))
+                .into(),
+                size: None,
+                background_fit: BackgroundFit::default(),
+                background_filters: &[],
+                blocks: &blocks,
+                images: &[],
+                shapes: &[],
+                fonts: &[],
+                grain: None,
+                measure_contrast: false,
+                palette: None,
+                image_cache: None,
+            })
+            .unwrap();
+            assert_eq!(*image.get_pixel(10, 10), pixel(0, 0, 255, 255));
         }
+    }
 
-        let lines_bottom = lines
-            .last()
+    #[test]
+    fn radius_can_be_set_per_corner() {
+        let radius: Radius = toml::Value::Float(4.0).try_into().unwrap();
+        assert_eq!(radius.corners(), [4.0; 4]);
+
+        let radius: Radius = toml::from_str("top_left = 8\nbottom_right = 2.5").unwrap();
+        assert_eq!(radius.corners(), [8.0, 0.0, 2.5, 0.0]);
+    }
+
+    #[test]
+    fn swatches_are_only_picked_when_used() {
+        let block = |background: &str| -> Vec<Block> {
+            toml::from_str::<toml::Value>(&format!(
+                r#"
+                [[blocks]]
+                min_size = 10
+                max_size = 20
+                text = []
+                rect = {{ left = 0, top = 0, right = 9, bottom = 9 }}
+                background = "{}"
+                "#,
+                background
+            ))
+            .unwrap()["blocks"]
+                .clone()
+                .try_into()
+                .unwrap()
+        };
+        let palette: Palette = toml::from_str(r#"accent = "@vibrant""#).unwrap();
+
+        let render = |blocks: &[Block]| {
+            overlay_text_with_info(&OverlayOptions {
+                background: image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                    20,
+                    20,
+                    pixel(220, 30, 40, 255),
+                ))
+                .into(),
+                size: None,
+                background_fit: BackgroundFit::default(),
+                background_filters: &[],
+                blocks,
+                images: &[],
+                shapes: &[],
+                fonts: &[],
+                grain: None,
+                measure_contrast: false,
+                palette: Some(&palette),
+                image_cache: None,
+            })
             .unwrap()
-            .1
-            .last()
-            .map(|g| g.glyph.position.y)
-            .unwrap_or(rect.bottom as f32);
-        let start_y = match block.v_align {
-            VAlign::Top => 0,
-            VAlign::Center => {
-                let first_glyph = &lines[0].1[0];
-                let rect_height = rect.bottom - rect.top;
-                let lines_top = first_glyph.glyph.position.y - first_glyph.glyph.scale.y;
-                (rect_height / 2) - (((lines_bottom - lines_top - 1.0) / 2.0) as u32)
-            }
-            VAlign::Bottom => rect.bottom - (lines_bottom as u32),
         };
-        println!("start_y: {}", start_y);
-
-        for (texts, glyphs) in lines {
-            for glyph in glyphs {
-                // println!("{:?}", glyph);
-                let run = &texts[glyph.section_index];
-                let color = Pixel::try_from(run.color.as_ref().unwrap_or(&block.color))?;
-                let glyph_font = &font_refs.as_slice()[glyph.font_id];
-                if let Some(g) = glyph_font.outline_glyph(glyph.glyph) {
-                    // println!("{:?}", g.px_bounds());
-                    let r = g.px_bounds();
-                    let x_base = r.min.x as u32;
-                    let y_base = start_y + r.min.y as u32;
-                    g.draw(|x, y, c| {
-                        // println!("{x}, {y}, {c}", x = x, y = y, c = c);
-                        let pixel = if c < 1.0 {
-                            let mut p = color.clone();
-                            p[3] = ((p[3] as f32) * c) as u8;
-                            blend(bg_pixel, p, c)
-                        } else {
-                            color
-                        };
-                        text_image.put_pixel(x_base + x, y_base + y, pixel);
-
-                        if let Some((s, i)) = shadow_image.as_mut() {
-                            let shadow_x = x_base + x + s.x;
-                            let shadow_y = y_base + y + s.y;
-                            if i.in_bounds(shadow_x, shadow_y) {
-                                let pixel = if c < 1.0 {
-                                    let mut p = shadow_color.clone();
-                                    p[3] = ((p[3] as f32) * c) as u8;
-                                    p
-                                } else {
-                                    color
-                                };
-
-                                i.put_pixel(shadow_x, shadow_y, pixel);
-                            }
-                        }
-                    })
+
+        let (image, info) = render(&block("@vibrant"));
+        assert_eq!(info.swatches.unwrap().vibrant, pixel(220, 30, 40, 255));
+        assert_eq!(*image.get_pixel(5, 5), pixel(220, 30, 40, 255));
+
+        // Swatches used through the palette count too.
+        let (image, info) = render(&block("$accent"));
+        assert!(info.swatches.is_some());
+        assert_eq!(*image.get_pixel(5, 5), pixel(220, 30, 40, 255));
+
+        let (_, info) = render(&[]);
+        assert!(info.swatches.is_none());
+    }
+
+    #[test]
+    fn swatch_references_are_found_in_every_element() {
+        let swatch_colors = |config: &str| {
+            let value = toml::from_str::<toml::Value>(config).unwrap();
+            let mut found = Vec::new();
+            let mut check = |color: &Color| {
+                if color.uses_swatches(None) {
+                    found.push(format!("{:?}", color));
                 }
+            };
+            if let Some(blocks) = value.get("blocks") {
+                let blocks: Vec<Block> = blocks.clone().try_into().unwrap();
+                blocks.each_color(&mut check);
             }
-        }
-
-        if let Some((s, i)) = shadow_image {
-            let i = s
-                .blur
-                .map(|blur_sigma| image::imageops::blur(&i, blur_sigma))
-                .unwrap_or(i);
-            image::imageops::overlay(&mut bg, &i, 0, 0);
-        }
+            if let Some(shapes) = value.get("shapes") {
+                let shapes: Vec<ShapeBlock> = shapes.clone().try_into().unwrap();
+                shapes.each_color(&mut check);
+            }
+            if let Some(images) = value.get("images") {
+                let images: Vec<ImageBlock> = images.clone().try_into().unwrap();
+                images.each_color(&mut check);
+            }
+            if let Some(filters) = value.get("filters") {
+                let filters: Vec<Filter> = filters.clone().try_into().unwrap();
+                filters.each_color(&mut check);
+            }
+            found.len()
+        };
 
-        image::imageops::overlay(&mut bg, &text_image, 0, 0);
+        let block =
+            "min_size = 10\nmax_size = 20\nrect = { left = 0, top = 0, right = 9, bottom = 9 }";
+        assert_eq!(
+            swatch_colors(&format!(
+                r#"
+                [[blocks]]
+                {}
+                text = [{{ font = "a", text = "a", color = "@light" }}]
+                shadow = {{ x = 1, y = 1, color = "@dark" }}
+                background = {{ type = "linear", stops = ["ff0000", {{ color = "@muted", position = 1 }}] }}
+                border = {{ color = "@dark", left = {{ color = "@vibrant" }}, shadow = {{ color = "@dark" }} }}
+                backdrop = {{ tint = "@muted" }}
+                color = "@light"
+                auto_color = {{ candidates = ["@light", "000000"], scrim = {{ color = "@dark" }} }}
+                "#,
+                block
+            )),
+            10
+        );
+        assert_eq!(
+            swatch_colors(&format!(
+                "[[blocks]]\n{}\ntext = []\ncolor = \"auto\"\nbackground = \"ff0000\"",
+                block
+            )),
+            0
+        );
+        assert_eq!(
+            swatch_colors(
+                r#"
+                [[shapes]]
+                type = "line"
+                from = [0, 0]
+                to = [1, 1]
+                fill = "@muted"
+                stroke = { width = 1, color = "@dark" }
+                shadow = { color = "@dark" }
+
+                [[images]]
+                path = "logo.svg"
+                rect = { left = 0, top = 0, right = 9, bottom = 9 }
+                color = "@light"
+                border = { color = "@vibrant" }
+
+                [[filters]]
+                type = "duotone"
+                shadows = "@dark"
+                highlights = "@light"
+
+                [[filters]]
+                type = "vignette"
+                color = "@dark"
+
+                [[filters]]
+                type = "overlay"
+                color = "@muted"
+
+                [[filters]]
+                type = "blur"
+                sigma = 2
+                "#
+            ),
+            9
+        );
     }
 
-    Ok(bg)
+    #[test]
+    fn only_colors_are_checked_for_palette_references() {
+        let grain: Grain = toml::from_str(r#"seed = "$title""#).unwrap();
+        let check = |config: &str| {
+            let value: toml::Value = toml::from_str(config).unwrap();
+            let palette: Palette = value["palette"].clone().try_into().unwrap();
+            let background: GeneratedBackground = value["background"].clone().try_into().unwrap();
+            let background_fit: BackgroundFit = value["fit"].clone().try_into().unwrap();
+            let options = OverlayOptions {
+                background: background.into(),
+                size: None,
+                background_fit,
+                background_filters: &[],
+                blocks: &[],
+                images: &[],
+                shapes: &[],
+                fonts: &[],
+                grain: Some(&grain),
+                measure_contrast: false,
+                palette: Some(&palette),
+                image_cache: None,
+            };
+            check_references(&options, options.palette).map_err(|e| e.to_string())
+        };
+
+        // Seeds are text, so they can start with `$`.
+        let config = r##"
+            palette = { base = "#123456", accent = "$base.lighten(10%)" }
+            fit = { letterbox = "$base" }
+            [background]
+            fill = "$base"
+            generator = { type = "grid", seed = "$title", palette = ["$base", "$accent.alpha(0.5)"] }
+            "##;
+        assert_eq!(check(config), Ok(()));
+
+        let config = r##"
+            palette = {}
+            fit = { letterbox = "$missing" }
+            background = { generator = { type = "grid", seed = "$title", palette = ["red"] } }
+            "##;
+        assert!(check(config).unwrap_err().contains("Unknown palette color"));
+
+        // Palette colors are checked even if nothing uses them.
+        let config = r##"
+            palette = { a = "$b", b = "$a" }
+            fit = {}
+            background = {}
+            "##;
+        assert!(check(config).unwrap_err().contains("refers to itself"));
+    }
 }
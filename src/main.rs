@@ -1,77 +1,352 @@
-use anyhow::{Context, Result};
-use glyph_brush_layout::ab_glyph::FontRef;
-use serde_derive::Deserialize;
-use std::borrow::Cow;
-use std::path::PathBuf;
+use anyhow::{anyhow, Context, Result};
+use create_social_card::{Template, WcagLevel};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-mod lib;
-use lib::{overlay_text, OverlayOptions};
-
 #[derive(Debug, StructOpt)]
 struct Args {
     #[structopt(long = "config", short = "c", help = "configuration file")]
     config: PathBuf,
 
-    #[structopt(long = "output", short = "o", help = "output path")]
+    #[structopt(
+        long = "output",
+        short = "o",
+        help = "output path. With themes, {theme} is replaced by the theme name, or the name is added before the extension. {name} is replaced by the name of the config file"
+    )]
     output: PathBuf,
+
+    #[structopt(
+        long = "theme",
+        help = "only render this theme, instead of every theme in the config"
+    )]
+    theme: Option<String>,
+
+    #[structopt(
+        long = "check-contrast",
+        help = "report the contrast of each text run, and fail if any is below the WCAG level (aa or aaa)"
+    )]
+    check_contrast: Option<WcagLevel>,
+
+    #[structopt(
+        long = "var",
+        parse(try_from_str = parse_variable),
+        help = "set a variable used by {{name}} placeholders in the config, as name=value"
+    )]
+    variables: Vec<(String, String)>,
+
+    #[structopt(
+        long = "vars",
+        help = "a TOML file of variables used by {{name}} placeholders. --var overrides these"
+    )]
+    variables_file: Option<PathBuf>,
+}
+
+fn parse_variable(s: &str) -> Result<(String, String)> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected a variable as name=value, not {:?}", s))?;
+    Ok((name.trim().to_string(), value.to_string()))
+}
+
+/// Read variables from a TOML file. Values that aren't strings are written as TOML.
+fn read_variables(path: &Path) -> Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Opening variables file {:?}", path))?;
+    let table: toml::value::Table =
+        toml::from_str(&contents).with_context(|| format!("Parsing variables file {:?}", path))?;
+    Ok(table
+        .into_iter()
+        .map(|(name, value)| match value {
+            toml::Value::String(s) => (name, s),
+            value => (name, value.to_string()),
+        })
+        .collect())
 }
 
-#[derive(Deserialize)]
-struct FontConfig {
-    name: String,
-    path: PathBuf,
+/// Merge a theme's settings into the config. Tables are merged key by key and other values are replaced.
+/// Lists of tables such as `blocks` can be overridden item by item with a list of tables, where `{}` leaves
+/// an item as it is, or with a table keyed by the `name` of the items to change. Other lists, such as the
+/// colors of a gradient, are replaced.
+fn merge_theme(base: &mut toml::Value, theme: toml::Value, path: &str) -> Result<()> {
+    use toml::Value;
+
+    match (base, theme) {
+        (Value::Table(base), Value::Table(theme)) => {
+            for (key, value) in theme {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match base.get_mut(&key) {
+                    Some(existing) => merge_theme(existing, value, &path)?,
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(theme))
+            if !theme.is_empty() && base.iter().chain(&theme).all(Value::is_table) =>
+        {
+            for (i, value) in theme.into_iter().enumerate() {
+                match base.get_mut(i) {
+                    Some(existing) => merge_theme(existing, value, &format!("{}[{}]", path, i))?,
+                    None => base.push(value),
+                }
+            }
+        }
+        (Value::Array(base), Value::Table(theme)) => {
+            for (name, value) in theme {
+                let item = base
+                    .iter_mut()
+                    .find(|item| item.get("name").and_then(Value::as_str) == Some(name.as_str()))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "The theme overrides {:?} in {}, but there is none with that name",
+                            name,
+                            path
+                        )
+                    })?;
+                merge_theme(item, value, &format!("{}.{}", path, name))?;
+            }
+        }
+        (base, theme) => *base = theme,
+    }
+
+    Ok(())
 }
 
-#[derive(Deserialize)]
-struct Config<'a> {
-    background: PathBuf,
-    fonts: Vec<FontConfig>,
-    blocks: Vec<lib::Block<'a>>,
+/// The output path for a card. `{name}` is replaced by the name of the config file. For themed cards,
+/// `{theme}` is replaced by the theme, or if the path doesn't have `{theme}`, the theme is added to the end
+/// of the file name.
+fn output_path(output: &Path, config: &Path, theme: Option<&str>) -> PathBuf {
+    let name = config
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let pattern = output.to_string_lossy();
+    let mut path = pattern.replace("{name}", &name);
+
+    if let Some(theme) = theme {
+        if path.contains("{theme}") {
+            path = path.replace("{theme}", theme);
+        } else {
+            let output = Path::new(&path);
+            let stem = output
+                .file_stem()
+                .map(|s| s.to_string_lossy())
+                .unwrap_or_default();
+            let file_name = match output.extension() {
+                Some(extension) => format!("{}-{}.{}", stem, theme, extension.to_string_lossy()),
+                None => format!("{}-{}", stem, theme),
+            };
+            return output.with_file_name(file_name);
+        }
+    }
+
+    PathBuf::from(path)
+}
+
+/// Render one card and save it to `output`. Returns how many text runs failed the contrast check.
+fn render_card(
+    template: &Template,
+    variables: &HashMap<String, String>,
+    output: &Path,
+    check_contrast: Option<WcagLevel>,
+) -> Result<usize> {
+    let (result, info) = template.render_with_info(variables)?;
+    if let Some(crop) = info.background_crop {
+        println!(
+            "Background cropped to {}x{} at ({}, {})",
+            crop.width, crop.height, crop.left, crop.top
+        );
+    }
+    result.save(output)?;
+
+    let mut failures = 0;
+    if let Some(level) = check_contrast {
+        for c in &info.text_contrast {
+            println!(
+                "Block {} run {} {:?} at {}pt: contrast min {:.2}, median {:.2}, {} needs {} ({})",
+                c.block,
+                c.run,
+                c.text,
+                c.font_size,
+                c.min,
+                c.median,
+                level,
+                c.required_contrast(level),
+                if c.passes(level) { "pass" } else { "FAIL" }
+            );
+        }
+
+        failures = info.contrast_failures(level).len();
+    }
+
+    Ok(failures)
 }
 
 fn main() -> Result<()> {
     let args = Args::from_args();
 
-    let config: Config = {
-        let config_contents =
-            std::fs::read_to_string(&args.config).context("Opening config file")?;
-        toml::from_str(&config_contents).context("Parsing config file")?
+    let mut variables = match args.variables_file.as_ref() {
+        Some(path) => read_variables(path)?,
+        None => HashMap::new(),
     };
+    variables.extend(args.variables.iter().cloned());
 
-    let bg = image::open(&config.background).context("Opening background image")?;
+    let config_contents = std::fs::read_to_string(&args.config).context("Opening config file")?;
+    let mut config_value: toml::Value =
+        toml::from_str(&config_contents).context("Parsing config file")?;
+    let themes = config_value
+        .as_table_mut()
+        .and_then(|config| config.remove("themes"));
 
-    let font_data = config
-        .fonts
-        .into_iter()
-        .map(|f| {
-            let font_data = std::fs::read(&f.path)
-                .with_context(|| format!("Opening font file {:?}", f.path))?;
-            Ok((f, font_data))
-        })
-        .collect::<Result<Vec<_>>>()?;
-
-    let fonts = font_data
-        .iter()
-        .enumerate()
-        .map(|(i, f)| {
-            let font = FontRef::try_from_slice_and_index(&f.1, i as u32)
-                .with_context(|| format!("Loading font {:?}", f.0.path))?;
-            Ok(lib::FontDef {
-                name: Cow::from(&f.0.name),
-                font,
-            })
-        })
-        .collect::<Result<Vec<_>>>()?;
+    let mut failures = 0;
+    match themes {
+        None => {
+            if let Some(theme) = args.theme.as_ref() {
+                return Err(anyhow!(
+                    "The config has no themes, so it has no {:?} theme",
+                    theme
+                ));
+            }
 
-    let options = OverlayOptions {
-        background: bg,
-        fonts: &fonts,
-        blocks: &config.blocks,
-    };
+            let mut template = Template::from_toml(&config_contents)?;
+            template.set_measure_contrast(args.check_contrast.is_some());
+            let output = output_path(&args.output, &args.config, None);
+            failures += render_card(&template, &variables, &output, args.check_contrast)?;
+        }
+        Some(toml::Value::Table(themes)) => {
+            if let Some(theme) = args.theme.as_ref() {
+                if !themes.contains_key(theme) {
+                    return Err(anyhow!("The config has no theme named {:?}", theme));
+                }
+            }
 
-    let result = overlay_text(&options)?;
-    result.save(&args.output)?;
+            for (name, theme) in themes {
+                if args.theme.as_ref().map(|t| *t != name).unwrap_or(false) {
+                    continue;
+                }
+
+                let mut themed = config_value.clone();
+                merge_theme(&mut themed, theme, "")
+                    .with_context(|| format!("Applying theme {:?}", name))?;
+                let mut template = Template::from_value(themed)
+                    .with_context(|| format!("Loading config with theme {:?}", name))?;
+                template.set_measure_contrast(args.check_contrast.is_some());
+                let output = output_path(&args.output, &args.config, Some(&name));
+                println!("Rendering theme {:?} to {:?}", name, output);
+                failures += render_card(&template, &variables, &output, args.check_contrast)
+                    .with_context(|| format!("Rendering theme {:?}", name))?;
+            }
+        }
+        Some(_) => return Err(anyhow!("`themes` must be a table of themes")),
+    }
+
+    if failures > 0 {
+        if let Some(level) = args.check_contrast {
+            return Err(anyhow!(
+                "{} text run(s) do not meet WCAG {} contrast",
+                failures,
+                level
+            ));
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(base: &str, theme: &str) -> Result<toml::Value> {
+        let mut base: toml::Value = toml::from_str(base).unwrap();
+        merge_theme(&mut base, toml::from_str(theme).unwrap(), "")?;
+        Ok(base)
+    }
+
+    #[test]
+    fn themes_replace_values_and_merge_tables() {
+        let config = merged(
+            "width = 10\nbackground_fit = { mode = \"cover\", letterbox = \"black\" }",
+            "width = 20\nbackground_fit = { letterbox = \"white\" }\ngrain = { seed = \"a\" }",
+        )
+        .unwrap();
+        let expected: toml::Value = toml::from_str(
+            "width = 20\nbackground_fit = { mode = \"cover\", letterbox = \"white\" }\ngrain = { seed = \"a\" }",
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn themes_merge_lists_item_by_item_or_by_name() {
+        let base = r#"
+            [[blocks]]
+            name = "title"
+            color = "black"
+            [[blocks]]
+            name = "byline"
+            color = "gray"
+            "#;
+
+        // `{}` leaves an item as it is, and extra items are added.
+        let config = merged(
+            base,
+            "blocks = [{}, { color = \"white\" }, { name = \"tag\" }]",
+        )
+        .unwrap();
+        let colors: Vec<_> = config["blocks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b.get("color").and_then(|c| c.as_str()))
+            .collect();
+        assert_eq!(colors, [Some("black"), Some("white"), None]);
+
+        let config = merged(base, "blocks.byline = { color = \"white\" }").unwrap();
+        assert_eq!(config["blocks"][0]["color"].as_str(), Some("black"));
+        assert_eq!(config["blocks"][1]["color"].as_str(), Some("white"));
+
+        let err = merged(base, "blocks.missing = { color = \"white\" }").unwrap_err();
+        assert!(err.to_string().contains("\"missing\" in blocks"), "{}", err);
+    }
+
+    #[test]
+    fn themes_replace_lists_of_values() {
+        let config = merged(
+            "[generator]\npalette = [\"red\", \"green\", \"blue\"]",
+            "[generator]\npalette = [\"white\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            config["generator"]["palette"],
+            toml::Value::Array(vec!["white".into()])
+        );
+
+        // An empty list replaces the list, rather than leaving every item as it is.
+        let config = merged("[[blocks]]\ncolor = \"red\"", "blocks = []").unwrap();
+        assert_eq!(config["blocks"], toml::Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn output_paths_name_each_theme() {
+        let config = Path::new("cards/post.toml");
+        let path = |output: &str, theme| output_path(Path::new(output), config, theme);
+
+        assert_eq!(path("{name}.png", None), Path::new("post.png"));
+        assert_eq!(
+            path("out/{name}.png", Some("dark")),
+            Path::new("out/post-dark.png")
+        );
+        assert_eq!(
+            path("{theme}/{name}.png", Some("dark")),
+            Path::new("dark/post.png")
+        );
+        assert_eq!(path("card", Some("dark")), Path::new("card-dark"));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::geometry::{Ellipse, Path, RoundedRect};
+use crate::image_block::{ImageCache, Source};
+use crate::Radius;
+use anyhow::Result;
+use image::GrayImage;
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+
+/// A shape that clips an image. The mask is sized to the area inside the image's border.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Mask {
+    /// The largest circle that fits, centered in the area.
+    Circle,
+    /// An ellipse that fills the area.
+    Ellipse,
+    RoundedRect {
+        radius: Radius,
+    },
+    /// A polygon whose points are fractions of the area's width and height, so `[0.5, 0]` is the
+    /// middle of the top edge.
+    Polygon {
+        points: Vec<(f32, f32)>,
+    },
+    /// Use the alpha channel of another image, stretched to fill the area.
+    Image {
+        path: PathBuf,
+    },
+}
+
+/// A `Mask` fitted to a particular area.
+pub(crate) enum MaskShape {
+    RoundedRect(RoundedRect),
+    Ellipse(Ellipse),
+    Path(Path),
+    Image {
+        alpha: GrayImage,
+        left: u32,
+        top: u32,
+    },
+}
+
+impl Mask {
+    pub(crate) fn shape(
+        &self,
+        area: &RoundedRect,
+        images: Option<&ImageCache>,
+    ) -> Result<MaskShape> {
+        let shape = match self {
+            Mask::Circle => {
+                let ellipse = Ellipse::inside(area);
+                let r = ellipse.rx.min(ellipse.ry);
+                MaskShape::Ellipse(Ellipse {
+                    rx: r,
+                    ry: r,
+                    ..ellipse
+                })
+            }
+            Mask::Ellipse => MaskShape::Ellipse(Ellipse::inside(area)),
+            Mask::RoundedRect { radius } => {
+                MaskShape::RoundedRect(area.with_radii(radius.corners()))
+            }
+            Mask::Polygon { points } => MaskShape::Path(Path::polygon(
+                points
+                    .iter()
+                    .map(|(x, y)| (area.left + x * area.width(), area.top + y * area.height()))
+                    .collect(),
+            )),
+            Mask::Image { path } => {
+                let (width, height) = (area.width().round() as u32, area.height().round() as u32);
+                let resized =
+                    Source::load(path, None, images)?.render(width.max(1), height.max(1))?;
+                let alpha = GrayImage::from_fn(resized.width(), resized.height(), |x, y| {
+                    image::Luma([resized.get_pixel(x, y)[3]])
+                });
+                MaskShape::Image {
+                    alpha,
+                    left: area.left.max(0.0) as u32,
+                    top: area.top.max(0.0) as u32,
+                }
+            }
+        };
+
+        Ok(shape)
+    }
+}
+
+impl MaskShape {
+    pub fn coverage(&self, x: u32, y: u32) -> f32 {
+        match self {
+            MaskShape::RoundedRect(r) => r.coverage(x, y),
+            MaskShape::Ellipse(e) => e.coverage(x, y),
+            MaskShape::Path(p) => p.coverage(x, y),
+            MaskShape::Image { alpha, left, top } => {
+                if x < *left || y < *top {
+                    return 0.0;
+                }
+
+                let (mx, my) = (x - left, y - top);
+                if mx >= alpha.width() || my >= alpha.height() {
+                    0.0
+                } else {
+                    alpha.get_pixel(mx, my)[0] as f32 / 255.0
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    /// A 40x20 area with its top left corner at (10, 10).
+    fn shape(config: &str) -> MaskShape {
+        let mask: Mask = toml::from_str(config).unwrap();
+        let area = RoundedRect::new(
+            &Rect {
+                left: 10,
+                top: 10,
+                right: 49,
+                bottom: 29,
+            },
+            [0.0; 4],
+        );
+        mask.shape(&area, None).unwrap()
+    }
+
+    #[test]
+    fn circles_fit_the_shorter_side() {
+        let mask = shape(r#"type = "circle""#);
+        assert_eq!(mask.coverage(29, 19), 1.0);
+        assert_eq!(mask.coverage(29, 11), 1.0);
+        assert_eq!(mask.coverage(18, 19), 0.0);
+        assert_eq!(mask.coverage(15, 19), 0.0);
+    }
+
+    #[test]
+    fn ellipses_fill_the_area() {
+        let mask = shape(r#"type = "ellipse""#);
+        assert_eq!(mask.coverage(11, 19), 1.0);
+        assert_eq!(mask.coverage(29, 10), 1.0);
+        assert_eq!(mask.coverage(11, 11), 0.0);
+        assert_eq!(mask.coverage(9, 19), 0.0);
+
+        let edge = mask.coverage(10, 19);
+        assert!(edge > 0.0 && edge < 1.0, "coverage was {}", edge);
+    }
+
+    #[test]
+    fn polygon_points_are_fractions_of_the_area() {
+        // A triangle pointing up.
+        let mask = shape(
+            r#"
+            type = "polygon"
+            points = [[0.5, 0], [1, 1], [0, 1]]
+            "#,
+        );
+        assert_eq!(mask.coverage(29, 28), 1.0);
+        assert_eq!(mask.coverage(12, 28), 1.0);
+        assert_eq!(mask.coverage(12, 12), 0.0);
+        assert_eq!(mask.coverage(47, 12), 0.0);
+        assert_eq!(mask.coverage(29, 5), 0.0);
+    }
+
+    #[test]
+    fn rounded_rect_masks_replace_the_radius() {
+        let mask = shape("type = \"roundedRect\"\nradius = 10");
+        assert_eq!(mask.coverage(10, 10), 0.0);
+        assert_eq!(mask.coverage(14, 14), 1.0);
+        assert_eq!(mask.coverage(29, 10), 1.0);
+    }
+}
@@ -0,0 +1,478 @@
+use crate::color::ColorOr;
+use crate::geometry::RoundedRect;
+use crate::{pixel, Color, ColorContext, Pixel};
+use anyhow::{anyhow, Result};
+use serde_derive::Deserialize;
+
+/// Something that can fill an area: either a single color or a gradient.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "ColorOr<Gradient<'a>>")]
+pub enum Paint<'a> {
+    Color(Color<'a>),
+    Gradient(Gradient<'a>),
+}
+
+impl<'a> From<ColorOr<Gradient<'a>>> for Paint<'a> {
+    fn from(paint: ColorOr<Gradient<'a>>) -> Paint<'a> {
+        match paint {
+            ColorOr::Color(color) => Paint::Color(color),
+            ColorOr::Table(gradient) => Paint::Gradient(gradient),
+        }
+    }
+}
+
+impl<'a> Default for Paint<'a> {
+    fn default() -> Paint<'a> {
+        Paint::Color(Color::default())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Gradient<'a> {
+    #[serde(flatten)]
+    pub shape: GradientShape,
+    pub stops: Vec<ColorStop<'a>>,
+    /// Add a small amount of ordered noise to hide banding when the gradient is reduced to 8 bits per channel.
+    #[serde(default)]
+    pub dither: bool,
+}
+
+/// The shape of a gradient. Centers are given as fractions of the box's width and height, so `[0.5, 0.5]` is
+/// the middle of the box.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GradientShape {
+    Linear {
+        /// The direction of the gradient in degrees, using the CSS convention where 0 points up and
+        /// 90 points right. Defaults to 180, which runs from top to bottom.
+        #[serde(default = "default_linear_angle")]
+        angle: f32,
+    },
+    Radial {
+        #[serde(default = "default_center")]
+        center: (f32, f32),
+        /// The radius of the gradient in pixels. Defaults to the distance from the center to the
+        /// farthest corner of the box.
+        radius: Option<f32>,
+    },
+    Conic {
+        #[serde(default = "default_center")]
+        center: (f32, f32),
+        /// The angle where the gradient starts, in degrees clockwise from the top.
+        #[serde(default)]
+        angle: f32,
+    },
+}
+
+fn default_linear_angle() -> f32 {
+    180.0
+}
+
+fn default_center() -> (f32, f32) {
+    (0.5, 0.5)
+}
+
+/// A color in a gradient, either just a color or a color with a position from 0 to 1 along the gradient.
+/// Stops without a position are spaced evenly between their neighbors.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "ColorOr<PositionedStop<'a>>")]
+pub enum ColorStop<'a> {
+    Color(Color<'a>),
+    Positioned { color: Color<'a>, position: f32 },
+}
+
+#[derive(Deserialize)]
+struct PositionedStop<'a> {
+    color: Color<'a>,
+    position: f32,
+}
+
+impl<'a> From<ColorOr<PositionedStop<'a>>> for ColorStop<'a> {
+    fn from(stop: ColorOr<PositionedStop<'a>>) -> ColorStop<'a> {
+        match stop {
+            ColorOr::Color(color) => ColorStop::Color(color),
+            ColorOr::Table(PositionedStop { color, position }) => {
+                ColorStop::Positioned { color, position }
+            }
+        }
+    }
+}
+
+/// A premultiplied color in the 0-1 range, for interpolating between gradient stops.
+type Premultiplied = [f32; 4];
+
+fn premultiply(p: Pixel) -> Premultiplied {
+    let a = p[3] as f32 / 255.0;
+    [
+        p[0] as f32 / 255.0 * a,
+        p[1] as f32 / 255.0 * a,
+        p[2] as f32 / 255.0 * a,
+        a,
+    ]
+}
+
+fn unpremultiply(c: Premultiplied) -> Pixel {
+    unpremultiply_dithered(c, 0.0)
+}
+
+/// Convert back to 8 bits per channel, adding `offset` (in units of one 8-bit step) to each color
+/// channel before rounding.
+fn unpremultiply_dithered(c: Premultiplied, offset: f32) -> Pixel {
+    if c[3] <= 0.0 {
+        return pixel(0, 0, 0, 0);
+    }
+
+    let channel = |v: f32| {
+        ((v / c[3]).clamp(0.0, 1.0) * 255.0 + offset)
+            .round()
+            .clamp(0.0, 255.0) as u8
+    };
+    pixel(
+        channel(c[0]),
+        channel(c[1]),
+        channel(c[2]),
+        (c[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+    )
+}
+
+/// An 8x8 Bayer matrix, for ordered dithering.
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn dither_offset(x: u32, y: u32) -> f32 {
+    (BAYER[(y % 8) as usize][(x % 8) as usize] as f32 + 0.5) / 64.0 - 0.5
+}
+
+/// Gradient stops with all their positions filled in.
+#[derive(Debug)]
+pub(crate) struct Stops(Vec<(f32, Premultiplied)>);
+
+impl Stops {
+    fn new(stops: &[ColorStop], colors: &ColorContext) -> Result<Stops> {
+        if stops.is_empty() {
+            return Err(anyhow!("Gradient must have at least one color stop"));
+        }
+
+        let mut positions = stops
+            .iter()
+            .map(|s| match s {
+                ColorStop::Color(_) => None,
+                ColorStop::Positioned { position, .. } => Some(*position),
+            })
+            .collect::<Vec<_>>();
+
+        let last = positions.len() - 1;
+        positions[0].get_or_insert(0.0);
+        positions[last].get_or_insert(1.0);
+
+        // Positions must not decrease, so clamp each one to the position before it.
+        let mut max_position = 0.0f32;
+        for p in positions.iter_mut().flatten() {
+            max_position = max_position.max(*p);
+            *p = max_position;
+        }
+
+        // Spread out any runs of stops that have no position.
+        let mut i = 0;
+        while i < positions.len() {
+            if positions[i].is_some() {
+                i += 1;
+                continue;
+            }
+
+            let start = i - 1;
+            let end = (i..positions.len())
+                .find(|j| positions[*j].is_some())
+                .unwrap();
+            let from = positions[start].unwrap();
+            let to = positions[end].unwrap();
+            for (j, p) in positions.iter_mut().enumerate().take(end).skip(i) {
+                let t = (j - start) as f32 / (end - start) as f32;
+                *p = Some(from + (to - from) * t);
+            }
+            i = end;
+        }
+
+        let stops = stops
+            .iter()
+            .zip(positions)
+            .map(|(stop, position)| {
+                let color = match stop {
+                    ColorStop::Color(c) => c,
+                    ColorStop::Positioned { color, .. } => color,
+                };
+                Ok((position.unwrap(), premultiply(color.resolve(colors)?)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Stops(stops))
+    }
+
+    fn at(&self, t: f32) -> Premultiplied {
+        let stops = &self.0;
+        let (first, last) = (&stops[0], &stops[stops.len() - 1]);
+        if t <= first.0 {
+            return first.1;
+        } else if t >= last.0 {
+            return last.1;
+        }
+
+        let next = stops.iter().position(|s| s.0 > t).unwrap();
+        let (p0, c0) = stops[next - 1];
+        let (p1, c1) = stops[next];
+        let f = (t - p0) / (p1 - p0);
+        [
+            c0[0] + (c1[0] - c0[0]) * f,
+            c0[1] + (c1[1] - c0[1]) * f,
+            c0[2] + (c1[2] - c0[2]) * f,
+            c0[3] + (c1[3] - c0[3]) * f,
+        ]
+    }
+}
+
+/// A `Paint` with its colors parsed and its geometry fixed to a particular area of the image.
+#[derive(Debug)]
+pub(crate) enum Painter {
+    Solid(Pixel),
+    Gradient {
+        stops: Stops,
+        geometry: GradientGeometry,
+        dither: bool,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) enum GradientGeometry {
+    Linear {
+        /// The start of the gradient line.
+        origin: (f32, f32),
+        /// The direction of the gradient line, scaled so that the dot product with an offset from
+        /// the origin gives the position along the line.
+        step: (f32, f32),
+    },
+    Radial {
+        center: (f32, f32),
+        radius: f32,
+    },
+    Conic {
+        center: (f32, f32),
+        /// The starting angle, in radians.
+        start: f32,
+    },
+}
+
+impl GradientGeometry {
+    fn new(shape: &GradientShape, area: &RoundedRect) -> GradientGeometry {
+        let (width, height) = (area.width(), area.height());
+        let point = |(x, y): (f32, f32)| (area.left + width * x, area.top + height * y);
+
+        match shape {
+            GradientShape::Linear { angle } => {
+                let radians = angle.to_radians();
+                let (dx, dy) = (radians.sin(), -radians.cos());
+
+                // As in CSS, the gradient line is long enough that the corners of the box get the
+                // colors at the start and end of the gradient.
+                let length = (width * dx).abs() + (height * dy).abs();
+                let center = point((0.5, 0.5));
+                let origin = (center.0 - dx * length / 2.0, center.1 - dy * length / 2.0);
+                let step = if length > 0.0 {
+                    (dx / length, dy / length)
+                } else {
+                    (0.0, 0.0)
+                };
+
+                GradientGeometry::Linear { origin, step }
+            }
+            GradientShape::Radial { center, radius } => {
+                let center = point(*center);
+                let radius = radius.unwrap_or_else(|| {
+                    let dx = (center.0 - area.left).max(area.right - center.0);
+                    let dy = (center.1 - area.top).max(area.bottom - center.1);
+                    (dx * dx + dy * dy).sqrt()
+                });
+                GradientGeometry::Radial { center, radius }
+            }
+            GradientShape::Conic { center, angle } => GradientGeometry::Conic {
+                center: point(*center),
+                start: angle.to_radians(),
+            },
+        }
+    }
+
+    /// The position along the gradient for the center of the pixel at `x`, `y`.
+    fn position(&self, x: u32, y: u32) -> f32 {
+        let px = x as f32 + 0.5;
+        let py = y as f32 + 0.5;
+        match self {
+            GradientGeometry::Linear { origin, step } => {
+                (px - origin.0) * step.0 + (py - origin.1) * step.1
+            }
+            GradientGeometry::Radial { center, radius } => {
+                if *radius <= 0.0 {
+                    return 1.0;
+                }
+                let (dx, dy) = (px - center.0, py - center.1);
+                (dx * dx + dy * dy).sqrt() / radius
+            }
+            GradientGeometry::Conic { center, start } => {
+                // Angle clockwise from the top.
+                let angle = (px - center.0).atan2(center.1 - py) - start;
+                angle.rem_euclid(std::f32::consts::TAU) / std::f32::consts::TAU
+            }
+        }
+    }
+}
+
+impl<'a> Paint<'a> {
+    /// Prepare the paint to fill `area`. Gradients are sized to fit the area.
+    pub(crate) fn painter(&self, area: &RoundedRect, colors: &ColorContext) -> Result<Painter> {
+        match self {
+            Paint::Color(c) => Ok(Painter::Solid(c.resolve(colors)?)),
+            Paint::Gradient(g) => Ok(Painter::Gradient {
+                stops: Stops::new(&g.stops, colors)?,
+                geometry: GradientGeometry::new(&g.shape, area),
+                dither: g.dither,
+            }),
+        }
+    }
+}
+
+impl Painter {
+    pub fn at(&self, x: u32, y: u32) -> Pixel {
+        match self {
+            Painter::Solid(p) => *p,
+            Painter::Gradient {
+                stops,
+                geometry,
+                dither,
+            } => {
+                let color = stops.at(geometry.position(x, y));
+                if *dither {
+                    unpremultiply_dithered(color, dither_offset(x, y))
+                } else {
+                    unpremultiply(color)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    fn stops(config: &str) -> Stops {
+        let stops: Vec<ColorStop> = toml::from_str::<toml::Value>(config).unwrap()["stops"]
+            .clone()
+            .try_into()
+            .unwrap();
+        Stops::new(&stops, &ColorContext::default()).unwrap()
+    }
+
+    fn positions(stops: &Stops) -> Vec<f32> {
+        stops.0.iter().map(|s| s.0).collect()
+    }
+
+    #[test]
+    fn missing_stop_positions_are_spread_out() {
+        let s = stops(r#"stops = ["000000", "000000", "000000", "000000", "000000"]"#);
+        assert_eq!(positions(&s), vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+
+        let s = stops(
+            r#"stops = ["000000", { color = "000000", position = 0.6 }, "000000", "000000"]"#,
+        );
+        assert_eq!(positions(&s), vec![0.0, 0.6, 0.8, 1.0]);
+
+        // A position that goes backwards is moved up to the one before it.
+        let s = stops(
+            r#"stops = [{ color = "000000", position = 0.5 }, { color = "000000", position = 0.2 }]"#,
+        );
+        assert_eq!(positions(&s), vec![0.5, 0.5]);
+
+        assert!(Stops::new(&[], &ColorContext::default()).is_err());
+    }
+
+    #[test]
+    fn stops_are_interpolated() {
+        let s = stops(r#"stops = ["000000", "ff8000", "ffffff"]"#);
+        assert_eq!(unpremultiply(s.at(-1.0)), pixel(0, 0, 0, 255));
+        assert_eq!(unpremultiply(s.at(0.25)), pixel(128, 64, 0, 255));
+        assert_eq!(unpremultiply(s.at(0.5)), pixel(255, 128, 0, 255));
+        assert_eq!(unpremultiply(s.at(0.75)), pixel(255, 192, 128, 255));
+        assert_eq!(unpremultiply(s.at(2.0)), pixel(255, 255, 255, 255));
+
+        // Fading to transparent keeps the color instead of darkening it.
+        let s = stops(r#"stops = ["ff000000", "ff0000ff"]"#);
+        assert_eq!(unpremultiply(s.at(0.5)), pixel(255, 0, 0, 128));
+        assert_eq!(unpremultiply(s.at(0.0)), pixel(0, 0, 0, 0));
+    }
+
+    fn geometry(config: &str) -> GradientGeometry {
+        let shape: GradientShape = toml::from_str(config).unwrap();
+        let area = RoundedRect::new(
+            &Rect {
+                left: 0,
+                top: 0,
+                right: 99,
+                bottom: 49,
+            },
+            [0.0; 4],
+        );
+        GradientGeometry::new(&shape, &area)
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.04,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn gradient_shapes() {
+        let g = geometry(r#"type = "linear""#);
+        assert_near(g.position(50, 0), 0.0);
+        assert_near(g.position(50, 49), 1.0);
+
+        // At 45 degrees, the corners still get the end colors.
+        let g = geometry("type = \"linear\"\nangle = 45");
+        assert_near(g.position(0, 49), 0.0);
+        assert_near(g.position(99, 0), 1.0);
+
+        let g = geometry("type = \"radial\"\nradius = 20");
+        assert_near(g.position(49, 24), 0.0);
+        assert_near(g.position(59, 24), 0.5);
+        assert_eq!(
+            geometry("type = \"radial\"\nradius = 0").position(0, 0),
+            1.0
+        );
+
+        let g = geometry("type = \"conic\"\nangle = 90");
+        assert_near(g.position(90, 26), 0.0);
+        assert_near(g.position(49, 45), 0.25);
+        assert_near(g.position(10, 24), 0.5);
+    }
+
+    #[test]
+    fn dithering_only_nudges_colors() {
+        let color = premultiply(pixel(100, 150, 200, 255));
+        for (x, y) in [(0, 0), (3, 5), (7, 7)].iter() {
+            let p = unpremultiply_dithered(color, dither_offset(*x, *y));
+            for (a, b) in p.0.iter().zip([100u8, 150, 200, 255].iter()) {
+                assert!((*a as i32 - *b as i32).abs() <= 1, "{:?}", p);
+            }
+        }
+    }
+}
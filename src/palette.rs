@@ -0,0 +1,11 @@
+use crate::Color;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+/// Named colors that any other color can refer to as `"$name"`. Palette colors can themselves refer to
+/// other palette colors and to swatches.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Palette<'a> {
+    pub colors: HashMap<String, Color<'a>>,
+}
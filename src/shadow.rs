@@ -0,0 +1,228 @@
+use crate::geometry::RoundedRect;
+use crate::{Color, ColorContext, Pixel, DEFAULT_SHADOW_COLOR};
+use anyhow::Result;
+use image::RgbaImage;
+use serde_derive::Deserialize;
+
+/// A shadow cast by a block's box, modeled on the CSS `box-shadow` property.
+#[derive(Debug, Deserialize)]
+pub struct BoxShadow<'a> {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    pub blur: Option<f32>,
+    /// Grow the shadow by this many pixels on each side before blurring it, or shrink it if negative.
+    #[serde(default)]
+    pub spread: i32,
+    /// Draw the shadow inside the block's border instead of behind it.
+    #[serde(default)]
+    pub inset: bool,
+    pub color: Option<Color<'a>>,
+}
+
+impl<'a> BoxShadow<'a> {
+    pub(crate) fn pixel(&self, colors: &ColorContext) -> Result<Pixel> {
+        Ok(self
+            .color
+            .as_ref()
+            .map(|c| c.resolve(colors))
+            .transpose()?
+            .unwrap_or(DEFAULT_SHADOW_COLOR))
+    }
+
+    pub(crate) fn blur_margin(&self) -> f32 {
+        // The gaussian is effectively zero past 3 sigma.
+        self.blur.map(|b| (b * 3.0).ceil() + 1.0).unwrap_or(1.0)
+    }
+}
+
+/// Build a layer covering `region` where each pixel is the shadow color scaled by `alpha`, and then blur it.
+pub(crate) fn shadow_layer(
+    shadow: &BoxShadow,
+    color: Pixel,
+    region: &crate::Rect,
+    alpha: impl Fn(u32, u32) -> f32,
+) -> RgbaImage {
+    let layer = RgbaImage::from_fn(
+        region.right - region.left + 1,
+        region.bottom - region.top + 1,
+        |x, y| {
+            let mut p = color;
+            p[3] = (p[3] as f32 * alpha(x + region.left, y + region.top)) as u8;
+            p
+        },
+    );
+
+    match shadow.blur {
+        Some(sigma) if sigma > 0.0 => image::imageops::blur(&layer, sigma),
+        _ => layer,
+    }
+}
+
+/// Draw the shadows that fall outside the block. As in CSS, the first shadow in the list is drawn on top,
+/// and no part of the shadow is drawn underneath the block itself.
+pub(crate) fn draw_outer_shadows(
+    image: &mut RgbaImage,
+    shadows: &[BoxShadow],
+    border_box: &RoundedRect,
+    colors: &ColorContext,
+) -> Result<()> {
+    let (width, height) = image.dimensions();
+    for shadow in shadows.iter().rev().filter(|s| !s.inset) {
+        let shape = border_box
+            .spread(shadow.spread as f32)
+            .offset(shadow.x as f32, shadow.y as f32);
+        if shape.is_empty() {
+            continue;
+        }
+
+        let region = match shape.pixel_bounds(shadow.blur_margin(), width, height) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut layer = shadow_layer(shadow, shadow.pixel(colors)?, &region, |x, y| {
+            shape.coverage(x, y)
+        });
+
+        // The shadow should not show through if the block is transparent, so clear out the block's area.
+        for (x, y, p) in layer.enumerate_pixels_mut() {
+            let block_coverage = border_box.coverage(x + region.left, y + region.top);
+            p[3] = (p[3] as f32 * (1.0 - block_coverage)) as u8;
+        }
+
+        image::imageops::overlay(image, &layer, region.left as i64, region.top as i64);
+    }
+
+    Ok(())
+}
+
+/// Draw the inset shadows, clipped to the inside of the block's border.
+pub(crate) fn draw_inset_shadows(
+    image: &mut RgbaImage,
+    shadows: &[BoxShadow],
+    padding_box: &RoundedRect,
+    colors: &ColorContext,
+) -> Result<()> {
+    let (width, height) = image.dimensions();
+    for shadow in shadows.iter().rev().filter(|s| s.inset) {
+        let hole = padding_box
+            .spread(-shadow.spread as f32)
+            .offset(shadow.x as f32, shadow.y as f32);
+
+        // The shadow surrounds the padding box, so extend the region far enough that its blurred edge
+        // is drawn correctly.
+        let region = match padding_box.pixel_bounds(shadow.blur_margin(), width, height) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut layer = shadow_layer(shadow, shadow.pixel(colors)?, &region, |x, y| {
+            1.0 - hole.coverage(x, y)
+        });
+
+        for (x, y, p) in layer.enumerate_pixels_mut() {
+            let clip = padding_box.coverage(x + region.left, y + region.top);
+            p[3] = (p[3] as f32 * clip) as u8;
+        }
+
+        image::imageops::overlay(image, &layer, region.left as i64, region.top as i64);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rect;
+
+    fn shadow(x: i32, y: i32, spread: i32, inset: bool) -> BoxShadow<'static> {
+        BoxShadow {
+            x,
+            y,
+            blur: None,
+            spread,
+            inset,
+            color: Some(Color::Rgb(0, 0, 0)),
+        }
+    }
+
+    fn block_box() -> RoundedRect {
+        RoundedRect::new(
+            &Rect {
+                left: 10,
+                top: 10,
+                right: 19,
+                bottom: 19,
+            },
+            [0.0; 4],
+        )
+    }
+
+    fn alpha(image: &RgbaImage, x: u32, y: u32) -> u8 {
+        image.get_pixel(x, y)[3]
+    }
+
+    #[test]
+    fn spread_grows_and_shrinks_outer_shadows() {
+        let mut image = RgbaImage::new(30, 30);
+        draw_outer_shadows(
+            &mut image,
+            &[shadow(0, 0, 3, false)],
+            &block_box(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        assert_eq!(alpha(&image, 7, 15), 255);
+        assert_eq!(alpha(&image, 22, 15), 255);
+        assert_eq!(alpha(&image, 6, 15), 0);
+        assert_eq!(alpha(&image, 23, 15), 0);
+        // Nothing is drawn underneath the block.
+        assert_eq!(alpha(&image, 15, 15), 0);
+
+        // A negative spread lets the offset shadow show only on the side it moves toward.
+        let mut image = RgbaImage::new(30, 30);
+        draw_outer_shadows(
+            &mut image,
+            &[shadow(4, 0, -2, false)],
+            &block_box(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        assert_eq!(alpha(&image, 21, 15), 255);
+        assert_eq!(alpha(&image, 22, 15), 0);
+        assert_eq!(alpha(&image, 21, 11), 0);
+        assert_eq!(alpha(&image, 9, 15), 0);
+    }
+
+    #[test]
+    fn inset_shadows_stay_inside_the_box() {
+        let mut image = RgbaImage::new(30, 30);
+        let shadows = [shadow(0, 0, 2, true), shadow(0, 0, 8, false)];
+        draw_inset_shadows(&mut image, &shadows, &block_box(), &ColorContext::default()).unwrap();
+        assert_eq!(alpha(&image, 10, 15), 255);
+        assert_eq!(alpha(&image, 11, 15), 255);
+        assert_eq!(alpha(&image, 12, 15), 0);
+        assert_eq!(alpha(&image, 15, 15), 0);
+        // The outer shadow is not drawn, and nothing spills outside the box.
+        assert_eq!(alpha(&image, 9, 15), 0);
+        assert_eq!(alpha(&image, 5, 15), 0);
+    }
+
+    #[test]
+    fn offset_inset_shadows_fall_on_one_side() {
+        let mut image = RgbaImage::new(30, 30);
+        draw_inset_shadows(
+            &mut image,
+            &[shadow(3, 0, 0, true)],
+            &block_box(),
+            &ColorContext::default(),
+        )
+        .unwrap();
+        assert_eq!(alpha(&image, 12, 15), 255);
+        assert_eq!(alpha(&image, 13, 15), 0);
+        assert_eq!(alpha(&image, 19, 15), 0);
+    }
+}
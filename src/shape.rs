@@ -0,0 +1,305 @@
+use crate::blend_mode::{self, BlendMode};
+use crate::geometry::{Ellipse, LineCap, Path, RoundedRect, Subpath};
+use crate::paint::Paint;
+use crate::shadow::{self, BoxShadow};
+use crate::svg_path::parse_path;
+use crate::{blend, one_or_many, ColorContext, Radius, Rect};
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use serde_derive::Deserialize;
+
+/// A decorative vector shape, such as a divider line or an accent dot.
+#[derive(Debug, Deserialize)]
+pub struct ShapeBlock<'a> {
+    #[serde(flatten)]
+    pub shape: Shape,
+    pub fill: Option<Paint<'a>>,
+    pub stroke: Option<Stroke<'a>>,
+    /// Shadows cast by the shape. Outer shadows follow the outline of the fill and stroke, and inset
+    /// shadows are drawn inside the fill.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub shadow: Vec<BoxShadow<'a>>,
+    #[serde(default = "crate::one")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    /// Elements with a higher `z_index` are drawn on top of those with a lower one.
+    #[serde(default)]
+    pub z_index: i32,
+}
+
+/// The outline of a shape. All coordinates are in pixels.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Shape {
+    /// A straight line. Lines have no inside, so they need a `stroke` to be visible.
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Rect {
+        rect: Rect,
+        radius: Option<Radius>,
+    },
+    /// The ellipse that fills `rect`.
+    Ellipse {
+        rect: Rect,
+    },
+    Polygon {
+        points: Vec<(f32, f32)>,
+    },
+    /// An SVG path, such as `M 0 0 L 100 0 Q 100 100 0 100 Z`. Elliptical arc commands are not supported.
+    Path {
+        d: String,
+    },
+}
+
+/// The line drawn along the outline of a shape.
+#[derive(Debug, Deserialize)]
+pub struct Stroke<'a> {
+    pub width: f32,
+    pub color: Paint<'a>,
+    #[serde(default)]
+    pub cap: LineCap,
+    /// Alternating lengths of dashes and gaps. The stroke is solid if this is not set.
+    pub dash: Option<Vec<f32>>,
+}
+
+impl Shape {
+    fn path(&self) -> Result<Path> {
+        let path = match self {
+            Shape::Line { from, to } => Path {
+                subpaths: vec![Subpath {
+                    points: vec![*from, *to],
+                    closed: false,
+                }],
+            },
+            Shape::Rect { rect, radius } => Path::rounded_rect(&RoundedRect::new(
+                rect,
+                radius.map(|r| r.corners()).unwrap_or_default(),
+            )),
+            Shape::Ellipse { rect } => {
+                Path::ellipse(&Ellipse::inside(&RoundedRect::new(rect, [0.0; 4])))
+            }
+            Shape::Polygon { points } => Path::polygon(points.clone()),
+            Shape::Path { d } => parse_path(d)?,
+        };
+
+        Ok(path)
+    }
+}
+
+/// Signed distance from a position along a dashed stroke to the nearest dash. Negative values are
+/// inside a dash.
+fn dash_distance(dash: &[f32], total: f32, along: f32) -> f32 {
+    let position = along.rem_euclid(total);
+    let mut start = 0.0;
+    let mut nearest = f32::INFINITY;
+    for (i, length) in dash.iter().enumerate() {
+        let end = start + length;
+        if i % 2 == 0 {
+            // Check this dash and its copies in the neighboring repeats of the pattern, since the
+            // nearest dash may wrap around.
+            for shift in [-total, 0.0, total].iter() {
+                let d = (start + shift - position).max(position - end - shift);
+                nearest = nearest.min(d);
+            }
+        }
+        start = end;
+    }
+    nearest
+}
+
+struct StrokeStyle {
+    width: f32,
+    cap: LineCap,
+    /// The dash pattern and its total length.
+    dash: Option<(Vec<f32>, f32)>,
+}
+
+impl StrokeStyle {
+    fn new(stroke: &Stroke) -> Result<StrokeStyle> {
+        if stroke.width <= 0.0 {
+            return Err(anyhow!("Stroke width must be positive"));
+        }
+
+        let dash = match stroke.dash.as_deref() {
+            Some(dash) if !dash.is_empty() => {
+                if dash.iter().any(|d| *d < 0.0) {
+                    return Err(anyhow!("Dash lengths must not be negative"));
+                }
+
+                // As in SVG, an odd number of lengths is repeated to make it even.
+                let mut dash = dash.to_vec();
+                if dash.len() % 2 == 1 {
+                    dash.extend_from_within(..);
+                }
+
+                let total = dash.iter().sum::<f32>();
+                if total <= 0.0 {
+                    return Err(anyhow!("Dash lengths must not all be zero"));
+                }
+                Some((dash, total))
+            }
+            _ => None,
+        };
+
+        Ok(StrokeStyle {
+            width: stroke.width,
+            cap: stroke.cap,
+            dash,
+        })
+    }
+
+    fn distance(&self, path: &Path, x: f32, y: f32) -> f32 {
+        let d = path.stroke_distance(x, y, self.width, self.cap);
+        let (dash, total) = match self.dash.as_ref() {
+            Some(dash) => dash,
+            None => return d.distance,
+        };
+
+        let half = self.width / 2.0;
+        let from_center = d.distance + half;
+        let along = dash_distance(dash, *total, d.along);
+        match self.cap {
+            LineCap::Butt => d.distance.max(along),
+            LineCap::Square => d.distance.max(along - half),
+            LineCap::Round if along > 0.0 => along.hypot(from_center) - half,
+            LineCap::Round => d.distance,
+        }
+    }
+}
+
+fn coverage(distance: f32) -> f32 {
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+pub(crate) fn draw_shape(
+    bg: &mut RgbaImage,
+    block: &ShapeBlock,
+    colors: &ColorContext,
+) -> Result<()> {
+    let (width, height) = bg.dimensions();
+    let path = block.shape.path()?;
+    let stroke = block.stroke.as_ref().map(StrokeStyle::new).transpose()?;
+    let stroke_width = stroke.as_ref().map(|s| s.width).unwrap_or(0.0);
+
+    let fill_bounds = path.bounds();
+    // Square caps can reach past half the stroke width at their corners.
+    let bounds = fill_bounds.spread(stroke_width);
+    let fill = if matches!(block.shape, Shape::Line { .. }) {
+        None
+    } else {
+        block.fill.as_ref()
+    };
+
+    let fill_distance = |x: f32, y: f32| path.fill_distance(x, y);
+    let outline_distance = |x: f32, y: f32| {
+        let fill_d = if fill.is_some() {
+            fill_distance(x, y)
+        } else {
+            f32::INFINITY
+        };
+        let stroke_d = stroke
+            .as_ref()
+            .map(|s| s.distance(&path, x, y))
+            .unwrap_or(f32::INFINITY);
+        fill_d.min(stroke_d)
+    };
+
+    let mut layer = RgbaImage::new(width, height);
+
+    // Outer shadows, with the first one in the list on top.
+    for s in block.shadow.iter().rev().filter(|s| !s.inset) {
+        let (dx, dy, spread) = (s.x as f32, s.y as f32, s.spread as f32);
+        let region = match bounds.spread(spread.max(0.0)).offset(dx, dy).pixel_bounds(
+            s.blur_margin(),
+            width,
+            height,
+        ) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let mut shadow_image = shadow::shadow_layer(s, s.pixel(colors)?, &region, |x, y| {
+            let (px, py) = (x as f32 + 0.5 - dx, y as f32 + 0.5 - dy);
+            coverage(outline_distance(px, py) - spread)
+        });
+
+        // As with blocks, the shadow shouldn't show through a transparent shape.
+        for (x, y, p) in shadow_image.enumerate_pixels_mut() {
+            let (px, py) = (
+                (x + region.left) as f32 + 0.5,
+                (y + region.top) as f32 + 0.5,
+            );
+            let shape_coverage = coverage(outline_distance(px, py));
+            p[3] = (p[3] as f32 * (1.0 - shape_coverage)) as u8;
+        }
+
+        image::imageops::overlay(
+            &mut layer,
+            &shadow_image,
+            region.left as i64,
+            region.top as i64,
+        );
+    }
+
+    let region = match bounds.pixel_bounds(1.0, width, height) {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+
+    if let Some(fill) = fill {
+        let painter = fill.painter(&fill_bounds, colors)?;
+        for y in region.top..=region.bottom {
+            for x in region.left..=region.right {
+                let c = coverage(fill_distance(x as f32 + 0.5, y as f32 + 0.5));
+                if c > 0.0 {
+                    let dest = layer.get_pixel_mut(x, y);
+                    *dest = blend(*dest, painter.at(x, y), c);
+                }
+            }
+        }
+
+        for s in block.shadow.iter().rev().filter(|s| s.inset) {
+            let (dx, dy, spread) = (s.x as f32, s.y as f32, s.spread as f32);
+            let mut shadow_image = shadow::shadow_layer(s, s.pixel(colors)?, &region, |x, y| {
+                let (px, py) = (x as f32 + 0.5 - dx, y as f32 + 0.5 - dy);
+                1.0 - coverage(fill_distance(px, py) + spread)
+            });
+
+            for (x, y, p) in shadow_image.enumerate_pixels_mut() {
+                let (px, py) = (
+                    (x + region.left) as f32 + 0.5,
+                    (y + region.top) as f32 + 0.5,
+                );
+                p[3] = (p[3] as f32 * coverage(fill_distance(px, py))) as u8;
+            }
+
+            image::imageops::overlay(
+                &mut layer,
+                &shadow_image,
+                region.left as i64,
+                region.top as i64,
+            );
+        }
+    }
+
+    if let (Some(style), Some(stroke)) = (stroke.as_ref(), block.stroke.as_ref()) {
+        let painter = stroke
+            .color
+            .painter(&fill_bounds.spread(style.width / 2.0), colors)?;
+        for y in region.top..=region.bottom {
+            for x in region.left..=region.right {
+                let c = coverage(style.distance(&path, x as f32 + 0.5, y as f32 + 0.5));
+                if c > 0.0 {
+                    let dest = layer.get_pixel_mut(x, y);
+                    *dest = blend(*dest, painter.at(x, y), c);
+                }
+            }
+        }
+    }
+
+    blend_mode::composite(bg, &layer, 0, 0, block.blend_mode, block.opacity);
+    Ok(())
+}
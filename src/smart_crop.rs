@@ -0,0 +1,221 @@
+use image::{imageops::FilterType, GrayImage, RgbaImage};
+
+/// The longest side of the downscaled image used to choose the crop.
+const ANALYSIS_SIZE: u32 = 256;
+
+/// How much a window's luminance entropy counts compared to its edge density.
+const ENTROPY_WEIGHT: f32 = 0.3;
+
+/// The number of bins in the brightness histogram, and the highest entropy it can have.
+const HISTOGRAM_BINS: usize = 32;
+const MAX_ENTROPY: f32 = 5.0;
+
+/// Find the most interesting part of `image` to keep when cropping it to the aspect ratio of `width` by
+/// `height`, and return its center as fractions of the image's width and height. Windows are scored by
+/// the density of edges and the entropy of the brightness inside them, so busy areas like faces and text
+/// win over flat areas like sky.
+pub(crate) fn find_focal_point(image: &RgbaImage, width: u32, height: u32) -> (f32, f32) {
+    let (image_width, image_height) = image.dimensions();
+    let scale = (ANALYSIS_SIZE as f32 / image_width.max(image_height) as f32).min(1.0);
+    let analysis_width = ((image_width as f32 * scale).round() as u32).max(1);
+    let analysis_height = ((image_height as f32 * scale).round() as u32).max(1);
+    let small =
+        image::imageops::resize(image, analysis_width, analysis_height, FilterType::Triangle);
+    let gray = GrayImage::from_fn(analysis_width, analysis_height, |x, y| {
+        let p = small.get_pixel(x, y);
+        let luma = 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32;
+        image::Luma([(luma * p[3] as f32 / 255.0).round() as u8])
+    });
+    let edges = edge_magnitudes(&gray);
+
+    // The window is the size the card covers after scaling, so it spans the whole image in one direction
+    // and only needs to slide in the other.
+    let window_scale =
+        (analysis_width as f32 / width as f32).min(analysis_height as f32 / height as f32);
+    let window_width = ((width as f32 * window_scale).round() as u32).clamp(1, analysis_width);
+    let window_height = ((height as f32 * window_scale).round() as u32).clamp(1, analysis_height);
+    let horizontal = window_width < analysis_width;
+    let positions = if horizontal {
+        analysis_width - window_width + 1
+    } else {
+        analysis_height - window_height + 1
+    };
+
+    let windows = (0..positions)
+        .map(|offset| {
+            let (left, top) = if horizontal { (offset, 0) } else { (0, offset) };
+            let (edge_density, entropy) =
+                window_stats(&gray, &edges, left, top, window_width, window_height);
+            (offset, edge_density, entropy)
+        })
+        .collect::<Vec<_>>();
+
+    let max_edges = windows
+        .iter()
+        .map(|w| w.1)
+        .fold(0.0f32, f32::max)
+        .max(f32::EPSILON);
+    let center = (positions - 1) as f32 / 2.0;
+    let best = windows
+        .iter()
+        .map(|(offset, edge_density, entropy)| {
+            let score = (1.0 - ENTROPY_WEIGHT) * edge_density / max_edges
+                + ENTROPY_WEIGHT * entropy / MAX_ENTROPY;
+            // Slightly prefer the middle, so that flat images, which score nothing anywhere, stay
+            // centered.
+            let off_center = if center > 0.0 {
+                (*offset as f32 - center).abs() / center
+            } else {
+                0.0
+            };
+            (
+                offset,
+                score * (1.0 - 0.05 * off_center) - 0.001 * off_center,
+            )
+        })
+        .fold((0, f32::NEG_INFINITY), |best, (offset, score)| {
+            if score > best.1 {
+                (*offset, score)
+            } else {
+                best
+            }
+        })
+        .0;
+
+    // Several windows can score about the same when they all contain the whole subject, so center the
+    // crop on the middle of the detail inside the best window rather than on the window itself.
+    let (length, window_length) = if horizontal {
+        (analysis_width, window_width)
+    } else {
+        (analysis_height, window_height)
+    };
+    let mut profile = vec![0.0f32; length as usize];
+    for y in 0..analysis_height {
+        for x in 0..analysis_width {
+            let i = if horizontal { x } else { y };
+            profile[i as usize] += edges[(y * analysis_width + x) as usize];
+        }
+    }
+
+    let (weighted, total) =
+        (best..best + window_length).fold((0.0, 0.0), |(weighted, total), i| {
+            let e = profile[i as usize];
+            (weighted + e * (i as f32 + 0.5), total + e)
+        });
+    let focus = if total > 0.0 {
+        weighted / total
+    } else {
+        best as f32 + window_length as f32 / 2.0
+    } / length as f32;
+
+    if horizontal {
+        (focus, 0.5)
+    } else {
+        (0.5, focus)
+    }
+}
+
+/// The magnitude of the Sobel gradient at each pixel.
+fn edge_magnitudes(gray: &GrayImage) -> Vec<f32> {
+    let (width, height) = gray.dimensions();
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        gray.get_pixel(x, y)[0] as f32
+    };
+
+    let mut edges = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            edges.push(gx.hypot(gy));
+        }
+    }
+    edges
+}
+
+/// The mean edge magnitude and the entropy of the brightness histogram, in bits, inside a window.
+fn window_stats(
+    gray: &GrayImage,
+    edges: &[f32],
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+) -> (f32, f32) {
+    let mut histogram = [0u32; HISTOGRAM_BINS];
+    let mut edge_total = 0.0;
+    for y in top..top + height {
+        for x in left..left + width {
+            histogram[gray.get_pixel(x, y)[0] as usize * HISTOGRAM_BINS / 256] += 1;
+            edge_total += edges[(y * gray.width() + x) as usize];
+        }
+    }
+
+    let count = (width * height) as f32;
+    let entropy = histogram
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f32 / count;
+            -p * p.log2()
+        })
+        .sum::<f32>();
+
+    (edge_total / count, entropy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// A flat gray image with a checkerboard in the square at `(left, top)`.
+    fn detail_at(width: u32, height: u32, left: u32, top: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let inside = (left..left + 20).contains(&x) && (top..top + 20).contains(&y);
+            if inside && (x / 2 + y / 2) % 2 == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn detail_is_kept() {
+        let (x, y) = find_focal_point(&detail_at(200, 100, 160, 40), 100, 100);
+        assert!((0.8..=0.9).contains(&x), "{}", x);
+        assert_eq!(y, 0.5);
+
+        let (x, y) = find_focal_point(&detail_at(200, 100, 20, 40), 100, 100);
+        assert!((0.1..=0.2).contains(&x), "{}", x);
+        assert_eq!(y, 0.5);
+
+        // Tall images slide the window vertically.
+        let (x, y) = find_focal_point(&detail_at(100, 200, 40, 10), 100, 50);
+        assert_eq!(x, 0.5);
+        assert!(y < 0.2, "{}", y);
+    }
+
+    #[test]
+    fn flat_images_stay_centered() {
+        let flat = RgbaImage::from_pixel(300, 100, Rgba([10, 200, 30, 255]));
+        let (x, y) = find_focal_point(&flat, 100, 100);
+        assert!((x - 0.5).abs() < 0.01, "{}", x);
+        assert_eq!(y, 0.5);
+    }
+
+    #[test]
+    fn large_images_are_analyzed_downscaled() {
+        let (x, _) = find_focal_point(&detail_at(1000, 300, 900, 100), 300, 300);
+        assert!(x > 0.8, "{}", x);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::Pixel;
+use anyhow::{anyhow, Context, Result};
+use image::RgbaImage;
+use resvg::{tiny_skia, usvg};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// The system fonts, for SVGs that contain text. Loading them takes a while, so it's only done once.
+fn system_fonts() -> Arc<usvg::fontdb::Database> {
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    FONTS
+        .get_or_init(|| {
+            let mut fonts = usvg::fontdb::Database::new();
+            fonts.load_system_fonts();
+            Arc::new(fonts)
+        })
+        .clone()
+}
+
+/// A parsed SVG file, ready to be rasterized at any size.
+pub(crate) struct SvgImage {
+    tree: Box<usvg::Tree>,
+}
+
+impl SvgImage {
+    /// Load an SVG. If `current_color` is set, it replaces the color that the SVG's `currentColor`
+    /// values refer to, which is how single color icons are usually recolored.
+    pub fn open(path: &Path, current_color: Option<Pixel>) -> Result<SvgImage> {
+        let data = std::fs::read(path).with_context(|| format!("Opening SVG {:?}", path))?;
+
+        let options = usvg::Options {
+            resources_dir: path.parent().map(|p| p.to_path_buf()),
+            style_sheet: current_color.map(|c| {
+                format!(
+                    "svg {{ color: rgba({}, {}, {}, {}) }}",
+                    c[0],
+                    c[1],
+                    c[2],
+                    c[3] as f32 / 255.0
+                )
+            }),
+            fontdb: system_fonts(),
+            ..usvg::Options::default()
+        };
+
+        let tree = usvg::Tree::from_data(&data, &options)
+            .with_context(|| format!("Parsing SVG {:?}", path))?;
+        Ok(SvgImage {
+            tree: Box::new(tree),
+        })
+    }
+
+    /// The size of the SVG in pixels, as given by its `width` and `height` or `viewBox`.
+    pub fn size(&self) -> (u32, u32) {
+        let size = self.tree.size();
+        (
+            size.width().ceil().max(1.0) as u32,
+            size.height().ceil().max(1.0) as u32,
+        )
+    }
+
+    /// Rasterize the SVG, stretching it to exactly `width` by `height`.
+    pub fn render(&self, width: u32, height: u32) -> Result<RgbaImage> {
+        let mut pixmap = tiny_skia::Pixmap::new(width, height)
+            .ok_or_else(|| anyhow!("Can not render an SVG at size {}x{}", width, height))?;
+        let size = self.tree.size();
+        let transform = tiny_skia::Transform::from_scale(
+            width as f32 / size.width(),
+            height as f32 / size.height(),
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        // tiny-skia works with premultiplied alpha, but the rest of the crate does not.
+        let mut image = RgbaImage::new(width, height);
+        for (dest, src) in image.pixels_mut().zip(pixmap.pixels()) {
+            let c = src.demultiply();
+            *dest = crate::pixel(c.red(), c.green(), c.blue(), c.alpha());
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(name: &str, svg: &str, current_color: Option<Pixel>) -> SvgImage {
+        let path = std::env::temp_dir().join(format!(
+            "create-social-card-{}-{}.svg",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, svg).unwrap();
+        let image = SvgImage::open(&path, current_color);
+        std::fs::remove_file(&path).unwrap();
+        image.unwrap()
+    }
+
+    const ICON: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 5">
+        <rect x="0" y="0" width="5" height="5" fill="currentColor" />
+        <rect x="5" y="0" width="5" height="5" fill="#00ff00" />
+    </svg>"##;
+
+    #[test]
+    fn size_comes_from_the_view_box() {
+        assert_eq!(open("size", ICON, None).size(), (10, 5));
+    }
+
+    #[test]
+    fn current_color_is_replaced() {
+        let image = open("color", ICON, Some(crate::pixel(255, 0, 0, 255)))
+            .render(20, 10)
+            .unwrap();
+        assert_eq!(image.dimensions(), (20, 10));
+        assert_eq!(*image.get_pixel(4, 5), crate::pixel(255, 0, 0, 255));
+        assert_eq!(*image.get_pixel(15, 5), crate::pixel(0, 255, 0, 255));
+
+        // Without a color, currentColor is black.
+        let image = open("black", ICON, None).render(10, 5).unwrap();
+        assert_eq!(*image.get_pixel(2, 2), crate::pixel(0, 0, 0, 255));
+    }
+
+    #[test]
+    fn invalid_svgs_are_errors() {
+        let path = std::env::temp_dir().join(format!(
+            "create-social-card-invalid-{}.svg",
+            std::process::id()
+        ));
+        std::fs::write(&path, "<svg").unwrap();
+        let result = SvgImage::open(&path, None);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
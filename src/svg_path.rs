@@ -0,0 +1,353 @@
+use crate::geometry::{Path, Subpath};
+use anyhow::{anyhow, Result};
+
+/// Parse the path data of an SVG `<path>` element, flattening curves into line segments. Elliptical
+/// arcs are not supported.
+pub(crate) fn parse_path(d: &str) -> Result<Path> {
+    let mut parser = Parser {
+        chars: d.char_indices().peekable(),
+        source: d,
+    };
+    let mut builder = Builder::default();
+    let mut command = None;
+
+    while parser.skip_separators() {
+        let c = parser.peek().unwrap();
+        if c.is_ascii_alphabetic() {
+            parser.chars.next();
+            command = Some(c);
+            if c == 'Z' || c == 'z' {
+                builder.close();
+                continue;
+            }
+        }
+
+        let c = command.ok_or_else(|| anyhow!("Path data {:?} must start with a command", d))?;
+        // Every other command repeats when more numbers follow it, but closing a path takes no numbers.
+        if c == 'Z' || c == 'z' {
+            return Err(anyhow!(
+                "Unexpected number after {} at position {} in path data {:?}",
+                c,
+                parser.position(),
+                d
+            ));
+        }
+        let relative = c.is_ascii_lowercase();
+        let (cx, cy) = builder.current;
+        let offset = |(x, y): (f32, f32)| {
+            if relative {
+                (x + cx, y + cy)
+            } else {
+                (x, y)
+            }
+        };
+
+        match c.to_ascii_uppercase() {
+            'M' => {
+                let p = offset(parser.point()?);
+                builder.move_to(p);
+                // Extra coordinates after a move are treated as lines.
+                command = Some(if relative { 'l' } else { 'L' });
+            }
+            'L' => builder.line_to(offset(parser.point()?)),
+            'H' => {
+                let x = parser.number()?;
+                builder.line_to((if relative { x + cx } else { x }, cy));
+            }
+            'V' => {
+                let y = parser.number()?;
+                builder.line_to((cx, if relative { y + cy } else { y }));
+            }
+            'C' => {
+                let c1 = offset(parser.point()?);
+                let c2 = offset(parser.point()?);
+                let end = offset(parser.point()?);
+                builder.cubic_to(c1, c2, end);
+            }
+            'S' => {
+                let c1 = builder.reflected_control(&['C', 'S']);
+                let c2 = offset(parser.point()?);
+                let end = offset(parser.point()?);
+                builder.cubic_to(c1, c2, end);
+            }
+            'Q' => {
+                let c1 = offset(parser.point()?);
+                let end = offset(parser.point()?);
+                builder.quad_to(c1, end);
+            }
+            'T' => {
+                let c1 = builder.reflected_control(&['Q', 'T']);
+                let end = offset(parser.point()?);
+                builder.quad_to(c1, end);
+            }
+            'A' => return Err(anyhow!("Arcs are not supported in path data {:?}", d)),
+            _ => {
+                return Err(anyhow!(
+                    "Unknown command {:?} in path data {:?}",
+                    c,
+                    parser.source
+                ))
+            }
+        }
+        builder.last_command = c.to_ascii_uppercase();
+    }
+
+    Ok(builder.finish())
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    source: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    /// The byte offset of the next character.
+    fn position(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|(i, _)| *i)
+            .unwrap_or(self.source.len())
+    }
+
+    /// Skip whitespace and commas, and return whether there is anything left.
+    fn skip_separators(&mut self) -> bool {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.chars.next();
+            } else {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn number(&mut self) -> Result<f32> {
+        self.skip_separators();
+        let start = match self.chars.peek() {
+            Some((i, _)) => *i,
+            None => return Err(anyhow!("Path data {:?} ended early", self.source)),
+        };
+
+        let mut end = start;
+        let mut seen_dot = false;
+        let mut seen_exponent = false;
+        while let Some((i, c)) = self.chars.peek().copied() {
+            let valid = match c {
+                '0'..='9' => true,
+                '+' | '-' => {
+                    i == start || matches!(self.source[..i].chars().last(), Some('e' | 'E'))
+                }
+                '.' if !seen_dot && !seen_exponent => {
+                    seen_dot = true;
+                    true
+                }
+                'e' | 'E' if !seen_exponent && i > start => {
+                    seen_exponent = true;
+                    true
+                }
+                _ => false,
+            };
+
+            if !valid {
+                break;
+            }
+            end = i + c.len_utf8();
+            self.chars.next();
+        }
+
+        self.source[start..end].parse().map_err(|_| {
+            anyhow!(
+                "Expected a number at position {} in path data {:?}",
+                start,
+                self.source
+            )
+        })
+    }
+
+    fn point(&mut self) -> Result<(f32, f32)> {
+        Ok((self.number()?, self.number()?))
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    subpaths: Vec<Subpath>,
+    points: Vec<(f32, f32)>,
+    current: (f32, f32),
+    start: (f32, f32),
+    last_command: char,
+    /// The last control point of the previous curve, for the smooth curve commands.
+    last_control: (f32, f32),
+}
+
+impl Builder {
+    fn finish_subpath(&mut self, closed: bool) {
+        if !self.points.is_empty() {
+            let points = std::mem::take(&mut self.points);
+            self.subpaths.push(Subpath { points, closed });
+        }
+    }
+
+    fn move_to(&mut self, p: (f32, f32)) {
+        self.finish_subpath(false);
+        self.points.push(p);
+        self.current = p;
+        self.start = p;
+    }
+
+    fn line_to(&mut self, p: (f32, f32)) {
+        if self.points.is_empty() {
+            self.points.push(self.current);
+        }
+        self.points.push(p);
+        self.current = p;
+    }
+
+    fn close(&mut self) {
+        self.finish_subpath(true);
+        self.current = self.start;
+        self.last_command = 'Z';
+    }
+
+    /// The first control point of a smooth curve, which is the reflection of the previous curve's last
+    /// control point if that curve was one of `commands`.
+    fn reflected_control(&self, commands: &[char]) -> (f32, f32) {
+        let (x, y) = self.current;
+        if commands.contains(&self.last_command) {
+            (2.0 * x - self.last_control.0, 2.0 * y - self.last_control.1)
+        } else {
+            self.current
+        }
+    }
+
+    fn cubic_to(&mut self, c1: (f32, f32), c2: (f32, f32), end: (f32, f32)) {
+        let start = self.current;
+        let steps = curve_steps(&[start, c1, c2, end]);
+        for i in 1..=steps {
+            let t = i as f32 / steps as f32;
+            let u = 1.0 - t;
+            let point = |a: f32, b: f32, c: f32, d: f32| {
+                u * u * u * a + 3.0 * u * u * t * b + 3.0 * u * t * t * c + t * t * t * d
+            };
+            self.line_to((
+                point(start.0, c1.0, c2.0, end.0),
+                point(start.1, c1.1, c2.1, end.1),
+            ));
+        }
+        self.last_control = c2;
+    }
+
+    fn quad_to(&mut self, c1: (f32, f32), end: (f32, f32)) {
+        let start = self.current;
+        let steps = curve_steps(&[start, c1, end]);
+        for i in 1..=steps {
+            let t = i as f32 / steps as f32;
+            let u = 1.0 - t;
+            let point = |a: f32, b: f32, c: f32| u * u * a + 2.0 * u * t * b + t * t * c;
+            self.line_to((point(start.0, c1.0, end.0), point(start.1, c1.1, end.1)));
+        }
+        self.last_control = c1;
+    }
+
+    fn finish(mut self) -> Path {
+        self.finish_subpath(false);
+        Path {
+            subpaths: self.subpaths,
+        }
+    }
+}
+
+/// How many line segments to split a curve into, based on the length of its control polygon.
+fn curve_steps(points: &[(f32, f32)]) -> usize {
+    let length: f32 = points
+        .windows(2)
+        .map(|w| ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt())
+        .sum();
+    ((length / 4.0).ceil() as usize).clamp(4, 200)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(d: &str) -> Vec<(Vec<(f32, f32)>, bool)> {
+        parse_path(d)
+            .unwrap()
+            .subpaths
+            .into_iter()
+            .map(|s| (s.points, s.closed))
+            .collect()
+    }
+
+    #[test]
+    fn lines_and_close() {
+        assert_eq!(
+            points("M 0 0 L 10 0 L 10 10 Z M 20 20 L 30 20"),
+            vec![
+                (vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)], true),
+                (vec![(20.0, 20.0), (30.0, 20.0)], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_and_implicit_commands() {
+        // Extra pairs after a move are lines, and H and V only change one coordinate.
+        assert_eq!(
+            points("m10,10 5,0 v5 h-5 l0-5"),
+            vec![(
+                vec![
+                    (10.0, 10.0),
+                    (15.0, 10.0),
+                    (15.0, 15.0),
+                    (10.0, 15.0),
+                    (10.0, 10.0)
+                ],
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn compact_numbers() {
+        assert_eq!(
+            points("M.5.5L1e1-2"),
+            vec![(vec![(0.5, 0.5), (10.0, -2.0)], false)]
+        );
+    }
+
+    #[test]
+    fn curves_end_at_their_end_point() {
+        for (d, end) in [
+            ("M0 0 C 10 0 10 10 0 10", (0.0, 10.0)),
+            ("M0 0 Q 10 5 0 10", (0.0, 10.0)),
+            ("M0 0 C 0 5 5 5 5 0 S 10 -5 10 0", (10.0, 0.0)),
+            ("M0 0 Q 5 5 10 0 T 20 0", (20.0, 0.0)),
+        ] {
+            let last = *points(d)[0].0.last().unwrap();
+            assert!(
+                (last.0 - end.0).abs() < 1e-4 && (last.1 - end.1).abs() < 1e-4,
+                "{}: ended at {:?}",
+                d,
+                last
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let error = |d: &str| parse_path(d).unwrap_err().to_string();
+        assert!(error("10 10").contains("must start with a command"));
+        assert!(error("M 0 0 A 5 5 0 0 1 10 10").contains("Arcs are not supported"));
+        assert!(error("M 0 0 L 10").contains("ended early"));
+        assert!(error("M 0 0 L x 10").contains("Expected a number at position 8"));
+        assert_eq!(
+            error("M 0 0 L 10 0 Z 5 5"),
+            "Unexpected number after Z at position 15 in path data \"M 0 0 L 10 0 Z 5 5\""
+        );
+    }
+}
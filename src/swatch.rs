@@ -0,0 +1,329 @@
+use crate::{pixel, Pixel};
+use anyhow::{anyhow, Result};
+use image::{imageops::FilterType, RgbaImage};
+
+/// Representative colors picked from the background, which any color in the config can refer to as
+/// `"@vibrant"`, `"@muted"`, `"@dark"` or `"@light"`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Swatches {
+    /// A saturated color of medium brightness, for accents.
+    pub vibrant: image::Rgba<u8>,
+    /// A desaturated color of medium brightness, for block backgrounds.
+    pub muted: image::Rgba<u8>,
+    pub dark: image::Rgba<u8>,
+    pub light: image::Rgba<u8>,
+}
+
+impl Swatches {
+    fn get(&self, name: &str) -> Option<Pixel> {
+        match name.to_ascii_lowercase().as_str() {
+            "vibrant" => Some(self.vibrant),
+            "muted" => Some(self.muted),
+            "dark" => Some(self.dark),
+            "light" => Some(self.light),
+            _ => None,
+        }
+    }
+}
+
+/// The longest side of the downscaled image that the colors are taken from.
+const ANALYSIS_SIZE: u32 = 128;
+
+/// How many boxes median cut splits the colors into.
+const MAX_BOXES: usize = 16;
+
+/// A group of similar colors found by median cut.
+struct ColorBox {
+    colors: Vec<[u8; 3]>,
+}
+
+impl ColorBox {
+    /// The channel with the widest range, and the size of that range.
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3)
+            .map(|c| {
+                let (min, max) = self
+                    .colors
+                    .iter()
+                    .fold((255, 0), |(min, max), p| (p[c].min(min), p[c].max(max)));
+                (c, max.saturating_sub(min))
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap()
+    }
+
+    fn average(&self) -> Pixel {
+        let sum = self.colors.iter().fold([0u64; 3], |mut sum, p| {
+            for c in 0..3 {
+                sum[c] += p[c] as u64;
+            }
+            sum
+        });
+        let count = self.colors.len() as u64;
+        let channel = |c: usize| ((sum[c] + count / 2) / count) as u8;
+        pixel(channel(0), channel(1), channel(2), 255)
+    }
+}
+
+/// Split the colors into boxes of similar colors, always splitting the box with the widest channel range
+/// at its median.
+fn median_cut(colors: Vec<[u8; 3]>) -> Vec<ColorBox> {
+    let mut boxes = vec![ColorBox { colors }];
+    while boxes.len() < MAX_BOXES {
+        let (index, (channel, range)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .map(|(i, b)| (i, b.widest_channel()))
+            .max_by_key(|(_, (_, range))| *range)
+            .unwrap_or((0, (0, 0)));
+        if range == 0 {
+            break;
+        }
+
+        let mut split = boxes.swap_remove(index);
+        split.colors.sort_unstable_by_key(|p| p[channel]);
+        let upper = split.colors.split_off(split.colors.len() / 2);
+        boxes.push(split);
+        boxes.push(ColorBox { colors: upper });
+    }
+    boxes
+}
+
+/// The saturation and lightness of a color, from 0 to 1.
+fn saturation_lightness(color: Pixel) -> (f32, f32) {
+    let [r, g, b] = [
+        color[0] as f32 / 255.0,
+        color[1] as f32 / 255.0,
+        color[2] as f32 / 255.0,
+    ];
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let saturation = if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+    };
+    (saturation, lightness)
+}
+
+/// What each swatch looks for. A color is scored by how close its saturation and lightness are to the
+/// target and by how much of the image it covers, and colors outside the ranges are only used when
+/// nothing else is left.
+struct Target {
+    saturation: f32,
+    lightness: f32,
+    saturation_range: (f32, f32),
+    lightness_range: (f32, f32),
+    saturation_weight: f32,
+    population_weight: f32,
+}
+
+const LIGHTNESS_WEIGHT: f32 = 0.52;
+
+const TARGETS: [Target; 4] = [
+    // Vibrant colors are usually small accents, so how much of the image they cover matters less.
+    Target {
+        saturation: 1.0,
+        lightness: 0.5,
+        saturation_range: (0.35, 1.0),
+        lightness_range: (0.3, 0.7),
+        saturation_weight: 0.4,
+        population_weight: 0.1,
+    },
+    // Muted
+    Target {
+        saturation: 0.3,
+        lightness: 0.5,
+        saturation_range: (0.0, 0.4),
+        lightness_range: (0.3, 0.7),
+        saturation_weight: 0.24,
+        population_weight: 0.24,
+    },
+    // Dark
+    Target {
+        saturation: 0.5,
+        lightness: 0.18,
+        saturation_range: (0.0, 1.0),
+        lightness_range: (0.0, 0.45),
+        saturation_weight: 0.1,
+        population_weight: 0.24,
+    },
+    // Light
+    Target {
+        saturation: 0.3,
+        lightness: 0.85,
+        saturation_range: (0.0, 1.0),
+        lightness_range: (0.55, 1.0),
+        saturation_weight: 0.1,
+        population_weight: 0.24,
+    },
+];
+
+impl Target {
+    fn in_range(&self, saturation: f32, lightness: f32) -> bool {
+        let within = |v: f32, (min, max): (f32, f32)| v >= min && v <= max;
+        within(saturation, self.saturation_range) && within(lightness, self.lightness_range)
+    }
+
+    fn score(&self, saturation: f32, lightness: f32, population: f32) -> f32 {
+        self.saturation_weight * (1.0 - (saturation - self.saturation).abs())
+            + LIGHTNESS_WEIGHT * (1.0 - (lightness - self.lightness).abs())
+            + self.population_weight * population
+    }
+}
+
+/// Find the vibrant, muted, dark and light colors of `image`. Transparent pixels are ignored. Each swatch
+/// is taken from a different group of colors when the image has enough of them.
+pub fn extract_swatches(image: &RgbaImage) -> Swatches {
+    let (width, height) = image.dimensions();
+    let scale = (ANALYSIS_SIZE as f32 / width.max(height).max(1) as f32).min(1.0);
+    let small = image::imageops::resize(
+        image,
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+        FilterType::Triangle,
+    );
+    let colors = small
+        .pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect::<Vec<_>>();
+    if colors.is_empty() {
+        return Swatches {
+            vibrant: pixel(128, 128, 128, 255),
+            muted: pixel(128, 128, 128, 255),
+            dark: pixel(0, 0, 0, 255),
+            light: pixel(255, 255, 255, 255),
+        };
+    }
+
+    let total = colors.len() as f32;
+    let candidates = median_cut(colors)
+        .iter()
+        .map(|b| (b.average(), b.colors.len() as f32 / total))
+        .collect::<Vec<_>>();
+    let max_population = candidates.iter().map(|c| c.1).fold(0.0, f32::max);
+
+    let mut used = vec![false; candidates.len()];
+    let mut pick = |target: &Target| {
+        // Prefer colors that no other swatch has taken and that are in the target's ranges.
+        let rank = |i: usize| {
+            let (color, population) = candidates[i];
+            let (saturation, lightness) = saturation_lightness(color);
+            (
+                !used[i],
+                target.in_range(saturation, lightness),
+                target.score(saturation, lightness, population / max_population),
+            )
+        };
+        let index = (0..candidates.len())
+            .max_by(|&a, &b| {
+                let (a, b) = (rank(a), rank(b));
+                (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.total_cmp(&b.2))
+            })
+            .unwrap();
+        used[index] = true;
+        candidates[index].0
+    };
+
+    Swatches {
+        vibrant: pick(&TARGETS[0]),
+        muted: pick(&TARGETS[1]),
+        dark: pick(&TARGETS[2]),
+        light: pick(&TARGETS[3]),
+    }
+}
+
+/// Look up a swatch reference such as `"@vibrant"`, without the `@`.
+pub(crate) fn lookup(name: &str, swatches: Option<&Swatches>) -> Result<Pixel> {
+    let swatches = swatches.ok_or_else(|| {
+        anyhow!(
+            "Color \"@{}\" can't be used here, because swatches are only available after the background is drawn",
+            name
+        )
+    })?;
+
+    swatches.get(name).ok_or_else(|| {
+        anyhow!(
+            "Unknown swatch \"@{}\", expected vibrant, muted, dark or light",
+            name
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image split into vertical stripes of the given colors and widths.
+    fn stripes(colors: &[(Pixel, u32)]) -> RgbaImage {
+        let width = colors.iter().map(|c| c.1).sum();
+        RgbaImage::from_fn(width, 10, |x, _| {
+            let mut left = 0;
+            for (color, w) in colors {
+                left += w;
+                if x < left {
+                    return *color;
+                }
+            }
+            unreachable!()
+        })
+    }
+
+    #[test]
+    fn each_swatch_finds_its_kind_of_color() {
+        let red = pixel(220, 30, 40, 255);
+        let gray_blue = pixel(110, 120, 140, 255);
+        let navy = pixel(10, 20, 50, 255);
+        let cream = pixel(250, 240, 220, 255);
+        let swatches = extract_swatches(&stripes(&[
+            (red, 10),
+            (gray_blue, 40),
+            (navy, 30),
+            (cream, 20),
+        ]));
+        assert_eq!(swatches.vibrant, red);
+        assert_eq!(swatches.muted, gray_blue);
+        assert_eq!(swatches.light, cream);
+        // The dark stripe is mixed a little with its neighbor where the image is smoothed.
+        let (_, lightness) = saturation_lightness(swatches.dark);
+        assert!(lightness < 0.3 && swatches.dark[2] > swatches.dark[0]);
+    }
+
+    #[test]
+    fn swatches_use_different_colors_when_they_can() {
+        let red = pixel(220, 30, 40, 255);
+        let black = pixel(0, 0, 0, 255);
+        let swatches = extract_swatches(&stripes(&[(red, 50), (black, 50)]));
+        assert_eq!(swatches.vibrant, red);
+        assert_eq!(swatches.dark, black);
+
+        // With a single color, every swatch has to use it.
+        let swatches = extract_swatches(&stripes(&[(red, 10)]));
+        assert_eq!([swatches.muted, swatches.light], [red, red]);
+    }
+
+    #[test]
+    fn transparent_pixels_are_ignored() {
+        let swatches = extract_swatches(&RgbaImage::new(10, 10));
+        assert_eq!(swatches.dark, pixel(0, 0, 0, 255));
+        assert_eq!(swatches.light, pixel(255, 255, 255, 255));
+
+        let red = pixel(220, 30, 40, 255);
+        let swatches = extract_swatches(&stripes(&[(red, 10), (pixel(0, 255, 0, 0), 90)]));
+        assert_eq!(swatches.vibrant, red);
+    }
+
+    #[test]
+    fn lookups() {
+        let swatches = extract_swatches(&stripes(&[(pixel(220, 30, 40, 255), 10)]));
+        assert_eq!(
+            lookup("Vibrant", Some(&swatches)).unwrap(),
+            swatches.vibrant
+        );
+        assert!(lookup("shiny", Some(&swatches)).is_err());
+        assert!(lookup("vibrant", None).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+use crate::each_color::EachColor;
+use crate::variables::stand_in_variables;
+use crate::{
+    check_references, expand_variables, overlay_text_with_info, Background, BackgroundFit, Block,
+    Color, Filter, FontDef, GeneratedBackground, Grain, ImageBlock, ImageCache, OverlayOptions,
+    Palette, RenderInfo, ShapeBlock,
+};
+use anyhow::{anyhow, Context, Result};
+use glyph_brush_layout::ab_glyph::FontRef;
+use image::RgbaImage;
+use serde::de::{self, value::MapAccessDeserializer, MapAccess, Visitor};
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+struct FontConfig {
+    name: String,
+    path: PathBuf,
+}
+
+/// Either the path of a background image, or a description of the background to draw. This is
+/// deserialized by hand rather than as an untagged enum, so that mistakes in a generated background report
+/// the field that is wrong.
+fn background<'de, 'a, D>(deserializer: D) -> std::result::Result<Background<'a>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct BackgroundVisitor<'a>(std::marker::PhantomData<Background<'a>>);
+
+    impl<'de, 'a> Visitor<'de> for BackgroundVisitor<'a> {
+        type Value = Background<'a>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("the path of an image or a table describing the background")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Background<'a>, E> {
+            Ok(Background::File(PathBuf::from(v)))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(
+            self,
+            map: A,
+        ) -> std::result::Result<Background<'a>, A::Error> {
+            GeneratedBackground::deserialize(MapAccessDeserializer::new(map))
+                .map(Background::Generated)
+        }
+    }
+
+    deserializer.deserialize_any(BackgroundVisitor(std::marker::PhantomData))
+}
+
+/// The contents of a card's config file.
+#[derive(Deserialize)]
+struct Config<'a> {
+    #[serde(deserialize_with = "background")]
+    background: Background<'a>,
+    /// The size of the card. The background is scaled to this size according to `background_fit`. If
+    /// the size is not set, the card is the size of the background.
+    width: Option<u32>,
+    height: Option<u32>,
+    #[serde(default)]
+    background_fit: BackgroundFit<'a>,
+    #[serde(default)]
+    background_filters: Vec<Filter<'a>>,
+    fonts: Vec<FontConfig>,
+    /// Blocks, images and shapes can also have a `name`, which themes use to refer to them.
+    blocks: Vec<Block<'a>>,
+    #[serde(default)]
+    images: Vec<ImageBlock<'a>>,
+    #[serde(default)]
+    shapes: Vec<ShapeBlock<'a>>,
+    grain: Option<Grain>,
+    /// Named colors that any color in the config can refer to as `"$name"`.
+    #[serde(default)]
+    palette: Palette<'a>,
+}
+
+impl<'a> EachColor for Config<'a> {
+    fn each_color(&self, f: &mut dyn FnMut(&Color)) {
+        self.palette.each_color(f);
+        self.background.each_color(f);
+        self.background_fit.each_color(f);
+        self.background_filters.each_color(f);
+        self.shapes.each_color(f);
+        self.images.each_color(f);
+        self.blocks.each_color(f);
+    }
+}
+
+/// The settings of a config that have `{{name}}` placeholders, parsed again for each card once they are
+/// filled in. Settings that are left out are the same for every card.
+#[derive(Default, Deserialize)]
+struct Overrides<'a> {
+    #[serde(default, deserialize_with = "optional_background")]
+    background: Option<Background<'a>>,
+    width: Option<u32>,
+    height: Option<u32>,
+    background_fit: Option<BackgroundFit<'a>>,
+    background_filters: Option<Vec<Filter<'a>>>,
+    blocks: Option<Vec<Block<'a>>>,
+    images: Option<Vec<ImageBlock<'a>>>,
+    shapes: Option<Vec<ShapeBlock<'a>>>,
+    grain: Option<Grain>,
+    palette: Option<Palette<'a>>,
+}
+
+fn optional_background<'de, 'a, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Background<'a>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    background(deserializer).map(Some)
+}
+
+/// The size of the card, if it's set.
+fn card_size(width: Option<u32>, height: Option<u32>) -> Result<Option<(u32, u32)>> {
+    match (width, height) {
+        (Some(width), Some(height)) => Ok(Some((width, height))),
+        (None, None) => Ok(None),
+        _ => Err(anyhow!(
+            "The card needs both a width and a height, or neither to use the size of the background"
+        )),
+    }
+}
+
+struct LoadedFont {
+    name: String,
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+/// A card config that is loaded once and then used to render any number of cards. Strings in the config
+/// can contain `{{name}}` placeholders, which are filled in from the variables passed to `render`.
+///
+/// The config is checked when the template is created, with `"000"` standing in for each placeholder. So
+/// placeholders can be used in text, paths and colors, but not in settings that only take certain words,
+/// such as `fit`. Only the top-level settings that have placeholders, such as `blocks`, are parsed again
+/// for each card.
+///
+/// Fonts are read when the template is created. Images are decoded the first time a card uses them, and
+/// kept in an `ImageCache` for the cards after that. A template can be shared between threads to render
+/// cards in parallel.
+pub struct Template {
+    /// The config, with stand-ins for the placeholders.
+    config: Config<'static>,
+    /// The top-level settings that have placeholders.
+    variable_settings: toml::value::Table,
+    fonts: Vec<LoadedFont>,
+    images: ImageCache,
+    measure_contrast: bool,
+}
+
+impl Template {
+    /// Create a template from the contents of a TOML config file.
+    pub fn from_toml(contents: &str) -> Result<Template> {
+        let value: toml::Value = toml::from_str(contents).context("Parsing config file")?;
+        if has_placeholders(&value) {
+            return Template::from_value(value);
+        }
+
+        // Parse the text directly rather than through a `toml::Value`, so that errors have line numbers.
+        let config = toml::from_str(contents).context("Parsing config file")?;
+        Template::new(config, toml::value::Table::new())
+    }
+
+    /// Create a template from a config that has already been parsed as TOML.
+    pub fn from_value(value: toml::Value) -> Result<Template> {
+        if !has_placeholders(&value) {
+            let config = value.clone().try_into().context("Parsing config file")?;
+            return Template::new(config, toml::value::Table::new());
+        }
+
+        if value.get("fonts").is_some_and(has_placeholders) {
+            return Err(anyhow!(
+                "Fonts can not use {{{{name}}}} placeholders, because they are loaded when the template is created"
+            ));
+        }
+
+        // Check the whole config now, rather than when the first card is rendered.
+        let mut checked = value.clone();
+        fill_placeholders(&mut checked, "", &stand_in_variables)?;
+        let config = checked.try_into().context(
+            "Parsing config file, with \"000\" standing in for each {{name}} placeholder",
+        )?;
+
+        let variable_settings = match value {
+            toml::Value::Table(table) => table
+                .into_iter()
+                .filter(|(_, setting)| has_placeholders(setting))
+                .collect(),
+            _ => toml::value::Table::new(),
+        };
+        Template::new(config, variable_settings)
+    }
+
+    fn new(config: Config<'static>, variable_settings: toml::value::Table) -> Result<Template> {
+        card_size(config.width, config.height)?;
+        // Colors with placeholders are checked again once they're filled in, when each card is drawn.
+        check_references(&config, Some(&config.palette))?;
+
+        let fonts = config
+            .fonts
+            .iter()
+            .map(|f| {
+                let data = std::fs::read(&f.path)
+                    .with_context(|| format!("Opening font file {:?}", f.path))?;
+                FontRef::try_from_slice(&data)
+                    .with_context(|| format!("Loading font {:?}", f.path))?;
+                Ok(LoadedFont {
+                    name: f.name.clone(),
+                    path: f.path.clone(),
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Template {
+            config,
+            variable_settings,
+            fonts,
+            images: ImageCache::default(),
+            measure_contrast: false,
+        })
+    }
+
+    /// Measure the contrast of each text run for `RenderInfo::text_contrast`. This is off by default,
+    /// because it costs an extra pass over each card.
+    pub fn set_measure_contrast(&mut self, measure_contrast: bool) {
+        self.measure_contrast = measure_contrast;
+    }
+
+    /// Render a card, filling in the config's placeholders from `variables`.
+    pub fn render(&self, variables: &HashMap<String, String>) -> Result<RgbaImage> {
+        self.render_with_info(variables).map(|(image, _)| image)
+    }
+
+    /// Render a card like `render`, and also return details about how it was rendered.
+    pub fn render_with_info(
+        &self,
+        variables: &HashMap<String, String>,
+    ) -> Result<(RgbaImage, RenderInfo)> {
+        let config = &self.config;
+        let overrides: Overrides = if self.variable_settings.is_empty() {
+            Overrides::default()
+        } else {
+            let mut settings = toml::Value::Table(self.variable_settings.clone());
+            fill_placeholders(&mut settings, "", &|s| expand_variables(s, variables))?;
+            settings.try_into().context("Parsing config file")?
+        };
+
+        // Looking up a font's tables is cheap, and the font data has already been read and checked.
+        let fonts = self
+            .fonts
+            .iter()
+            .map(|f| {
+                let font = FontRef::try_from_slice(&f.data)
+                    .with_context(|| format!("Loading font {:?}", f.path))?;
+                Ok(FontDef {
+                    name: Cow::from(&f.name),
+                    font,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let options = OverlayOptions {
+            // A template background is a path or a description, so this is cheap to copy.
+            background: overrides
+                .background
+                .unwrap_or_else(|| config.background.clone()),
+            size: card_size(
+                overrides.width.or(config.width),
+                overrides.height.or(config.height),
+            )?,
+            background_fit: overrides
+                .background_fit
+                .unwrap_or_else(|| config.background_fit.clone()),
+            background_filters: overrides
+                .background_filters
+                .as_deref()
+                .unwrap_or(&config.background_filters),
+            fonts: &fonts,
+            blocks: overrides.blocks.as_deref().unwrap_or(&config.blocks),
+            images: overrides.images.as_deref().unwrap_or(&config.images),
+            shapes: overrides.shapes.as_deref().unwrap_or(&config.shapes),
+            grain: overrides.grain.as_ref().or(config.grain.as_ref()),
+            measure_contrast: self.measure_contrast,
+            palette: Some(overrides.palette.as_ref().unwrap_or(&config.palette)),
+            image_cache: Some(&self.images),
+        };
+
+        overlay_text_with_info(&options)
+    }
+}
+
+fn has_placeholders(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::String(s) => s.contains("{{"),
+        toml::Value::Array(items) => items.iter().any(has_placeholders),
+        toml::Value::Table(table) => table.values().any(has_placeholders),
+        _ => false,
+    }
+}
+
+fn key_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Replace the `{{name}}` placeholders in every string in `value` with `fill`.
+fn fill_placeholders(
+    value: &mut toml::Value,
+    path: &str,
+    fill: &dyn Fn(&str) -> Result<String>,
+) -> Result<()> {
+    match value {
+        toml::Value::String(s) if s.contains("{{") => {
+            *s = fill(s).with_context(|| format!("Expanding {}", path))?;
+        }
+        toml::Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                fill_placeholders(item, &format!("{}[{}]", path, i), fill)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                fill_placeholders(item, &key_path(path, key), fill)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_for_each_card() {
+        let template = Template::from_toml(
+            r#"
+            background = { width = 4, height = 2, fill = "{{bg}}" }
+            fonts = []
+            blocks = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(template.variable_settings.len(), 1);
+
+        let red = template.render(&variables(&[("bg", "red")])).unwrap();
+        assert_eq!(*red.get_pixel(0, 0), pixel(255, 0, 0, 255));
+        let blue = template.render(&variables(&[("bg", "#00f")])).unwrap();
+        assert_eq!(*blue.get_pixel(3, 1), pixel(0, 0, 255, 255));
+
+        assert!(template.render(&variables(&[("bg", "nope")])).is_err());
+        assert!(template.render(&variables(&[])).is_err());
+    }
+
+    #[test]
+    fn mistakes_are_reported_when_loading() {
+        let error = |config: &str| Template::from_toml(config).err().unwrap();
+
+        // The mistake is outside of the settings with placeholders.
+        let mistake = error(
+            r#"
+            background = { width = 4, height = 2, fill = "{{bg}}" }
+            width = 4
+            fonts = []
+            blocks = []
+            "#,
+        );
+        assert!(format!("{:#}", mistake).contains("needs both a width and a height"));
+
+        let mistake = error(
+            r#"
+            background = { width = 4, height = 2, fill = "{{bg | darken}}" }
+            fonts = []
+            blocks = []
+            "#,
+        );
+        assert!(format!("{:#}", mistake).contains("Unknown filter \"darken\""));
+
+        let mistake = error(
+            r#"
+            background = { width = 4, height = 2, fill = "$missing" }
+            fonts = []
+            blocks = []
+            shapes = [{ type = "rect", rect = { left = 0, top = 0, right = 1, bottom = 1 }, fill = "{{fg}}" }]
+            "#,
+        );
+        assert!(format!("{:#}", mistake).contains("Unknown palette color \"$missing\""));
+    }
+
+    fn background(config: &str) -> std::result::Result<Background<'static>, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper<'a> {
+            #[serde(deserialize_with = "super::background")]
+            background: Background<'a>,
+        }
+
+        toml::from_str::<Wrapper>(config).map(|w| w.background)
+    }
+
+    #[test]
+    fn backgrounds_are_paths_or_tables() {
+        assert!(matches!(
+            background(r#"background = "bg.png""#),
+            Ok(Background::File(p)) if p == std::path::Path::new("bg.png")
+        ));
+        assert!(matches!(
+            background("background = { width = 10, height = 10 }"),
+            Ok(Background::Generated(_))
+        ));
+
+        // Mistakes in a generated background report what is wrong with the field.
+        let err = background(r#"background = { width = "wide" }"#)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("expected u32"), "{}", err);
+        assert!(background("background = 5").is_err());
+    }
+
+    #[test]
+    fn card_size_needs_a_width_and_a_height() {
+        assert_eq!(card_size(Some(10), Some(20)).unwrap(), Some((10, 20)));
+        assert_eq!(card_size(None, None).unwrap(), None);
+        assert!(card_size(Some(10), None).is_err());
+        assert!(card_size(None, Some(10)).is_err());
+    }
+
+    #[test]
+    fn only_colors_are_checked_for_palette_references() {
+        // Paths, seeds and text aren't colors, so they can start with `$`.
+        let template = Template::from_toml(
+            r#"
+            background = "$images/bg.png"
+            fonts = []
+            blocks = [{ text = [{ text = "$5 off", font = "body" }], rect = { left = 0, top = 0, right = 1, bottom = 1 }, min_size = 1, max_size = 2 }]
+            grain = { seed = "$title" }
+            "#,
+        );
+        assert!(template.is_ok(), "{:#}", template.err().unwrap());
+
+        let template = Template::from_toml(
+            r#"
+            palette = { base = "black" }
+            background = { width = 4, height = 2, generator = { type = "grid", seed = "$title", palette = ["$base", "{{fg}}"] } }
+            fonts = []
+            blocks = []
+            "#,
+        );
+        assert!(template.is_ok(), "{:#}", template.err().unwrap());
+
+        let mistake = Template::from_toml(
+            r#"
+            palette = { base = "$base.darken(10%)" }
+            background = { width = 4, height = 2 }
+            fonts = []
+            blocks = []
+            "#,
+        )
+        .err()
+        .unwrap();
+        assert!(format!("{:#}", mistake).contains("refers to itself"));
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+/// Replace `{{name}}` placeholders in `template` with the values of variables. A placeholder can pass
+/// the value through filters, such as `{{title | upper | truncate(60)}}`:
+///
+/// - `upper`, `lower` and `trim`
+/// - `truncate(n)` shortens the value to at most `n` characters, ending with an ellipsis if it was cut
+/// - `date("%b %d, %Y")` formats a date written as `2024-03-05` or in RFC 3339 format, using
+///   `strftime` syntax
+/// - `default("text")` is used when the variable isn't set, instead of failing
+pub fn expand_variables(template: &str, variables: &HashMap<String, String>) -> Result<String> {
+    replace_placeholders(template, |placeholder| {
+        expand_placeholder(placeholder, variables)
+    })
+}
+
+/// What placeholders are replaced with to check a config before its variables are known. It can be read
+/// as text, as a file name, and as a color.
+const STAND_IN: &str = "000";
+
+/// Check that every placeholder in `template` is written correctly, and replace them with a stand-in value.
+pub(crate) fn stand_in_variables(template: &str) -> Result<String> {
+    replace_placeholders(template, |placeholder| {
+        parse_placeholder(placeholder)?;
+        Ok(STAND_IN.to_string())
+    })
+}
+
+fn replace_placeholders(
+    template: &str,
+    mut replace: impl FnMut(&str) -> Result<String>,
+) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("Unclosed \"{{{{\" in {:?}", template))?;
+        let placeholder = &after[..end];
+        output.push_str(
+            &replace(placeholder).map_err(|e| anyhow!("{} in \"{{{{{}}}}}\"", e, placeholder))?,
+        );
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// Split a placeholder into the variable name and its filters.
+fn parse_placeholder(placeholder: &str) -> Result<(&str, Vec<Filter>)> {
+    let mut parts = split_filters(placeholder).into_iter();
+    let name = parts.next().unwrap_or_default().trim();
+    if name.is_empty() {
+        return Err(anyhow!("Missing variable name"));
+    }
+
+    let filters = parts.map(Filter::parse).collect::<Result<Vec<_>>>()?;
+    Ok((name, filters))
+}
+
+fn expand_placeholder(placeholder: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let (name, filters) = parse_placeholder(placeholder)?;
+    let mut value = match variables.get(name) {
+        Some(value) => value.clone(),
+        None => filters
+            .iter()
+            .find_map(|f| match f {
+                Filter::Default(default) => Some(default.clone()),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("Undefined variable {:?}", name))?,
+    };
+
+    for filter in &filters {
+        value = filter.apply(value)?;
+    }
+    Ok(value)
+}
+
+/// Split a placeholder at the `|`s that aren't inside quotes.
+fn split_filters(placeholder: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    for (i, c) in placeholder.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('|', None) => {
+                parts.push(&placeholder[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&placeholder[start..]);
+    parts
+}
+
+enum Filter {
+    Upper,
+    Lower,
+    Trim,
+    Truncate(usize),
+    Date(String),
+    Default(String),
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Result<Filter> {
+        let filter = filter.trim();
+        let (name, argument) = match filter.find('(') {
+            Some(open) => {
+                let argument = filter[open + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| anyhow!("Missing ')' after filter {:?}", filter))?
+                    .trim();
+                (filter[..open].trim(), Some(argument))
+            }
+            None => (filter, None),
+        };
+
+        let string_argument = || {
+            let argument =
+                argument.ok_or_else(|| anyhow!("The {} filter needs an argument", name))?;
+            let unquoted = argument
+                .strip_prefix('"')
+                .and_then(|a| a.strip_suffix('"'))
+                .or_else(|| {
+                    argument
+                        .strip_prefix('\'')
+                        .and_then(|a| a.strip_suffix('\''))
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The argument to {} must be a quoted string, not {}",
+                        name,
+                        argument
+                    )
+                })?;
+            Ok::<_, anyhow::Error>(unquoted.to_string())
+        };
+
+        match (name, argument) {
+            ("upper", None) => Ok(Filter::Upper),
+            ("lower", None) => Ok(Filter::Lower),
+            ("trim", None) => Ok(Filter::Trim),
+            ("truncate", Some(length)) => length.parse().map(Filter::Truncate).map_err(|_| {
+                anyhow!(
+                    "The length for truncate must be a whole number, not {}",
+                    length
+                )
+            }),
+            ("date", _) => Ok(Filter::Date(string_argument()?)),
+            ("default", _) => Ok(Filter::Default(string_argument()?)),
+            ("upper", Some(_)) | ("lower", Some(_)) | ("trim", Some(_)) => {
+                Err(anyhow!("The {} filter doesn't take an argument", name))
+            }
+            ("truncate", None) => Err(anyhow!("The truncate filter needs a length")),
+            _ => Err(anyhow!(
+                "Unknown filter {:?}, expected upper, lower, trim, truncate, date or default",
+                name
+            )),
+        }
+    }
+
+    fn apply(&self, value: String) -> Result<String> {
+        Ok(match self {
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Trim => value.trim().to_string(),
+            Filter::Truncate(length) => truncate(&value, *length),
+            Filter::Date(format) => format_date(&value, format)?,
+            Filter::Default(_) => value,
+        })
+    }
+}
+
+/// Shorten `value` to at most `length` characters. If it has to be cut, it's cut at the end of a word
+/// when there's one in the second half, and an ellipsis is added.
+fn truncate(value: &str, length: usize) -> String {
+    if value.chars().count() <= length {
+        return value.to_string();
+    }
+    if length == 0 {
+        return String::new();
+    }
+
+    let cut = value
+        .char_indices()
+        .nth(length - 1)
+        .map(|(i, _)| i)
+        .unwrap_or(value.len());
+    let kept = &value[..cut];
+    let kept = match kept.rfind(char::is_whitespace) {
+        Some(space) if kept[..space].chars().count() >= length / 2 => &kept[..space],
+        _ => kept,
+    };
+    format!("{}…", kept.trim_end())
+}
+
+fn format_date(value: &str, format: &str) -> Result<String> {
+    let value = value.trim();
+    let date = DateTime::parse_from_rfc3339(value)
+        .map(|d| d.naive_local())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_time(Default::default()))
+        })
+        .map_err(|_| {
+            anyhow!(
+                "Can't read {:?} as a date, expected a date like 2024-03-05",
+                value
+            )
+        })?;
+
+    // chrono panics when formatting with an invalid format, so check it first.
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(anyhow!("Invalid date format {:?}", format));
+    }
+
+    // Formatting can still fail, such as for `%Z` since the date has no time zone. `to_string` would panic.
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format_with_items(items.into_iter())).map_err(|_| {
+        anyhow!(
+            "Can't format {:?} as {:?}. Dates don't have a time zone, so %Z and %z can't be used",
+            value,
+            format
+        )
+    })?;
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str, variables: &[(&str, &str)]) -> Result<String> {
+        let variables = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        expand_variables(template, &variables)
+    }
+
+    #[test]
+    fn placeholders() {
+        let variables = [("title", "  Hello, World  "), ("date", "2024-03-05")];
+        assert_eq!(
+            expand("{{title}}!", &variables).unwrap(),
+            "  Hello, World  !"
+        );
+        assert_eq!(
+            expand("{{ title | trim | upper }}", &variables).unwrap(),
+            "HELLO, WORLD"
+        );
+        assert_eq!(
+            expand(
+                "{{title|lower|trim}} on {{date | date(\"%b %-d, %Y\")}}",
+                &variables
+            )
+            .unwrap(),
+            "hello, world on Mar 5, 2024"
+        );
+        assert_eq!(
+            expand("{{author | default('Anonymous | Guest')}}", &variables).unwrap(),
+            "Anonymous | Guest"
+        );
+        assert_eq!(expand("no placeholders", &[]).unwrap(), "no placeholders");
+    }
+
+    #[test]
+    fn stand_ins() {
+        assert_eq!(
+            stand_in_variables("#{{hex}} and {{ title | upper }}").unwrap(),
+            "#000 and 000"
+        );
+        assert!(stand_in_variables("{{title | shout}}")
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown filter"));
+        assert!(stand_in_variables("{{title").is_err());
+    }
+
+    #[test]
+    fn dates() {
+        let date = |value: &str, format: &str| format_date(value, format);
+        assert_eq!(date("2024-03-05T14:30:00Z", "%H:%M").unwrap(), "14:30");
+        assert_eq!(
+            date("2024-03-05T14:30:00+02:00", "%Y-%m-%d %H:%M").unwrap(),
+            "2024-03-05 14:30"
+        );
+        assert_eq!(date("2024-03-05 08:00:00", "%d/%m").unwrap(), "05/03");
+        assert!(date("March 5th", "%Y").is_err());
+        assert!(date("2024-03-05", "%Q").is_err());
+        // The date has no time zone to show, which made chrono's formatter fail.
+        assert!(date("2024-03-05T14:30:00Z", "%Z")
+            .unwrap_err()
+            .to_string()
+            .contains("%Z and %z can't be used"));
+        assert!(date("2024-03-05", "%H:%M %z").is_err());
+        assert!(date("2024-03-05", "%:z").is_err());
+    }
+
+    #[test]
+    fn truncating() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly", 7), "exactly");
+        // Cut at the end of a word when there's one in the second half.
+        assert_eq!(truncate("The quick brown fox jumps", 14), "The quick…");
+        // Otherwise cut mid-word.
+        assert_eq!(truncate("Supercalifragilistic", 8), "Superca…");
+        assert_eq!(truncate("a bcdefghijklmnop", 10), "a bcdefgh…");
+        // Lengths count characters, not bytes.
+        assert_eq!(truncate("héllo wörld ünïcode", 12), "héllo wörld…");
+        assert_eq!(truncate("日本語のテキスト", 4), "日本語…");
+        assert_eq!(truncate("anything", 0), "");
+    }
+
+    #[test]
+    fn errors() {
+        let error = |template: &str| expand(template, &[("x", "1")]).unwrap_err().to_string();
+        assert_eq!(
+            error("{{missing}}"),
+            "Undefined variable \"missing\" in \"{{missing}}\""
+        );
+        assert_eq!(error("{{x"), "Unclosed \"{{\" in \"{{x\"");
+        assert_eq!(error("{{ }}"), "Missing variable name in \"{{ }}\"");
+        assert!(error("{{x | shout}}").contains("Unknown filter \"shout\""));
+        assert!(error("{{x | truncate}}").contains("needs a length"));
+        assert!(error("{{x | truncate(ten)}}").contains("must be a whole number"));
+        assert!(error("{{x | upper(1)}}").contains("doesn't take an argument"));
+        assert!(error("{{x | default(none)}}").contains("must be a quoted string"));
+        assert!(error("{{x | date(\"%Y\")}}").contains("Can't read \"1\" as a date"));
+    }
+}